@@ -0,0 +1,1460 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{instruction::Instruction, machine::PROGRAM_START};
+
+/*
+ * An assembler for the Octo language (https://johnearnest.github.io/Octo/docs/Manual.html).
+ *
+ * Supported:
+ *  * labels (": name"), forward references and calling a label by naming it
+ *  * :macro, :const, :alias, :calc, :org, :byte, :call, :unpack, :next, :breakpoint
+ *  * :segment code / :segment data, and :include of .8o sources and raw binary files
+ *  * every chip8 statement, e.g. "v0 := 5", "i := label", "sprite v0 v1 15", "bcd v3"
+ *  * if ... then, if ... begin ... else ... end, loop ... while ... again
+ *
+ * Tokens are separated by whitespace and "#" starts a comment that runs to the end of the line.
+ */
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+impl Error for AssembleError {}
+
+/* The result of assembling a program */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    // the rom, to be loaded at 0x200
+    pub bytes: Vec<u8>,
+    // every label and the address it points at
+    pub labels: HashMap<String, u16>,
+    // addresses marked with :breakpoint
+    pub breakpoints: Vec<(String, u16)>,
+    // the address of every instruction and the line of the source file it came from, by address.
+    // Instructions from included files and data are left out.
+    pub source_lines: Vec<(u16, usize)>,
+}
+impl Program {
+    /**
+     * The address of the first instruction on a line, or on the first line after it that has
+     * one, along with that line. Debuggers use this to put breakpoints on source lines.
+     */
+    pub fn line_address(&self, line: usize) -> Option<(usize, u16)> {
+        self.source_lines
+            .iter()
+            .filter(|(_, source_line)| *source_line >= line)
+            .min_by_key(|(address, source_line)| (*source_line, *address))
+            .map(|(address, source_line)| (*source_line, *address))
+    }
+
+    /** The source line of the instruction at an address. */
+    pub fn address_line(&self, address: u16) -> Option<usize> {
+        self.source_lines
+            .iter()
+            .find(|(line_address, _)| *line_address == address)
+            .map(|(_, line)| *line)
+    }
+
+    /** The label at or closest before an address, which is usually the routine it is in. */
+    pub fn label_before(&self, address: u16) -> Option<&str> {
+        self.labels
+            .iter()
+            .filter(|(_, label_address)| **label_address <= address)
+            .max_by_key(|(name, label_address)| (**label_address, std::cmp::Reverse(*name)))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+    // read from an :include, so the line is in another file
+    included: bool,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, AssembleError> {
+    let mut tokens = Vec::new();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let mut chars = line.char_indices().peekable();
+
+        while let Some(&(start, c)) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c == '#' {
+                break;
+            } else if c == '"' {
+                // string literals are only used by :include
+                chars.next();
+                let mut text = String::from("\"");
+                let mut closed = false;
+                for (_, c) in chars.by_ref() {
+                    text.push(c);
+                    if c == '"' {
+                        closed = true;
+                        break;
+                    }
+                }
+                if !closed {
+                    return Err(AssembleError {
+                        line: line_index + 1,
+                        column: start + 1,
+                        message: "unterminated string".to_string(),
+                    });
+                }
+                tokens.push(Token {
+                    text,
+                    line: line_index + 1,
+                    column: start + 1,
+                    included: false,
+                });
+            } else {
+                let mut text = String::new();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    text.push(c);
+                    chars.next();
+                }
+                tokens.push(Token {
+                    text,
+                    line: line_index + 1,
+                    column: start + 1,
+                    included: false,
+                });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/** Parses an Octo number literal: decimal, 0x hex or 0b binary, optionally negative. */
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+// where a label lives, data segment addresses are only known once all the code is assembled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Location {
+    Code(usize),
+    Data(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Segment {
+    Code,
+    Data,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FixupKind {
+    // the low 12 bits of the instruction at the location
+    Address,
+    // the high nibble of the address, or'ed into the byte at the location
+    HighNibble,
+    // the low byte of the address
+    LowByte,
+    // the 16 bit address after F000 in i := long
+    LongAddress,
+}
+
+#[derive(Debug, Clone)]
+enum Target {
+    Name(Token),
+    Location(Location),
+}
+
+#[derive(Debug, Clone)]
+struct Fixup {
+    at: Location,
+    kind: FixupKind,
+    target: Target,
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    arguments: Vec<String>,
+    body: Vec<Token>,
+}
+
+#[derive(Debug, Clone)]
+enum Block {
+    // the jump over the "begin" block, and the jump over the "else" block once it is seen
+    If {
+        skip: Location,
+        has_else: bool,
+    },
+    // the start of the loop and the "while" exits to patch at "again"
+    Loop {
+        start: Location,
+        exits: Vec<Location>,
+    },
+}
+
+// an operand of a statement: a register, or a value known now or later
+#[derive(Debug, Clone)]
+enum Operand {
+    Register(u8),
+    Number(i64),
+    Name(Token),
+}
+
+// a condition in "if" and "while", written as "vx == 5", "vx != vy", "vx key", "vx < 3" ...
+#[derive(Debug, Clone)]
+struct Condition {
+    left: u8,
+    op: String,
+    right: Option<Operand>,
+    token: Token,
+}
+
+const MEMORY_SIZE: usize = 0x10000;
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    image: Vec<u8>,
+    code_end: usize,
+    here: usize,
+    data: Vec<u8>,
+    segment: Segment,
+    labels: HashMap<String, Location>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<(Block, Token)>,
+    breakpoints: Vec<(String, Location)>,
+    source_lines: Vec<(u16, usize)>,
+    base_dir: Option<PathBuf>,
+    last: Token,
+}
+
+fn error_at<T>(token: &Token, message: impl Into<String>) -> Result<T, AssembleError> {
+    Err(AssembleError {
+        line: token.line,
+        column: token.column,
+        message: message.into(),
+    })
+}
+
+impl Assembler {
+    fn new(tokens: Vec<Token>, base_dir: Option<PathBuf>) -> Assembler {
+        Assembler {
+            tokens: tokens.into(),
+            image: vec![0; MEMORY_SIZE],
+            code_end: PROGRAM_START as usize,
+            here: PROGRAM_START as usize,
+            data: Vec::new(),
+            segment: Segment::Code,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            breakpoints: Vec::new(),
+            source_lines: Vec::new(),
+            base_dir,
+            last: Token {
+                text: String::new(),
+                line: 1,
+                column: 1,
+                included: false,
+            },
+        }
+    }
+
+    fn next(&mut self) -> Result<Token, AssembleError> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.last = token.clone();
+                Ok(token)
+            }
+            None => error_at(&self.last, "unexpected end of file"),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, AssembleError> {
+        let token = self.next()?;
+        if token.text != text {
+            return error_at(
+                &token,
+                format!("expected \"{text}\", found \"{}\"", token.text),
+            );
+        }
+        Ok(token)
+    }
+
+    fn location(&self) -> Location {
+        match self.segment {
+            Segment::Code => Location::Code(self.here),
+            Segment::Data => Location::Data(self.data.len()),
+        }
+    }
+
+    fn emit(&mut self, byte: u8, token: &Token) -> Result<(), AssembleError> {
+        match self.segment {
+            Segment::Code => {
+                if self.here >= MEMORY_SIZE {
+                    return error_at(token, "program does not fit in memory");
+                }
+                self.image[self.here] = byte;
+                self.here += 1;
+                self.code_end = self.code_end.max(self.here);
+            }
+            Segment::Data => self.data.push(byte),
+        }
+        Ok(())
+    }
+
+    fn emit_instruction(
+        &mut self,
+        instruction: Instruction,
+        token: &Token,
+    ) -> Result<(), AssembleError> {
+        if self.segment == Segment::Code && !token.included {
+            self.source_lines.push((self.here as u16, token.line));
+        }
+        for byte in instruction.encode_bytes() {
+            self.emit(byte, token)?;
+        }
+        Ok(())
+    }
+
+    // emits an instruction whose 12 bit address is filled in once every label is known
+    fn emit_with_address(
+        &mut self,
+        instruction: Instruction,
+        target: Target,
+        token: &Token,
+    ) -> Result<(), AssembleError> {
+        self.fixups.push(Fixup {
+            at: self.location(),
+            kind: FixupKind::Address,
+            target,
+        });
+        self.emit_instruction(instruction, token)
+    }
+
+    fn register_name(&self, text: &str) -> Option<u8> {
+        if let Some(register) = self.aliases.get(text) {
+            return Some(*register);
+        }
+        let lower = text.to_ascii_lowercase();
+        let index = lower.strip_prefix('v')?;
+        if index.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(index, 16).ok()
+    }
+
+    fn register(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        match self.register_name(&token.text) {
+            Some(register) => Ok(register),
+            None => error_at(
+                &token,
+                format!("expected a register, found \"{}\"", token.text),
+            ),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, AssembleError> {
+        let token = self.next()?;
+        if let Some(register) = self.register_name(&token.text) {
+            return Ok(Operand::Register(register));
+        }
+        if let Some(number) = parse_number(&token.text) {
+            return Ok(Operand::Number(number));
+        }
+        if let Some(value) = self.constants.get(&token.text) {
+            return Ok(Operand::Number(*value as i64));
+        }
+        Ok(Operand::Name(token))
+    }
+
+    // a value that must be known right away, such as a byte or a :org address
+    fn number(&mut self) -> Result<i64, AssembleError> {
+        if self.peek() == Some("{") {
+            let value = self.calc_block()?;
+            return Ok(value as i64);
+        }
+        match self.operand()? {
+            Operand::Number(value) => Ok(value),
+            Operand::Name(token) => match self.labels.get(&token.text) {
+                Some(Location::Code(address)) => Ok(*address as i64),
+                _ => error_at(
+                    &token,
+                    format!("\"{}\" is not a known constant", token.text),
+                ),
+            },
+            Operand::Register(_) => error_at(&self.last, "expected a number, found a register"),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, AssembleError> {
+        let value = self.number()?;
+        if !(-128..=255).contains(&value) {
+            return error_at(&self.last, format!("{value} does not fit in a byte"));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8, AssembleError> {
+        let value = self.number()?;
+        if !(0..=15).contains(&value) {
+            return error_at(&self.last, format!("{value} does not fit in a nibble"));
+        }
+        Ok(value as u8)
+    }
+
+    // an address operand, which may be a label defined later on
+    fn target(&mut self) -> Result<Target, AssembleError> {
+        let token = self.next()?;
+        if let Some(number) = parse_number(&token.text) {
+            return Ok(Target::Location(Location::Code(number as usize)));
+        }
+        if let Some(value) = self.constants.get(&token.text) {
+            return Ok(Target::Location(Location::Code(*value as usize)));
+        }
+        Ok(Target::Name(token))
+    }
+
+    fn define_label(&mut self, token: &Token, location: Location) -> Result<(), AssembleError> {
+        if self.labels.contains_key(&token.text) || self.constants.contains_key(&token.text) {
+            return error_at(token, format!("\"{}\" is already defined", token.text));
+        }
+        self.labels.insert(token.text.clone(), location);
+        Ok(())
+    }
+
+    /* :calc expressions are evaluated right to left, use parentheses to group */
+    fn calc_block(&mut self) -> Result<f64, AssembleError> {
+        self.expect("{")?;
+        let value = self.calc_expression()?;
+        self.expect("}")?;
+        Ok(value)
+    }
+
+    fn calc_expression(&mut self) -> Result<f64, AssembleError> {
+        let left = self.calc_term()?;
+
+        let op = match self.peek() {
+            Some("}") | Some(")") | None => return Ok(left),
+            Some(op) => op.to_string(),
+        };
+        let token = self.next()?;
+        let right = self.calc_expression()?;
+
+        let (l, r) = (left as i64, right as i64);
+        let value = match op.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (l & r) as f64,
+            "|" => (l | r) as f64,
+            "^" => (l ^ r) as f64,
+            "<<" | ">>" => {
+                let shifted = u32::try_from(r).ok().and_then(|r| {
+                    if op == "<<" {
+                        l.checked_shl(r)
+                    } else {
+                        l.checked_shr(r)
+                    }
+                });
+                match shifted {
+                    Some(value) => value as f64,
+                    None => return error_at(&token, format!("cannot shift by {r}")),
+                }
+            }
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            "!=" => (left != right) as i64 as f64,
+            _ => return error_at(&token, format!("unknown operator \"{op}\"")),
+        };
+        Ok(value)
+    }
+
+    fn calc_term(&mut self) -> Result<f64, AssembleError> {
+        let token = self.next()?;
+
+        let unary = |f: fn(f64) -> f64, assembler: &mut Assembler| -> Result<f64, AssembleError> {
+            Ok(f(assembler.calc_term()?))
+        };
+
+        match token.text.as_str() {
+            "(" => {
+                let value = self.calc_expression()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            "-" => unary(|v| -v, self),
+            "~" => unary(|v| !(v as i64) as f64, self),
+            "!" => unary(|v| (v == 0.0) as i64 as f64, self),
+            "abs" => unary(f64::abs, self),
+            "sqrt" => unary(f64::sqrt, self),
+            "floor" => unary(f64::floor, self),
+            "ceil" => unary(f64::ceil, self),
+            "sin" => unary(f64::sin, self),
+            "cos" => unary(f64::cos, self),
+            "sign" => unary(f64::signum, self),
+            "@" => {
+                let address = self.calc_term()? as usize;
+                match self.image.get(address) {
+                    Some(byte) => Ok(*byte as f64),
+                    None => error_at(&token, format!("{address} is outside of memory")),
+                }
+            }
+            "HERE" => match self.location() {
+                Location::Code(address) => Ok(address as f64),
+                Location::Data(_) => error_at(&token, "HERE is not known in the data segment"),
+            },
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            text => {
+                if let Some(number) = parse_number(text) {
+                    return Ok(number as f64);
+                }
+                if let Some(value) = self.constants.get(text) {
+                    return Ok(*value);
+                }
+                match self.labels.get(text) {
+                    Some(Location::Code(address)) => Ok(*address as f64),
+                    Some(Location::Data(_)) => error_at(
+                        &token,
+                        format!("the address of \"{text}\" is not known until the end"),
+                    ),
+                    None => error_at(&token, format!("\"{text}\" is not defined")),
+                }
+            }
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, AssembleError> {
+        let left = self.register()?;
+        let token = self.next()?;
+        let op = token.text.clone();
+        let right = match op.as_str() {
+            "key" | "-key" => None,
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => Some(self.operand()?),
+            _ => return error_at(&token, format!("unknown comparison \"{op}\"")),
+        };
+        Ok(Condition {
+            left,
+            op,
+            right,
+            token,
+        })
+    }
+
+    /*
+     * emit_skip
+     * emits instructions that skip the next instruction when the condition is equal to `when`.
+     * "if c then x" skips x when c is false, "if c begin" skips the jump past the block when c is true.
+     */
+    fn emit_skip(&mut self, condition: Condition, when: bool) -> Result<(), AssembleError> {
+        let Condition {
+            left: x,
+            op,
+            right,
+            token,
+        } = condition;
+
+        let (op, when) = match op.as_str() {
+            "!=" => ("==", !when),
+            "-key" => ("key", !when),
+            ">" => ("<=", !when),
+            ">=" => ("<", !when),
+            op => (op, when),
+        };
+
+        let instruction = match (op, right) {
+            ("key", _) if when => Instruction::SkipKey { x },
+            ("key", _) => Instruction::SkipNotKey { x },
+            ("==", Some(Operand::Register(y))) if when => Instruction::SkipEqReg { x, y },
+            ("==", Some(Operand::Register(y))) => Instruction::SkipNeReg { x, y },
+            ("==", Some(Operand::Number(nn))) if when => Instruction::SkipEqByte {
+                x,
+                nn: self.checked_byte(nn)?,
+            },
+            ("==", Some(Operand::Number(nn))) => Instruction::SkipNeByte {
+                x,
+                nn: self.checked_byte(nn)?,
+            },
+            // vx < y and vx <= y are worked out in vF with a subtraction, vF is 0 when it borrows
+            ("<", Some(right)) | ("<=", Some(right)) => {
+                let f = 0xF;
+                match (op, right) {
+                    // vF = vx - vy, no borrow means vx >= vy
+                    ("<", Operand::Register(y)) => {
+                        self.emit_instruction(Instruction::LoadReg { x: f, y: x }, &token)?;
+                        self.emit_instruction(Instruction::Sub { x: f, y }, &token)?;
+                    }
+                    // vF = vx - n
+                    ("<", Operand::Number(n)) => {
+                        let nn = self.checked_byte(n)?;
+                        self.emit_instruction(Instruction::LoadByte { x: f, nn }, &token)?;
+                        self.emit_instruction(Instruction::SubN { x: f, y: x }, &token)?;
+                    }
+                    // vF = vy - vx, no borrow means vx <= vy
+                    (_, Operand::Register(y)) => {
+                        self.emit_instruction(Instruction::LoadReg { x: f, y }, &token)?;
+                        self.emit_instruction(Instruction::Sub { x: f, y: x }, &token)?;
+                    }
+                    // vF = n - vx
+                    (_, Operand::Number(n)) => {
+                        let nn = self.checked_byte(n)?;
+                        self.emit_instruction(Instruction::LoadByte { x: f, nn }, &token)?;
+                        self.emit_instruction(Instruction::Sub { x: f, y: x }, &token)?;
+                    }
+                    (_, Operand::Name(name)) => {
+                        return error_at(
+                            &name,
+                            format!("\"{}\" is not a known constant", name.text),
+                        )
+                    }
+                }
+                // "<" is true when vF is 0, "<=" when vF is 1
+                let true_when_zero = op == "<";
+                if when == true_when_zero {
+                    Instruction::SkipEqByte { x: f, nn: 0 }
+                } else {
+                    Instruction::SkipNeByte { x: f, nn: 0 }
+                }
+            }
+            (_, Some(Operand::Name(name))) => {
+                return error_at(&name, format!("\"{}\" is not a known constant", name.text))
+            }
+            _ => return error_at(&token, "incomplete condition"),
+        };
+
+        self.emit_instruction(instruction, &token)
+    }
+
+    fn assignment(&mut self, x: u8, token: &Token) -> Result<(), AssembleError> {
+        let op = self.next()?;
+        let instruction = match op.text.as_str() {
+            ":=" => match self.peek() {
+                Some("key") => {
+                    self.next()?;
+                    Instruction::WaitKey { x }
+                }
+                Some("delay") => {
+                    self.next()?;
+                    Instruction::LoadDelay { x }
+                }
+                Some("random") => {
+                    self.next()?;
+                    Instruction::Random {
+                        x,
+                        nn: self.byte()?,
+                    }
+                }
+                _ => match self.operand()? {
+                    Operand::Register(y) => Instruction::LoadReg { x, y },
+                    Operand::Number(nn) => Instruction::LoadByte {
+                        x,
+                        nn: self.checked_byte(nn)?,
+                    },
+                    Operand::Name(name) => {
+                        self.tokens.push_front(name);
+                        Instruction::LoadByte {
+                            x,
+                            nn: self.byte()?,
+                        }
+                    }
+                },
+            },
+            "+=" => match self.operand()? {
+                Operand::Register(y) => Instruction::AddReg { x, y },
+                Operand::Number(nn) => Instruction::AddByte {
+                    x,
+                    nn: self.checked_byte(nn)?,
+                },
+                Operand::Name(name) => {
+                    self.tokens.push_front(name);
+                    Instruction::AddByte {
+                        x,
+                        nn: self.byte()?,
+                    }
+                }
+            },
+            "-=" => match self.operand()? {
+                Operand::Register(y) => Instruction::Sub { x, y },
+                Operand::Number(nn) => Instruction::AddByte {
+                    x,
+                    nn: self.checked_byte(nn)?.wrapping_neg(),
+                },
+                Operand::Name(name) => {
+                    self.tokens.push_front(name);
+                    Instruction::AddByte {
+                        x,
+                        nn: self.byte()?.wrapping_neg(),
+                    }
+                }
+            },
+            "|=" => Instruction::Or {
+                x,
+                y: self.register()?,
+            },
+            "&=" => Instruction::And {
+                x,
+                y: self.register()?,
+            },
+            "^=" => Instruction::Xor {
+                x,
+                y: self.register()?,
+            },
+            "=-" => Instruction::SubN {
+                x,
+                y: self.register()?,
+            },
+            ">>=" => Instruction::ShiftRight {
+                x,
+                y: self.register()?,
+            },
+            "<<=" => Instruction::ShiftLeft {
+                x,
+                y: self.register()?,
+            },
+            _ => return error_at(&op, format!("unknown operator \"{}\"", op.text)),
+        };
+        self.emit_instruction(instruction, token)
+    }
+
+    fn checked_byte(&self, value: i64) -> Result<u8, AssembleError> {
+        if !(-128..=255).contains(&value) {
+            return error_at(&self.last, format!("{value} does not fit in a byte"));
+        }
+        Ok(value as u8)
+    }
+
+    fn include(&mut self, token: &Token) -> Result<(), AssembleError> {
+        let file = self.next()?;
+        let name = file.text.trim_matches('"').to_string();
+        let path = match &self.base_dir {
+            Some(base_dir) => base_dir.join(&name),
+            None => PathBuf::from(&name),
+        };
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+
+        match extension.as_deref() {
+            Some("png") | Some("gif") | Some("bmp") | Some("jpg") => error_at(
+                &file,
+                format!("cannot include \"{name}\", images are not supported"),
+            ),
+            Some("8o") => {
+                let source = fs::read_to_string(&path)
+                    .or_else(|err| error_at(&file, format!("cannot read \"{name}\": {err}")))?;
+                let tokens = tokenize(&source)?;
+                for mut included in tokens.into_iter().rev() {
+                    included.included = true;
+                    self.tokens.push_front(included);
+                }
+                Ok(())
+            }
+            _ => {
+                let bytes = fs::read(&path)
+                    .or_else(|err| error_at(&file, format!("cannot read \"{name}\": {err}")))?;
+                for byte in bytes {
+                    self.emit(byte, token)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn directive(&mut self, token: Token) -> Result<(), AssembleError> {
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next()?;
+                let location = self.location();
+                self.define_label(&name, location)?;
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let register = self.register()?;
+                self.aliases.insert(name.text, register);
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.number()?;
+                if self.labels.contains_key(&name.text) {
+                    return error_at(&name, format!("\"{}\" is already defined", name.text));
+                }
+                self.constants.insert(name.text, value as f64);
+            }
+            ":calc" => {
+                let name = self.next()?;
+                let value = self.calc_block()?;
+                self.constants.insert(name.text, value);
+            }
+            ":macro" => {
+                let name = self.next()?;
+                let mut arguments = Vec::new();
+                while self.peek() != Some("{") {
+                    arguments.push(self.next()?.text);
+                }
+                self.expect("{")?;
+
+                let mut body = Vec::new();
+                let mut depth = 1;
+                loop {
+                    let token = self.next()?;
+                    match token.text.as_str() {
+                        "{" => depth += 1,
+                        "}" => depth -= 1,
+                        _ => {}
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                    body.push(token);
+                }
+                self.macros.insert(name.text, Macro { arguments, body });
+            }
+            ":org" => {
+                let address = self.number()?;
+                if !(0..MEMORY_SIZE as i64).contains(&address) {
+                    return error_at(&self.last, format!("{address} is outside of memory"));
+                }
+                self.segment = Segment::Code;
+                self.here = address as usize;
+            }
+            ":byte" => {
+                let byte = self.byte()?;
+                self.emit(byte, &token)?;
+            }
+            ":call" => {
+                let target = self.target()?;
+                self.emit_with_address(Instruction::Call { nnn: 0 }, target, &token)?;
+            }
+            ":unpack" => {
+                // v0 := nibble << 4 | address >> 8, v1 := address & 0xFF
+                let nibble = self.nibble()?;
+                let target = self.target()?;
+                let location = self.location();
+                self.emit_instruction(
+                    Instruction::LoadByte {
+                        x: 0,
+                        nn: nibble << 4,
+                    },
+                    &token,
+                )?;
+                self.emit_instruction(Instruction::LoadByte { x: 1, nn: 0 }, &token)?;
+
+                let at = |offset| match location {
+                    Location::Code(address) => Location::Code(address + offset),
+                    Location::Data(offset_in_data) => Location::Data(offset_in_data + offset),
+                };
+                self.fixups.push(Fixup {
+                    at: at(1),
+                    kind: FixupKind::HighNibble,
+                    target: target.clone(),
+                });
+                self.fixups.push(Fixup {
+                    at: at(3),
+                    kind: FixupKind::LowByte,
+                    target,
+                });
+            }
+            ":next" => {
+                // the label points at the second byte of the next instruction
+                let name = self.next()?;
+                let location = match self.location() {
+                    Location::Code(address) => Location::Code(address + 1),
+                    Location::Data(offset) => Location::Data(offset + 1),
+                };
+                self.define_label(&name, location)?;
+            }
+            ":breakpoint" => {
+                let name = self.next()?;
+                let location = self.location();
+                self.breakpoints.push((name.text, location));
+            }
+            ":monitor" => {
+                // monitors only mean something to the Octo ide
+                self.next()?;
+                self.next()?;
+            }
+            ":segment" => {
+                let segment = self.next()?;
+                self.segment = match segment.text.as_str() {
+                    "code" => Segment::Code,
+                    "data" => Segment::Data,
+                    _ => return error_at(&segment, "expected \"code\" or \"data\""),
+                };
+            }
+            ":include" => self.include(&token)?,
+            _ => return error_at(&token, format!("unknown directive \"{}\"", token.text)),
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), AssembleError> {
+        let token = self.next()?;
+
+        if token.text.starts_with(':') {
+            return self.directive(token);
+        }
+
+        if let Some(x) = self.register_name(&token.text) {
+            return self.assignment(x, &token);
+        }
+
+        if let Some(number) = parse_number(&token.text) {
+            let byte = self.checked_byte(number)?;
+            return self.emit(byte, &token);
+        }
+
+        if let Some(definition) = self.macros.get(&token.text).cloned() {
+            let mut arguments = HashMap::new();
+            for argument in definition.arguments {
+                arguments.insert(argument, self.next()?.text);
+            }
+            for body_token in definition.body.into_iter().rev() {
+                let text = arguments
+                    .get(&body_token.text)
+                    .cloned()
+                    .unwrap_or(body_token.text);
+                // errors inside a macro are reported where it is used
+                self.tokens.push_front(Token {
+                    text,
+                    line: token.line,
+                    column: token.column,
+                    included: token.included,
+                });
+            }
+            return Ok(());
+        }
+
+        match token.text.as_str() {
+            "clear" => self.emit_instruction(Instruction::Cls, &token),
+            "return" | ";" => self.emit_instruction(Instruction::Ret, &token),
+            "hires" => self.emit_instruction(Instruction::HighRes, &token),
+            "lores" => self.emit_instruction(Instruction::LowRes, &token),
+            "exit" => self.emit_instruction(Instruction::Exit, &token),
+            "scroll-left" => self.emit_instruction(Instruction::ScrollLeft, &token),
+            "scroll-right" => self.emit_instruction(Instruction::ScrollRight, &token),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit_instruction(Instruction::ScrollDown { n }, &token)
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit_instruction(Instruction::StoreFlags { x }, &token)
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit_instruction(Instruction::LoadFlags { x }, &token)
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit_instruction(Instruction::Bcd { x }, &token)
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                let instruction = if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    if token.text == "save" {
+                        Instruction::StoreRange { x, y }
+                    } else {
+                        Instruction::LoadRange { x, y }
+                    }
+                } else if token.text == "save" {
+                    Instruction::Store { x }
+                } else {
+                    Instruction::Load { x }
+                };
+                self.emit_instruction(instruction, &token)
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit_instruction(Instruction::ScrollUp { n }, &token)
+            }
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit_instruction(Instruction::Plane { n }, &token)
+            }
+            "audio" => self.emit_instruction(Instruction::Audio, &token),
+            "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.emit_instruction(Instruction::Pitch { x }, &token)
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit_instruction(Instruction::Draw { x, y, n }, &token)
+            }
+            "jump" => {
+                let target = self.target()?;
+                self.emit_with_address(Instruction::Jump { nnn: 0 }, target, &token)
+            }
+            "jump0" => {
+                let target = self.target()?;
+                self.emit_with_address(Instruction::JumpV0 { nnn: 0 }, target, &token)
+            }
+            "native" => {
+                let target = self.target()?;
+                self.emit_with_address(Instruction::Sys { nnn: 0 }, target, &token)
+            }
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let instruction = if token.text == "delay" {
+                    Instruction::SetDelay { x }
+                } else {
+                    Instruction::SetSound { x }
+                };
+                self.emit_instruction(instruction, &token)
+            }
+            "i" => {
+                let op = self.next()?;
+                match op.text.as_str() {
+                    "+=" => {
+                        let x = self.register()?;
+                        self.emit_instruction(Instruction::AddI { x }, &token)
+                    }
+                    ":=" if self.peek() == Some("hex") => {
+                        self.next()?;
+                        let x = self.register()?;
+                        self.emit_instruction(Instruction::LoadFont { x }, &token)
+                    }
+                    ":=" if self.peek() == Some("long") => {
+                        self.next()?;
+                        let target = self.target()?;
+                        let at = match self.location() {
+                            Location::Code(address) => Location::Code(address + 2),
+                            Location::Data(offset) => Location::Data(offset + 2),
+                        };
+                        self.fixups.push(Fixup {
+                            at,
+                            kind: FixupKind::LongAddress,
+                            target,
+                        });
+                        self.emit_instruction(Instruction::LoadLongI { nnnn: 0 }, &token)
+                    }
+                    ":=" if self.peek() == Some("bighex") => {
+                        self.next()?;
+                        let x = self.register()?;
+                        self.emit_instruction(Instruction::LoadBigFont { x }, &token)
+                    }
+                    ":=" => {
+                        let target = self.target()?;
+                        self.emit_with_address(Instruction::LoadI { nnn: 0 }, target, &token)
+                    }
+                    _ => error_at(&op, format!("unknown operator \"{}\"", op.text)),
+                }
+            }
+            "if" => {
+                let condition = self.condition()?;
+                let keyword = self.next()?;
+                match keyword.text.as_str() {
+                    "then" => self.emit_skip(condition, false),
+                    "begin" => {
+                        self.emit_skip(condition, true)?;
+                        let skip = self.location();
+                        self.emit_instruction(Instruction::Jump { nnn: 0 }, &token)?;
+                        self.blocks.push((
+                            Block::If {
+                                skip,
+                                has_else: false,
+                            },
+                            token,
+                        ));
+                        Ok(())
+                    }
+                    _ => error_at(&keyword, "expected \"then\" or \"begin\""),
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some((
+                    Block::If {
+                        skip,
+                        has_else: false,
+                    },
+                    if_token,
+                )) => {
+                    let jump_to_end = self.location();
+                    self.emit_instruction(Instruction::Jump { nnn: 0 }, &token)?;
+                    self.fixups.push(Fixup {
+                        at: skip,
+                        kind: FixupKind::Address,
+                        target: Target::Location(self.location()),
+                    });
+                    self.blocks.push((
+                        Block::If {
+                            skip: jump_to_end,
+                            has_else: true,
+                        },
+                        if_token,
+                    ));
+                    Ok(())
+                }
+                _ => error_at(&token, "\"else\" without \"if ... begin\""),
+            },
+            "end" => match self.blocks.pop() {
+                Some((Block::If { skip, .. }, _)) => {
+                    self.fixups.push(Fixup {
+                        at: skip,
+                        kind: FixupKind::Address,
+                        target: Target::Location(self.location()),
+                    });
+                    Ok(())
+                }
+                _ => error_at(&token, "\"end\" without \"if ... begin\""),
+            },
+            "loop" => {
+                let start = self.location();
+                self.blocks.push((
+                    Block::Loop {
+                        start,
+                        exits: Vec::new(),
+                    },
+                    token,
+                ));
+                Ok(())
+            }
+            "while" => {
+                let condition = self.condition()?;
+                // leave the loop when the condition is false
+                self.emit_skip(condition, true)?;
+                let exit = self.location();
+                self.emit_instruction(Instruction::Jump { nnn: 0 }, &token)?;
+                match self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|(block, _)| matches!(block, Block::Loop { .. }))
+                {
+                    Some((Block::Loop { exits, .. }, _)) => {
+                        exits.push(exit);
+                        Ok(())
+                    }
+                    _ => error_at(&token, "\"while\" outside of a loop"),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some((Block::Loop { start, exits }, _)) => {
+                    self.emit_with_address(
+                        Instruction::Jump { nnn: 0 },
+                        Target::Location(start),
+                        &token,
+                    )?;
+                    for exit in exits {
+                        self.fixups.push(Fixup {
+                            at: exit,
+                            kind: FixupKind::Address,
+                            target: Target::Location(self.location()),
+                        });
+                    }
+                    Ok(())
+                }
+                _ => error_at(&token, "\"again\" without \"loop\""),
+            },
+            "then" | "begin" | "key" | "-key" | "hex" | "bighex" | "long" | "random" => {
+                error_at(&token, format!("unexpected \"{}\"", token.text))
+            }
+            // any other name is a call to a subroutine, which may be defined further down
+            _ => self.emit_with_address(
+                Instruction::Call { nnn: 0 },
+                Target::Name(token.clone()),
+                &token,
+            ),
+        }
+    }
+
+    fn finish(mut self) -> Result<Program, AssembleError> {
+        if let Some((_, token)) = self.blocks.last() {
+            return error_at(token, format!("\"{}\" is never closed", token.text));
+        }
+
+        let data_start = self.code_end;
+        if data_start + self.data.len() > MEMORY_SIZE {
+            return error_at(&self.last, "program does not fit in memory");
+        }
+        let data = std::mem::take(&mut self.data);
+        self.image[data_start..data_start + data.len()].copy_from_slice(&data);
+        let end = data_start + data.len();
+
+        let absolute = |location: Location| match location {
+            Location::Code(address) => address,
+            Location::Data(offset) => data_start + offset,
+        };
+
+        for fixup in &self.fixups {
+            let (address, token) = match &fixup.target {
+                Target::Location(location) => (absolute(*location), None),
+                Target::Name(token) => match self.labels.get(&token.text) {
+                    Some(location) => (absolute(*location), Some(token)),
+                    None => return error_at(token, format!("\"{}\" is not defined", token.text)),
+                },
+            };
+            let at = absolute(fixup.at);
+
+            match fixup.kind {
+                FixupKind::Address => {
+                    if address > 0xFFF {
+                        let token = token.unwrap_or(&self.last);
+                        return error_at(
+                            token,
+                            format!("address 0x{address:X} does not fit in 12 bits"),
+                        );
+                    }
+                    self.image[at] |= (address >> 8) as u8;
+                    self.image[at + 1] = address as u8;
+                }
+                FixupKind::HighNibble => self.image[at] |= (address >> 8) as u8 & 0xF,
+                FixupKind::LowByte => self.image[at] = address as u8,
+                FixupKind::LongAddress => {
+                    self.image[at] = (address >> 8) as u8;
+                    self.image[at + 1] = address as u8;
+                }
+            }
+        }
+
+        // :org can go back over code already written, the last instruction written at an address wins
+        let mut source_lines = Vec::<(u16, usize)>::new();
+        for (address, line) in self.source_lines.iter().rev() {
+            if !source_lines.iter().any(|(seen, _)| seen == address) {
+                source_lines.push((*address, *line));
+            }
+        }
+        source_lines.sort();
+
+        let start = PROGRAM_START as usize;
+        Ok(Program {
+            bytes: self.image[start..end.max(start)].to_vec(),
+            labels: self
+                .labels
+                .iter()
+                .map(|(name, location)| (name.clone(), absolute(*location) as u16))
+                .collect(),
+            breakpoints: self
+                .breakpoints
+                .iter()
+                .map(|(name, location)| (name.clone(), absolute(*location) as u16))
+                .collect(),
+            source_lines,
+        })
+    }
+}
+
+fn assemble_tokens(
+    tokens: Vec<Token>,
+    base_dir: Option<PathBuf>,
+) -> Result<Program, AssembleError> {
+    let mut assembler = Assembler::new(tokens, base_dir);
+    while !assembler.tokens.is_empty() {
+        assembler.statement()?;
+    }
+    assembler.finish()
+}
+
+/** Assembles Octo source code. Includes are looked up relative to the working directory. */
+pub fn assemble(source: &str) -> Result<Program, AssembleError> {
+    assemble_tokens(tokenize(source)?, None)
+}
+
+/** Assembles an .8o file. Includes are looked up relative to the file. */
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Program, AssembleError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|err| AssembleError {
+        line: 0,
+        column: 0,
+        message: format!("cannot read {}: {err}", path.display()),
+    })?;
+    assemble_tokens(tokenize(&source)?, path.parent().map(Path::to_path_buf))
+}
+
+#[cfg(test)]
+mod assembler_tests {
+    use super::*;
+
+    #[test]
+    fn builds_the_chip8_logo() {
+        let source = fs::read_to_string("roms/1-chip8-logo.8o").unwrap();
+        let expected = fs::read("roms/1-chip8-logo.ch8").unwrap();
+
+        // the splash image is not in this repository, and images can not be included anyway
+        let error = assemble_file("roms/1-chip8-logo.8o").unwrap_err();
+        assert!(
+            error.message.contains("images are not supported"),
+            "{error}"
+        );
+
+        // so its sprites are included as bytes taken from the expected rom, and only the code
+        // before them at 0x200 to 0x24F is checked against the rom Octo built
+        let sprites = &expected[0x50..];
+        let mut data = String::new();
+        for (index, sprite) in sprites.chunks(15).enumerate() {
+            data.push_str(&format!(": splash-{}-{}\n", index % 6, index / 6));
+            for byte in sprite {
+                data.push_str(&format!("0x{byte:02X} "));
+            }
+            data.push('\n');
+        }
+        let source = source.replace(":include \"../../pictures/splash.png\"", &data);
+
+        let program = assemble(&source).unwrap();
+
+        assert_eq!(program.bytes[..0x50], expected[..0x50]);
+        assert_eq!(program.bytes.len(), expected.len());
+        assert_eq!(program.labels["main"], 0x200);
+    }
+
+    #[test]
+    fn supports_constants_aliases_and_control_flow() {
+        let source = "
+            :const LIMIT 10
+            :alias counter v3
+            :calc DOUBLE { LIMIT * 2 }
+            : main
+              counter := 0
+              loop
+                counter += 1
+                while counter != LIMIT
+              again
+              if counter == DOUBLE begin
+                counter := 0
+              else
+                counter -= 1
+              end
+              draw
+              :org 0x300
+            : draw
+              i := hex counter
+              sprite v0 v1 5
+              return
+        ";
+        let program = assemble(source).unwrap();
+
+        assert_eq!(
+            program.bytes[..0x16],
+            [
+                0x63, 0x00, // counter := 0
+                0x73, 0x01, // loop: counter += 1
+                0x43, 0x0A, // while counter != LIMIT
+                0x12, 0x0A, //   jump past again
+                0x12, 0x02, // again
+                0x33, 0x14, // if counter == DOUBLE begin
+                0x12, 0x12, //   jump to else
+                0x63, 0x00, // counter := 0
+                0x12, 0x14, // else: jump past end
+                0x73, 0xFF, // counter -= 1
+                0x23, 0x00, // draw
+            ]
+        );
+        assert_eq!(program.bytes[0x100..], [0xF3, 0x29, 0xD0, 0x15, 0x00, 0xEE]);
+
+        // line 5 is ": main", so its breakpoint moves on to the first instruction
+        assert_eq!(program.line_address(5), Some((6, 0x200)));
+        assert_eq!(program.address_line(0x302), Some(20));
+        assert_eq!(program.label_before(0x304), Some("draw"));
+    }
+
+    #[test]
+    fn reports_the_line_and_column_of_errors() {
+        let error = assemble("clear\n  v0 := 5\n  v1 += nowhere\n").unwrap_err();
+
+        assert_eq!(error.line, 3);
+        assert_eq!(error.column, 9);
+
+        let error = assemble(": main\n  jump missing\n").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 8));
+        assert_eq!(error.message, "\"missing\" is not defined");
+
+        let error = assemble(":calc BIG { 1 << 64 }\n").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 15));
+        assert_eq!(error.message, "cannot shift by 64");
+        assert!(assemble(":calc NEGATIVE { 8 >> -1 }\n").is_err());
+        assert!(assemble(":calc EIGHT { 1 << 3 }\n").is_ok());
+
+        // comparisons take the same bytes as :=
+        let error = assemble("if v0 == 256 then clear\n").unwrap_err();
+        assert_eq!(error.message, "256 does not fit in a byte");
+        assert!(assemble("if v0 < 300 then clear\n").is_err());
+        assert!(assemble("if v0 != -1 then clear\n").is_ok());
+    }
+
+    #[test]
+    fn assembles_xo_chip_instructions() {
+        let program = assemble(
+            "i := long data plane 3 save v1 - v4 load v4 - v1 pitch := v2 audio scroll-up 2\n\
+             : data 0xAA",
+        )
+        .unwrap();
+
+        assert_eq!(
+            program.bytes,
+            [
+                0xF0, 0x00, 0x02, 0x10, 0xF3, 0x01, 0x51, 0x42, 0x54, 0x13, 0xF2, 0x3A, 0xF0, 0x02,
+                0x00, 0xD2, 0xAA
+            ]
+        );
+    }
+
+    #[test]
+    fn assembles_super_chip_instructions() {
+        let program = assemble(
+            "hires scroll-down 4 scroll-left i := bighex v2 saveflags v3 loadflags v3 exit",
+        )
+        .unwrap();
+
+        assert_eq!(
+            program.bytes,
+            [0x00, 0xFF, 0x00, 0xC4, 0x00, 0xFC, 0xF2, 0x30, 0xF3, 0x75, 0xF3, 0x85, 0x00, 0xFD]
+        );
+    }
+}
@@ -0,0 +1,365 @@
+use std::{
+    f32::consts::PI,
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+    str::FromStr,
+};
+
+/*
+ * Audio
+ * the buzzer sounds for as long as the sound timer is running. Every frame the machine works out
+ * the samples for that 60th of a second and hands them to an AudioSink, which may play them,
+ * write them to a file or throw them away.
+ */
+
+// the sample rate sinks run at unless they are given another
+pub const SAMPLE_RATE: u32 = 44100;
+
+/* The shape of the buzzer tone */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Sine,
+    Triangle,
+    Sawtooth,
+}
+impl Waveform {
+    /** The value of the wave at a phase between 0 and 1, from -1.0 to 1.0. */
+    pub fn sample(&self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sine => (phase * 2.0 * PI).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+        }
+    }
+}
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Waveform, String> {
+        match text.to_ascii_lowercase().as_str() {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            "triangle" => Ok(Waveform::Triangle),
+            "sawtooth" | "saw" => Ok(Waveform::Sawtooth),
+            _ => Err(format!(
+                "unknown waveform \"{text}\", expected square, sine, triangle or sawtooth"
+            )),
+        }
+    }
+}
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Waveform::Square => write!(f, "square"),
+            Waveform::Sine => write!(f, "sine"),
+            Waveform::Triangle => write!(f, "triangle"),
+            Waveform::Sawtooth => write!(f, "sawtooth"),
+        }
+    }
+}
+
+/* The sound of the buzzer */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    // in hertz
+    pub frequency: f32,
+    pub waveform: Waveform,
+    // from 0.0 (silent) to 1.0
+    pub volume: f32,
+}
+impl Default for Tone {
+    fn default() -> Self {
+        Tone {
+            frequency: 440.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+        }
+    }
+}
+
+/* Where the samples of each frame go */
+pub trait AudioSink {
+    /** The number of samples per second the sink expects. */
+    fn sample_rate(&self) -> u32;
+
+    /** Takes one frame of mono samples from -1.0 to 1.0, silence included. */
+    fn play(&mut self, samples: &[f32]) -> io::Result<()>;
+}
+
+/* A sink that throws every sample away, for running with no sound */
+#[derive(Debug, Clone, Default)]
+pub struct NullSink {}
+impl NullSink {
+    pub fn new() -> NullSink {
+        NullSink {}
+    }
+}
+impl AudioSink for NullSink {
+    // no samples are worked out for a sink that ignores them
+    fn sample_rate(&self) -> u32 {
+        0
+    }
+
+    fn play(&mut self, _samples: &[f32]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/*
+ * WavSink
+ * writes the samples to a 16 bit mono WAV file. The header holds the length of the data, so it is
+ * filled in when the sink is finished or dropped.
+ */
+pub struct WavSink<W: Write + Seek> {
+    writer: Option<W>,
+    sample_rate: u32,
+    // the number of bytes of sample data written so far
+    data_length: u32,
+}
+impl WavSink<BufWriter<File>> {
+    /** Creates the WAV file at the given path. */
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<Self> {
+        WavSink::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+impl<W: Write + Seek> WavSink<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<WavSink<W>> {
+        write_wav_header(&mut writer, sample_rate, 0)?;
+        Ok(WavSink {
+            writer: Some(writer),
+            sample_rate,
+            data_length: 0,
+        })
+    }
+
+    /** Fills in the header and returns the writer. */
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_lengths()?;
+        Ok(self.writer.take().unwrap())
+    }
+
+    fn write_lengths(&mut self) -> io::Result<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.seek(SeekFrom::Start(0))?;
+            write_wav_header(writer, self.sample_rate, self.data_length)?;
+            writer.seek(SeekFrom::End(0))?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+impl<W: Write + Seek> AudioSink for WavSink<W> {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn play(&mut self, samples: &[f32]) -> io::Result<()> {
+        let writer = self.writer.as_mut().unwrap();
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        self.data_length += samples.len() as u32 * 2;
+        Ok(())
+    }
+}
+impl<W: Write + Seek> Drop for WavSink<W> {
+    fn drop(&mut self) {
+        if let Err(err) = self.write_lengths() {
+            log::warn!("could not finish the wav file: {err}");
+        }
+    }
+}
+
+fn write_wav_header<W: Write>(
+    writer: &mut W,
+    sample_rate: u32,
+    data_length: u32,
+) -> io::Result<()> {
+    let channels = 1_u16;
+    let bits_per_sample = 16_u16;
+    let block_align = channels * bits_per_sample / 8;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_length).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16_u32.to_le_bytes())?;
+    // 1 is uncompressed PCM
+    writer.write_all(&1_u16.to_le_bytes())?;
+    writer.write_all(&channels.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&bits_per_sample.to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_length.to_le_bytes())
+}
+
+/* Works out the buzzer samples, keeping the phase of the wave between frames */
+#[derive(Debug, Clone, Default)]
+pub struct Buzzer {
+    tone: Tone,
+    // the position in the current cycle of the wave, from 0 to 1
+    phase: f32,
+    pattern_player: PatternPlayer,
+}
+impl Buzzer {
+    pub fn new(tone: Tone) -> Buzzer {
+        Buzzer {
+            tone,
+            phase: 0.0,
+            pattern_player: PatternPlayer::new(),
+        }
+    }
+
+    pub fn tone(&self) -> Tone {
+        self.tone
+    }
+
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.tone = tone;
+    }
+
+    /** Fills samples with the tone when sounding is set, and with silence when it is not. */
+    pub fn fill_tone(&mut self, sounding: bool, sample_rate: u32, samples: &mut [f32]) {
+        if !sounding {
+            samples.fill(0.0);
+            // start the next beep at the start of a cycle
+            self.phase = 0.0;
+            self.pattern_player.reset();
+            return;
+        }
+
+        let step = self.tone.frequency / sample_rate as f32;
+        for sample in samples.iter_mut() {
+            *sample = self.tone.waveform.sample(self.phase) * self.tone.volume;
+            self.phase = (self.phase + step).fract();
+        }
+    }
+
+    /** Fills samples with an XO-CHIP audio pattern at the tone's volume. */
+    pub fn fill_pattern(
+        &mut self,
+        pattern: &[u8; PATTERN_LENGTH],
+        pitch: u8,
+        sample_rate: u32,
+        samples: &mut [f32],
+    ) {
+        self.pattern_player
+            .fill(pattern, pitch, sample_rate, samples);
+        for sample in samples.iter_mut() {
+            *sample *= self.tone.volume;
+        }
+    }
+}
+
+/*
+ * XO-CHIP audio
+ * F002 loads a pattern of 128 one bit samples and FX3A sets the rate they are played at. The
+ * pattern loops for as long as the sound timer is running.
+ */
+
+// the pitch a machine starts with, which plays the pattern at 4000 bits per second
+pub const DEFAULT_PITCH: u8 = 64;
+
+// the number of bytes in an audio pattern
+pub const PATTERN_LENGTH: usize = 16;
+
+/** The number of pattern bits played per second at the given pitch. */
+pub fn pattern_rate(pitch: u8) -> f64 {
+    4000.0 * 2_f64.powf((pitch as f64 - 64.0) / 48.0)
+}
+
+/* Plays an audio pattern as samples, keeping its place between calls */
+#[derive(Debug, Clone, Default)]
+pub struct PatternPlayer {
+    // the position in the pattern in bits
+    position: f64,
+}
+impl PatternPlayer {
+    pub fn new() -> PatternPlayer {
+        PatternPlayer { position: 0.0 }
+    }
+
+    /** Fills samples with the pattern at the given pitch, a set bit is 1.0 and a clear bit -1.0. */
+    pub fn fill(
+        &mut self,
+        pattern: &[u8; PATTERN_LENGTH],
+        pitch: u8,
+        sample_rate: u32,
+        samples: &mut [f32],
+    ) {
+        let bits = (PATTERN_LENGTH * 8) as f64;
+        let step = pattern_rate(pitch) / sample_rate as f64;
+
+        for sample in samples.iter_mut() {
+            let bit = self.position as usize;
+            let on = pattern[bit / 8] >> (7 - bit % 8) & 1 == 1;
+            *sample = if on { 1.0 } else { -1.0 };
+
+            self.position = (self.position + step) % bits;
+        }
+    }
+
+    /** Starts the pattern again from its first bit. */
+    pub fn reset(&mut self) {
+        self.position = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod audio_tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn plays_the_pattern_at_its_pitch() {
+        assert_eq!(pattern_rate(DEFAULT_PITCH), 4000.0);
+        assert!((pattern_rate(DEFAULT_PITCH + 48) - 8000.0).abs() < 1e-9);
+
+        // one bit per sample, eight bits on then the rest off
+        let mut pattern = [0; PATTERN_LENGTH];
+        pattern[0] = 0xFF;
+        let mut samples = [0.0; 12];
+        PatternPlayer::new().fill(&pattern, DEFAULT_PITCH, 4000, &mut samples);
+
+        assert_eq!(samples[..8], [1.0; 8]);
+        assert_eq!(samples[8..], [-1.0; 4]);
+    }
+
+    #[test]
+    fn writes_wav_files() {
+        let mut buzzer = Buzzer::new(Tone {
+            frequency: 1000.0,
+            waveform: Waveform::Square,
+            volume: 1.0,
+        });
+        let mut samples = [0.0; 8];
+        buzzer.fill_tone(true, 8000, &mut samples);
+        assert_eq!(samples, [1.0, 1.0, 1.0, 1.0, -1.0, -1.0, -1.0, -1.0]);
+
+        let mut sink = WavSink::new(Cursor::new(Vec::new()), 8000).unwrap();
+        sink.play(&samples).unwrap();
+        let wav = sink.finish().unwrap().into_inner();
+
+        assert_eq!(wav.len(), 44 + 16);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(wav[4..8], 52_u32.to_le_bytes());
+        assert_eq!(wav[24..28], 8000_u32.to_le_bytes());
+        assert_eq!(wav[40..44], 16_u32.to_le_bytes());
+        assert_eq!(wav[44..46], i16::MAX.to_le_bytes());
+    }
+}
@@ -0,0 +1,608 @@
+use std::{
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Instant,
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    assembler::{assemble_file, Program},
+    debugger::{Breakpoint, Debugger, Register, StopReason},
+    machine::{Machine, FRAMES_PER_SECOND},
+    platform::Platform,
+    rom::load_rom,
+    scheduler::Scheduler,
+};
+
+/*
+ * Debug Adapter Protocol
+ * a server for editors that debug through the Debug Adapter Protocol
+ * (https://microsoft.github.io/debug-adapter-protocol/), over stdio or a TCP connection.
+ *
+ * It launches a rom, or an Octo .8o file that it assembles first, and runs it with no window.
+ * Breakpoints can go on source lines of an .8o file or on instruction addresses. The chip8 is
+ * shown as a single thread whose stack frames are the program counter followed by the call site
+ * of every return address on the Stack, and the registers and timers are its variables.
+ *
+ * The launch request takes the path of the program, and optionally stopOnEntry and a platform
+ * (chip8, schip or xochip).
+ */
+
+// the one thread there is
+const THREAD_ID: u64 = 1;
+
+// the variables reference of the registers scope, the only one
+const REGISTERS_REFERENCE: u64 = 1;
+
+pub struct DapServer<W: Write> {
+    output: W,
+    // the sequence number of the next message sent
+    seq: u64,
+    session: Option<Session>,
+    // set by disconnect, the server stops once it is
+    finished: bool,
+}
+
+/* A launched program */
+struct Session {
+    machine: Machine,
+    debugger: Debugger,
+    // the assembled source, when an .8o file was launched
+    program: Option<Program>,
+    path: PathBuf,
+    stop_on_entry: bool,
+    // the addresses of the breakpoints on source lines and on instructions, each set separately
+    line_breakpoints: Vec<u16>,
+    instruction_breakpoints: Vec<u16>,
+}
+
+impl<W: Write> DapServer<W> {
+    /** Creates a server that sends its messages to the output. */
+    pub fn new(output: W) -> DapServer<W> {
+        DapServer {
+            output,
+            seq: 1,
+            session: None,
+            finished: false,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /** Returns true while a launched program is running rather than stopped. */
+    pub fn is_running(&self) -> bool {
+        self.session
+            .as_ref()
+            .is_some_and(|session| !session.debugger.is_paused())
+    }
+
+    /**
+     * Answers the requests read from the input and runs the program in real time until the
+     * editor disconnects or the input ends.
+     */
+    pub fn serve<R: BufRead + Send + 'static>(mut self, mut input: R) -> io::Result<()> {
+        // the input is read on a thread of its own, so the program runs while no request comes
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(Some(message)) = read_message(&mut input) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut scheduler = Scheduler::new(FRAMES_PER_SECOND);
+        while !self.finished {
+            let message = if self.is_running() {
+                let timeout = scheduler
+                    .next_frame()
+                    .saturating_duration_since(Instant::now());
+                receiver.recv_timeout(timeout)
+            } else {
+                receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            };
+            match message {
+                Ok(message) => self.handle(&message)?,
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            for _ in 0..scheduler.frames_due(Instant::now()) {
+                self.run_frame()?;
+            }
+        }
+        Ok(())
+    }
+
+    /** Runs a frame of the program unless it is stopped, telling the editor when it stops. */
+    pub fn run_frame(&mut self) -> io::Result<()> {
+        let Some(session) = &mut self.session else {
+            return Ok(());
+        };
+        let Some(reason) = session.debugger.run_frame(&mut session.machine) else {
+            return Ok(());
+        };
+
+        match reason {
+            StopReason::Exited => {
+                self.event("exited", json!({ "exitCode": 0 }))?;
+                self.event("terminated", json!({}))
+            }
+            StopReason::Step => self.stopped("step"),
+            StopReason::Breakpoint(_) => self.stopped("breakpoint"),
+            StopReason::Watchpoint { .. } => self.stopped("data breakpoint"),
+            StopReason::Error(error) => {
+                let text = format!("The program crashed: {error}\n");
+                self.event("output", json!({ "category": "stderr", "output": text }))?;
+                self.stopped("exception")
+            }
+        }
+    }
+
+    /** Answers one message from the editor. */
+    pub fn handle(&mut self, message: &Value) -> io::Result<()> {
+        if message["type"] != "request" {
+            return Ok(());
+        }
+        let command = message["command"].as_str().unwrap_or_default();
+        let arguments = &message["arguments"];
+
+        match self.request(command, arguments) {
+            Ok(body) => {
+                self.respond(message, true, body)?;
+                self.after(command)
+            }
+            Err(err) => self.respond(
+                message,
+                false,
+                json!({ "error": { "id": 1, "format": err } }),
+            ),
+        }
+    }
+
+    // carries out a request and returns the body of its response
+    fn request(&mut self, command: &str, arguments: &Value) -> Result<Value, String> {
+        if command == "initialize" {
+            return Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsInstructionBreakpoints": true,
+            }));
+        }
+        if command == "launch" {
+            self.session = Some(Session::launch(arguments)?);
+            return Ok(json!({}));
+        }
+        if command == "disconnect" || command == "terminate" {
+            self.finished = true;
+            return Ok(json!({}));
+        }
+
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| format!("{command} needs a launched program"))?;
+        let machine = &session.machine;
+
+        let body = match command {
+            "setBreakpoints" => {
+                let lines = arguments["breakpoints"]
+                    .as_array()
+                    .map(|breakpoints| {
+                        breakpoints
+                            .iter()
+                            .filter_map(|breakpoint| breakpoint["line"].as_u64())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let breakpoints = session.set_line_breakpoints(lines);
+                json!({ "breakpoints": breakpoints })
+            }
+            "setInstructionBreakpoints" => {
+                let references = arguments["breakpoints"]
+                    .as_array()
+                    .map(|breakpoints| {
+                        breakpoints
+                            .iter()
+                            .map(|breakpoint| {
+                                let reference = breakpoint["instructionReference"]
+                                    .as_str()
+                                    .and_then(parse_address);
+                                let offset = breakpoint["offset"].as_i64().unwrap_or(0);
+                                reference.map(|address| (address as i64 + offset) as u16)
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let breakpoints = session.set_instruction_breakpoints(references);
+                json!({ "breakpoints": breakpoints })
+            }
+            "setExceptionBreakpoints" => json!({}),
+            "configurationDone" => {
+                if !session.stop_on_entry {
+                    session.debugger.resume();
+                }
+                json!({})
+            }
+            "threads" => json!({ "threads": [{ "id": THREAD_ID, "name": "chip8" }] }),
+            "stackTrace" => {
+                let frames = session.stack_frames();
+                json!({ "stackFrames": frames, "totalFrames": frames.len() })
+            }
+            "scopes" => json!({
+                "scopes": [{
+                    "name": "Registers",
+                    "presentationHint": "registers",
+                    "variablesReference": REGISTERS_REFERENCE,
+                    "expensive": false,
+                }]
+            }),
+            "variables" => json!({ "variables": registers(machine) }),
+            "continue" => {
+                session.debugger.resume();
+                json!({ "allThreadsContinued": true })
+            }
+            "next" => {
+                session.debugger.step_over(machine);
+                json!({})
+            }
+            "stepIn" => {
+                session.debugger.step(1);
+                json!({})
+            }
+            "stepOut" => {
+                // at the top level there is nothing to step out of, so it steps instead
+                if machine.stack().get_sp() > 0 {
+                    session.debugger.step_out(machine);
+                } else {
+                    session.debugger.step(1);
+                }
+                json!({})
+            }
+            "pause" => {
+                session.debugger.pause();
+                json!({})
+            }
+            _ => return Err(format!("{command} is not supported")),
+        };
+        Ok(body)
+    }
+
+    // the events that follow a response
+    fn after(&mut self, command: &str) -> io::Result<()> {
+        match command {
+            "launch" => self.event("initialized", json!({})),
+            "configurationDone" if !self.is_running() => self.stopped("entry"),
+            "pause" => self.stopped("pause"),
+            _ => Ok(()),
+        }
+    }
+
+    fn stopped(&mut self, reason: &str) -> io::Result<()> {
+        self.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        )
+    }
+
+    fn respond(&mut self, request: &Value, success: bool, body: Value) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": success,
+            "body": body,
+        });
+        if !success {
+            response["message"] = body["error"]["format"].clone();
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        let content = message.to_string();
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{content}",
+            content.len()
+        )?;
+        self.output.flush()
+    }
+}
+
+impl Session {
+    fn launch(arguments: &Value) -> Result<Session, String> {
+        let path = arguments["program"]
+            .as_str()
+            .ok_or("launch needs the path of a program")?;
+        let platform = match arguments["platform"].as_str() {
+            Some(platform) => platform.parse()?,
+            None => Platform::default(),
+        };
+
+        // .8o files are assembled here rather than by load_rom, to keep their source lines
+        let is_source = Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("8o"));
+        let (rom, program) = if is_source {
+            let program = assemble_file(path).map_err(|err| format!("{path}: {err}"))?;
+            (program.bytes.clone(), Some(program))
+        } else {
+            (load_rom(path)?, None)
+        };
+
+        Ok(Session {
+            machine: Machine::for_platform(platform, &rom)?,
+            debugger: Debugger::new(),
+            program,
+            path: PathBuf::from(path),
+            stop_on_entry: arguments["stopOnEntry"].as_bool().unwrap_or(false),
+            line_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new(),
+        })
+    }
+
+    // puts breakpoints on the source lines, moving each to the next line with an instruction
+    fn set_line_breakpoints(&mut self, lines: Vec<u64>) -> Vec<Value> {
+        self.line_breakpoints.clear();
+        let breakpoints = lines
+            .into_iter()
+            .map(|line| {
+                let found = self
+                    .program
+                    .as_ref()
+                    .and_then(|program| program.line_address(line as usize));
+                match found {
+                    Some((line, address)) => {
+                        self.line_breakpoints.push(address);
+                        json!({
+                            "verified": true,
+                            "line": line,
+                            "instructionReference": format!("0x{address:03X}"),
+                        })
+                    }
+                    None => json!({
+                        "verified": false,
+                        "line": line,
+                        "message": "no instruction on or after this line",
+                    }),
+                }
+            })
+            .collect();
+        self.update_breakpoints();
+        breakpoints
+    }
+
+    fn set_instruction_breakpoints(&mut self, addresses: Vec<Option<u16>>) -> Vec<Value> {
+        self.instruction_breakpoints = addresses.iter().flatten().copied().collect();
+        self.update_breakpoints();
+        addresses
+            .into_iter()
+            .map(|address| match address {
+                Some(address) => json!({
+                    "verified": true,
+                    "instructionReference": format!("0x{address:03X}"),
+                }),
+                None => json!({ "verified": false, "message": "not an address" }),
+            })
+            .collect()
+    }
+
+    fn update_breakpoints(&mut self) {
+        self.debugger.clear_breakpoints();
+        for address in self
+            .line_breakpoints
+            .iter()
+            .chain(&self.instruction_breakpoints)
+        {
+            self.debugger.add_breakpoint(Breakpoint::Address(*address));
+        }
+    }
+
+    // the program counter, then the call to every subroutine it is in, innermost first
+    fn stack_frames(&self) -> Vec<Value> {
+        let pc = self.machine.program_counter().get_pc() as u16;
+        let calls = self
+            .machine
+            .stack()
+            .get_stack()
+            .iter()
+            .rev()
+            .map(|return_address| return_address.wrapping_sub(2));
+
+        std::iter::once(pc)
+            .chain(calls)
+            .enumerate()
+            .map(|(id, address)| {
+                let label = self
+                    .program
+                    .as_ref()
+                    .and_then(|program| program.label_before(address));
+                let name = match label {
+                    Some(label) => format!("{label} (0x{address:03X})"),
+                    None => format!("0x{address:03X}"),
+                };
+                let line = self
+                    .program
+                    .as_ref()
+                    .and_then(|program| program.address_line(address));
+
+                let mut frame = json!({
+                    "id": id,
+                    "name": name,
+                    "line": line.unwrap_or(0),
+                    "column": 0,
+                    "instructionPointerReference": format!("0x{address:03X}"),
+                });
+                if line.is_some() {
+                    frame["source"] = json!({
+                        "name": self.path.file_name().map(|name| name.to_string_lossy()),
+                        "path": self.path.to_string_lossy(),
+                    });
+                    frame["column"] = json!(1);
+                }
+                frame
+            })
+            .collect()
+    }
+}
+
+// V0 to VF, I, PC, SP and the timers
+fn registers(machine: &Machine) -> Vec<Value> {
+    let registers = (0..16).map(Register::V).chain([
+        Register::I,
+        Register::Pc,
+        Register::Sp,
+        Register::Delay,
+        Register::Sound,
+    ]);
+    registers
+        .map(|register| {
+            let value = register.read(machine);
+            let value = match register {
+                Register::I | Register::Pc => format!("0x{value:03X}"),
+                _ => format!("0x{value:02X}"),
+            };
+            json!({
+                "name": register.to_string().to_uppercase(),
+                "value": value,
+                "variablesReference": 0,
+            })
+        })
+        .collect()
+}
+
+fn parse_address(text: &str) -> Option<u16> {
+    let text = text.trim();
+    match text.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/** Reads one message, framed by a Content-Length header. None when the input has ended. */
+pub fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut content = vec![0; length];
+    input.read_exact(&mut content)?;
+    serde_json::from_slice(&content)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod dap_tests {
+    use std::{fs, io::Cursor};
+
+    use super::*;
+
+    const SOURCE: &str = "\
+: main
+  v0 := 0
+  loop
+    count
+  again
+
+: count
+  v0 += 1
+  return
+";
+
+    // takes the messages the server has sent so far
+    fn sent(server: &mut DapServer<Vec<u8>>) -> Vec<Value> {
+        let mut input = Cursor::new(std::mem::take(&mut server.output));
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut input).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    fn request(server: &mut DapServer<Vec<u8>>, command: &str, arguments: Value) -> Vec<Value> {
+        server
+            .handle(&json!({
+                "seq": 1,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            }))
+            .unwrap();
+        sent(server)
+    }
+
+    #[test]
+    fn debugs_an_octo_program() {
+        let path = std::env::temp_dir().join("chip8-dap-test.8o");
+        fs::write(&path, SOURCE).unwrap();
+        let mut server = DapServer::new(Vec::new());
+
+        request(&mut server, "initialize", json!({}));
+        let launched = request(&mut server, "launch", json!({ "program": path }));
+        assert_eq!(launched[0]["success"], true);
+        assert_eq!(launched[1]["event"], "initialized");
+
+        // line 7 is the label, so the breakpoint moves on to "v0 += 1"
+        let set = request(
+            &mut server,
+            "setBreakpoints",
+            json!({ "source": { "path": path }, "breakpoints": [{ "line": 7 }] }),
+        );
+        assert_eq!(set[0]["body"]["breakpoints"][0]["line"], 8);
+        request(&mut server, "configurationDone", json!({}));
+
+        while sent(&mut server).is_empty() {
+            server.run_frame().unwrap();
+        }
+        let trace = request(&mut server, "stackTrace", json!({ "threadId": 1 }));
+        let frames = &trace[0]["body"]["stackFrames"];
+        assert_eq!(frames[0]["line"], 8);
+        assert_eq!(frames[0]["name"], "count (0x206)");
+        assert_eq!(frames[1]["line"], 4);
+        assert_eq!(frames[1]["name"], "main (0x202)");
+
+        // step out of the call, then into it again
+        request(&mut server, "setBreakpoints", json!({ "breakpoints": [] }));
+        request(&mut server, "stepOut", json!({ "threadId": 1 }));
+        while sent(&mut server).is_empty() {
+            server.run_frame().unwrap();
+        }
+        let trace = request(&mut server, "stackTrace", json!({ "threadId": 1 }));
+        assert_eq!(trace[0]["body"]["totalFrames"], 1);
+        assert_eq!(trace[0]["body"]["stackFrames"][0]["line"], 5);
+
+        let variables = request(&mut server, "variables", json!({ "variablesReference": 1 }));
+        let v0 = &variables[0]["body"]["variables"][0];
+        assert_eq!((&v0["name"], &v0["value"]), (&json!("V0"), &json!("0x01")));
+
+        let disconnected = request(&mut server, "disconnect", json!({}));
+        assert_eq!(disconnected[0]["success"], true);
+        assert!(server.is_finished());
+        fs::remove_file(path).unwrap();
+    }
+}
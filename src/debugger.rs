@@ -0,0 +1,879 @@
+use std::{fmt, ops::Range, str::FromStr};
+
+use crate::{error::Chip8Error, instruction::Instruction, machine::Machine};
+
+/*
+ * Debugger
+ * runs a machine an instruction at a time, stopping at breakpoints and watchpoints, and steps
+ * over calls, out of subroutines and on to an address. It is driven one frame at a time by the
+ * winit frontend, so the window keeps running while the program is paused, and is shared by the
+ * terminal debugger and the remote debugging servers.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    mode: RunMode,
+    // the value of each register condition after the last instruction, they stop when they turn true
+    conditions: Vec<bool>,
+    // a copy of memory holding the watched bytes as they were before the last instruction, only
+    // the watched ranges are kept up to date
+    watched: Vec<u8>,
+}
+
+/* What the debugger is doing with the machine */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RunMode {
+    #[default]
+    Paused,
+    Running,
+    // run the given number of instructions then pause
+    Step(u32),
+    // run until the program counter reaches the address with the stack no deeper than depth
+    RunTo {
+        address: u16,
+        depth: usize,
+    },
+    // run until the stack is shallower than depth
+    StepOut {
+        depth: usize,
+    },
+}
+
+/* Why the machine stopped */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    // a step, step over, step out or run to finished
+    Step,
+    Breakpoint(usize),
+    Watchpoint { address: u16, old: u8, new: u8 },
+    Exited,
+    // the instruction at the program counter failed, running it again fails the same way
+    Error(Chip8Error),
+}
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Step => write!(f, "stepped"),
+            StopReason::Breakpoint(index) => write!(f, "hit breakpoint {index}"),
+            StopReason::Watchpoint { address, old, new } => {
+                write!(f, "0x{address:03X} changed from 0x{old:02X} to 0x{new:02X}")
+            }
+            StopReason::Exited => write!(f, "the program exited"),
+            StopReason::Error(error) => write!(f, "the program crashed: {error}"),
+        }
+    }
+}
+
+/*
+ * Breakpoint
+ * stops the machine before the instruction at an address, before any instruction that matches
+ * an opcode pattern, or when a register condition turns true
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breakpoint {
+    Address(u16),
+    Opcode(OpcodePattern),
+    Condition(Condition),
+}
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    /** Parses "0x2A4", "op DXYN" or "if v3 == 5". */
+    fn from_str(text: &str) -> Result<Breakpoint, String> {
+        let text = text.trim();
+        if let Some(pattern) = text.strip_prefix("op ") {
+            return Ok(Breakpoint::Opcode(pattern.parse()?));
+        }
+        if let Some(condition) = text.strip_prefix("if ") {
+            return Ok(Breakpoint::Condition(condition.parse()?));
+        }
+        Ok(Breakpoint::Address(parse_address(text)?))
+    }
+}
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Address(address) => write!(f, "0x{address:03X}"),
+            Breakpoint::Opcode(pattern) => write!(f, "op {pattern}"),
+            Breakpoint::Condition(condition) => write!(f, "if {condition}"),
+        }
+    }
+}
+
+/*
+ * OpcodePattern
+ * an opcode written with hex digits and wildcards, "DXYN" matches every draw and "8XY6" every
+ * shift right. X, Y, N and any other letter past F stand for any digit.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodePattern {
+    value: u16,
+    // the bits of the opcode that have to match value
+    mask: u16,
+}
+impl OpcodePattern {
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+impl FromStr for OpcodePattern {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<OpcodePattern, String> {
+        let text = text.trim();
+        if text.len() != 4 {
+            return Err(format!(
+                "\"{text}\" is not an opcode pattern, expected four digits like DXYN"
+            ));
+        }
+
+        let mut pattern = OpcodePattern { value: 0, mask: 0 };
+        for character in text.chars() {
+            pattern.value <<= 4;
+            pattern.mask <<= 4;
+            if let Some(digit) = character.to_digit(16) {
+                pattern.value |= digit as u16;
+                pattern.mask |= 0xF;
+            }
+        }
+        Ok(pattern)
+    }
+}
+impl fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, wildcard) in ['N', 'Y', 'X', 'X'].iter().enumerate().rev() {
+            let shift = index * 4;
+            if self.mask >> shift & 0xF == 0 {
+                write!(f, "{wildcard}")?;
+            } else {
+                write!(f, "{:X}", self.value >> shift & 0xF)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/* A register that conditions and debuggers can read */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Delay,
+    Sound,
+}
+impl Register {
+    /** Reads the register from the machine. */
+    pub fn read(&self, machine: &Machine) -> u16 {
+        match self {
+            Register::V(x) => *machine.registers().get_register(*x).unwrap() as u16,
+            Register::I => *machine.registers().get_i_register(),
+            Register::Pc => machine.program_counter().get_pc() as u16,
+            Register::Sp => machine.stack().get_sp() as u16,
+            Register::Delay => *machine.registers().get_delay_timer() as u16,
+            Register::Sound => machine.sound_timer().get_timer() as u16,
+        }
+    }
+
+    /** Writes the register, values too big for it are cut down to its size. */
+    pub fn write(&self, machine: &mut Machine, value: u16) {
+        match self {
+            Register::V(x) => {
+                machine.registers_mut().set_register(*x, value as u8);
+            }
+            Register::I => machine.registers_mut().set_i_register(value),
+            Register::Pc => machine.program_counter_mut().set_counter(value),
+            Register::Sp => {
+                // a stack pointer past the end of the stack is left alone
+                let _ = machine.stack_mut().set_sp(value as usize);
+            }
+            Register::Delay => machine.registers_mut().set_delay_timer(value as u8),
+            Register::Sound => machine.sound_timer_mut().set_timer(value as u8),
+        }
+    }
+}
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Register, String> {
+        let lower = text.trim().to_ascii_lowercase();
+        match lower.as_str() {
+            "i" => Ok(Register::I),
+            "pc" => Ok(Register::Pc),
+            "sp" => Ok(Register::Sp),
+            "dt" | "delay" => Ok(Register::Delay),
+            "st" | "sound" => Ok(Register::Sound),
+            _ => match lower.strip_prefix('v') {
+                Some(x) if x.len() == 1 => u8::from_str_radix(x, 16)
+                    .map(Register::V)
+                    .map_err(|_| format!("unknown register \"{text}\"")),
+                _ => Err(format!("unknown register \"{text}\"")),
+            },
+        }
+    }
+}
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "v{x:X}"),
+            Register::I => write!(f, "i"),
+            Register::Pc => write!(f, "pc"),
+            Register::Sp => write!(f, "sp"),
+            Register::Delay => write!(f, "dt"),
+            Register::Sound => write!(f, "st"),
+        }
+    }
+}
+
+/* A comparison of a register with a value, e.g. "v3 == 5" or "i >= 0x300" */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+impl Condition {
+    pub fn holds(&self, machine: &Machine) -> bool {
+        let value = self.register.read(machine);
+        match self.comparison {
+            Comparison::Equal => value == self.value,
+            Comparison::NotEqual => value != self.value,
+            Comparison::Less => value < self.value,
+            Comparison::LessOrEqual => value <= self.value,
+            Comparison::Greater => value > self.value,
+            Comparison::GreaterOrEqual => value >= self.value,
+        }
+    }
+}
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Condition, String> {
+        let words = text.split_whitespace().collect::<Vec<&str>>();
+        let [register, comparison, value] = words.as_slice() else {
+            return Err(format!(
+                "\"{text}\" is not a condition, expected something like \"v3 == 5\""
+            ));
+        };
+        let comparison = match *comparison {
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            "<=" => Comparison::LessOrEqual,
+            ">" => Comparison::Greater,
+            ">=" => Comparison::GreaterOrEqual,
+            other => return Err(format!("unknown comparison \"{other}\"")),
+        };
+        Ok(Condition {
+            register: register.parse()?,
+            comparison,
+            value: parse_address(value)?,
+        })
+    }
+}
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let comparison = match self.comparison {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        };
+        write!(f, "{} {comparison} {}", self.register, self.value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/* Stops the machine when a byte in a range of memory changes, both ends included */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+}
+impl FromStr for Watchpoint {
+    type Err = String;
+
+    /** Parses "0x300" or "0x300-0x30F". */
+    fn from_str(text: &str) -> Result<Watchpoint, String> {
+        let (start, end) = match text.split_once('-') {
+            Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+            None => {
+                let address = parse_address(text)?;
+                (address, address)
+            }
+        };
+        if end < start {
+            return Err(format!("the watched range {text} ends before it starts"));
+        }
+        Ok(Watchpoint { start, end })
+    }
+}
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "0x{:03X}", self.start)
+        } else {
+            write!(f, "0x{:03X}-0x{:03X}", self.start, self.end)
+        }
+    }
+}
+
+impl Debugger {
+    /** Creates a debugger with the machine paused. */
+    pub fn new() -> Debugger {
+        Debugger::default()
+    }
+
+    pub fn mode(&self) -> RunMode {
+        self.mode
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.mode == RunMode::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.mode = RunMode::Paused;
+    }
+
+    /** Runs until a breakpoint or watchpoint is hit. */
+    pub fn resume(&mut self) {
+        self.mode = RunMode::Running;
+    }
+
+    /** Runs the given number of instructions. */
+    pub fn step(&mut self, count: u32) {
+        self.mode = RunMode::Step(count.max(1));
+    }
+
+    /** Runs one instruction, or a whole subroutine when the instruction is a call. */
+    pub fn step_over(&mut self, machine: &Machine) {
+        let pc = machine.program_counter().get_pc() as u16;
+        self.mode = match current_instruction(machine) {
+            Some(Instruction::Call { .. }) => RunMode::RunTo {
+                address: pc.wrapping_add(2),
+                depth: machine.stack().get_sp(),
+            },
+            _ => RunMode::Step(1),
+        };
+    }
+
+    /** Runs until the current subroutine returns. */
+    pub fn step_out(&mut self, machine: &Machine) {
+        self.mode = RunMode::StepOut {
+            depth: machine.stack().get_sp(),
+        };
+    }
+
+    /** Runs until the program counter reaches the address. */
+    pub fn run_to(&mut self, address: u16) {
+        self.mode = RunMode::RunTo {
+            address,
+            depth: usize::MAX,
+        };
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /** Adds a breakpoint and returns its number. */
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        self.breakpoints.push(breakpoint);
+        self.conditions.push(false);
+        self.breakpoints.len() - 1
+    }
+
+    pub fn remove_breakpoint(&mut self, index: usize) -> Result<Breakpoint, String> {
+        if index >= self.breakpoints.len() {
+            return Err(format!("there is no breakpoint {index}"));
+        }
+        self.conditions.remove(index);
+        Ok(self.breakpoints.remove(index))
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+        self.conditions.clear();
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /** Adds a watchpoint and returns its number. */
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        self.watchpoints.push(watchpoint);
+        self.watchpoints.len() - 1
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Result<Watchpoint, String> {
+        if index >= self.watchpoints.len() {
+            return Err(format!("there is no watchpoint {index}"));
+        }
+        Ok(self.watchpoints.remove(index))
+    }
+
+    /**
+     * Runs the rest of the machine's frame in the current mode, stopping early when the mode
+     * finishes or a breakpoint or watchpoint is hit. Nothing runs while paused.
+     */
+    pub fn run_frame(&mut self, machine: &mut Machine) -> Option<StopReason> {
+        // memory may have been changed from outside between frames, which watchpoints ignore
+        self.snapshot_watched(machine);
+        while !self.is_paused() {
+            let (frame_ended, stop) = self.step_instruction(machine);
+            if stop.is_some() {
+                self.mode = RunMode::Paused;
+                return stop;
+            }
+            if frame_ended {
+                break;
+            }
+        }
+        None
+    }
+
+    // runs one instruction and works out whether the machine should stop after it
+    fn step_instruction(&mut self, machine: &mut Machine) -> (bool, Option<StopReason>) {
+        let frame_ended = match machine.step_in_frame() {
+            Ok(frame_ended) => frame_ended,
+            Err(error) => return (false, Some(StopReason::Error(error))),
+        };
+
+        if machine.has_exited() {
+            return (frame_ended, Some(StopReason::Exited));
+        }
+
+        if let Some((address, old, new)) = self.changed_watched_byte(machine) {
+            return (
+                frame_ended,
+                Some(StopReason::Watchpoint { address, old, new }),
+            );
+        }
+
+        // conditions stop when they turn true, not on every instruction while they hold
+        let mut hit = None;
+        for (index, breakpoint) in self.breakpoints.iter().enumerate() {
+            let stop = match breakpoint {
+                Breakpoint::Address(address) => {
+                    machine.program_counter().get_pc() == *address as usize
+                }
+                Breakpoint::Opcode(pattern) => {
+                    current_opcode(machine).is_some_and(|opcode| pattern.matches(opcode))
+                }
+                Breakpoint::Condition(condition) => {
+                    let holds = condition.holds(machine);
+                    let turned_true = holds && !self.conditions[index];
+                    self.conditions[index] = holds;
+                    turned_true
+                }
+            };
+            if stop && hit.is_none() {
+                hit = Some(StopReason::Breakpoint(index));
+            }
+        }
+        if hit.is_some() {
+            return (frame_ended, hit);
+        }
+
+        let pc = machine.program_counter().get_pc() as u16;
+        let depth = machine.stack().get_sp();
+        let finished = match &mut self.mode {
+            RunMode::Step(count) => {
+                *count -= 1;
+                *count == 0
+            }
+            RunMode::RunTo {
+                address,
+                depth: start_depth,
+            } => pc == *address && depth <= *start_depth,
+            RunMode::StepOut { depth: start_depth } => depth < *start_depth,
+            RunMode::Running | RunMode::Paused => false,
+        };
+        (frame_ended, finished.then_some(StopReason::Step))
+    }
+
+    // the part of memory a watchpoint covers
+    fn watched_range(watchpoint: &Watchpoint, memory: &[u8]) -> Range<usize> {
+        let end = (watchpoint.end as usize + 1).min(memory.len());
+        (watchpoint.start as usize).min(end)..end
+    }
+
+    // copies the current value of every watched byte
+    fn snapshot_watched(&mut self, machine: &Machine) {
+        let memory = machine.memory().as_slice();
+        self.watched.resize(memory.len(), 0);
+        for watchpoint in &self.watchpoints {
+            let range = Debugger::watched_range(watchpoint, memory);
+            self.watched[range.clone()].copy_from_slice(&memory[range]);
+        }
+    }
+
+    // the first watched byte the last instruction changed, as its address, old and new values
+    fn changed_watched_byte(&mut self, machine: &Machine) -> Option<(u16, u8, u8)> {
+        let memory = machine.memory().as_slice();
+        for watchpoint in &self.watchpoints {
+            let range = Debugger::watched_range(watchpoint, memory);
+            let changed = range
+                .clone()
+                .find(|&address| memory[address] != self.watched[address]);
+            if let Some(address) = changed {
+                let old = self.watched[address];
+                self.watched[range.clone()].copy_from_slice(&memory[range]);
+                return Some((address as u16, old, memory[address]));
+            }
+        }
+        None
+    }
+}
+
+// the commands the terminal debugger understands, printed by help
+const HELP: &str = "\
+continue | c               run until a breakpoint or watchpoint
+pause | p                  stop the program
+step | s [count]           run one or more instructions
+next | n                   run one instruction, or a whole subroutine call
+finish | out               run until the current subroutine returns
+until | u <address>        run until the program reaches an address
+break | b <address>        stop before the instruction at an address
+break | b op <pattern>     stop before any instruction like DXYN or 8XY6
+break | b if <condition>   stop when a condition like v3 == 5 or i >= 0x300 turns true
+watch | w <start>[-<end>]  stop when a byte of memory in the range changes
+delete | d <n>             remove breakpoint n
+unwatch <n>                remove watchpoint n
+info | i                   list the breakpoints and watchpoints
+regs | r                   show the registers, timers and stack
+list | l [address]         disassemble around the program counter or an address
+x <address> [length]       show memory";
+
+impl Debugger {
+    /**
+     * Runs a line typed at the terminal debugger and returns what to print. Commands that start
+     * the machine running return straight away, run_frame does the running.
+     */
+    pub fn execute(&mut self, machine: &Machine, line: &str) -> Result<String, String> {
+        let line = line.trim();
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let argument = argument.trim();
+
+        match command {
+            "" => Ok(String::new()),
+            "help" | "h" | "?" => Ok(String::from(HELP)),
+            "continue" | "c" => {
+                self.resume();
+                Ok(String::from("running"))
+            }
+            "pause" | "p" => {
+                self.pause();
+                Ok(describe_machine(machine))
+            }
+            "step" | "s" => {
+                let count = match argument {
+                    "" => 1,
+                    count => count
+                        .parse()
+                        .map_err(|_| format!("\"{count}\" is not a number of steps"))?,
+                };
+                self.step(count);
+                Ok(String::new())
+            }
+            "next" | "n" => {
+                self.step_over(machine);
+                Ok(String::new())
+            }
+            "finish" | "out" => {
+                if machine.stack().get_sp() == 0 {
+                    return Err(String::from("not in a subroutine"));
+                }
+                self.step_out(machine);
+                Ok(String::new())
+            }
+            "until" | "u" => {
+                self.run_to(parse_address(argument)?);
+                Ok(String::new())
+            }
+            "break" | "b" => {
+                let breakpoint = argument.parse()?;
+                let index = self.add_breakpoint(breakpoint);
+                Ok(format!("breakpoint {index}: {breakpoint}"))
+            }
+            "watch" | "w" => {
+                let watchpoint = argument.parse()?;
+                let index = self.add_watchpoint(watchpoint);
+                Ok(format!("watchpoint {index}: {watchpoint}"))
+            }
+            "delete" | "d" => {
+                let breakpoint = self.remove_breakpoint(parse_index(argument)?)?;
+                Ok(format!("deleted breakpoint {breakpoint}"))
+            }
+            "unwatch" => {
+                let watchpoint = self.remove_watchpoint(parse_index(argument)?)?;
+                Ok(format!("deleted watchpoint {watchpoint}"))
+            }
+            "info" | "i" => {
+                let mut lines = Vec::new();
+                for (index, breakpoint) in self.breakpoints.iter().enumerate() {
+                    lines.push(format!("breakpoint {index}: {breakpoint}"));
+                }
+                for (index, watchpoint) in self.watchpoints.iter().enumerate() {
+                    lines.push(format!("watchpoint {index}: {watchpoint}"));
+                }
+                if lines.is_empty() {
+                    lines.push(String::from("no breakpoints or watchpoints"));
+                }
+                Ok(lines.join("\n"))
+            }
+            "regs" | "r" => Ok(describe_registers(machine)),
+            "list" | "l" => {
+                let address = match argument {
+                    "" => machine.program_counter().get_pc() as u16,
+                    address => parse_address(address)?,
+                };
+                Ok(disassemble_around(machine, address, 5, 5).join("\n"))
+            }
+            "x" => {
+                let (address, length) = argument.split_once(' ').unwrap_or((argument, "16"));
+                let address = parse_address(address)? as usize;
+                let length = parse_address(length)? as usize;
+                let memory = machine.memory().as_slice();
+                let end = (address + length).min(memory.len());
+                let lines = memory
+                    .get(address..end)
+                    .unwrap_or(&[])
+                    .chunks(16)
+                    .enumerate()
+                    .map(|(row, bytes)| {
+                        let bytes = bytes
+                            .iter()
+                            .map(|byte| format!("{byte:02X}"))
+                            .collect::<Vec<String>>();
+                        format!("{:03X}: {}", address + row * 16, bytes.join(" "))
+                    })
+                    .collect::<Vec<String>>();
+                Ok(lines.join("\n"))
+            }
+            _ => Err(format!("unknown command \"{command}\", try help")),
+        }
+    }
+}
+
+/** Describes the machine where it stopped: its registers and the instructions around it. */
+pub fn describe_machine(machine: &Machine) -> String {
+    let pc = machine.program_counter().get_pc() as u16;
+    let listing = disassemble_around(machine, pc, 3, 4).join("\n");
+    format!("{}\n{listing}", describe_registers(machine))
+}
+
+/** The opcode at the program counter. */
+pub fn current_opcode(machine: &Machine) -> Option<u16> {
+    let pc = machine.program_counter().get_pc();
+    let memory = machine.memory().as_slice();
+    Some((*memory.get(pc)? as u16) << 8 | *memory.get(pc + 1)? as u16)
+}
+
+/** The instruction at the program counter, when it is one. */
+pub fn current_instruction(machine: &Machine) -> Option<Instruction> {
+    let pc = machine.program_counter().get_pc();
+    Instruction::decode_bytes(machine.memory().as_slice().get(pc..)?).ok()
+}
+
+/** Lists the instructions around an address, the address itself marked with an arrow. */
+pub fn disassemble_around(machine: &Machine, address: u16, before: u16, after: u16) -> Vec<String> {
+    let memory = machine.memory().as_slice();
+    let start = address.saturating_sub(before * 2);
+    let end = address.saturating_add(after * 2 + 2);
+    (start..end)
+        .step_by(2)
+        .filter(|address| (*address as usize) + 1 < memory.len())
+        .map(|line_address| {
+            let bytes = &memory[line_address as usize..];
+            let opcode = (bytes[0] as u16) << 8 | bytes[1] as u16;
+            let text = match Instruction::decode_bytes(bytes) {
+                Ok(instruction) => instruction.to_string(),
+                Err(_) => format!("DW 0x{opcode:04X}"),
+            };
+            let marker = if line_address == address { "=>" } else { "  " };
+            format!("{marker} {line_address:03X}: {opcode:04X}  {text}")
+        })
+        .collect()
+}
+
+/** Describes the registers, timers and stack of the machine. */
+pub fn describe_registers(machine: &Machine) -> String {
+    let registers = machine.registers();
+    let mut text = String::new();
+    for row in 0..2 {
+        let line = (0..8)
+            .map(|column| {
+                let x = row * 8 + column;
+                format!("V{x:X}={:02X}", registers.get_register(x).unwrap())
+            })
+            .collect::<Vec<String>>()
+            .join(" ");
+        text.push_str(&line);
+        text.push('\n');
+    }
+    text.push_str(&format!(
+        "I={:03X} PC={:03X} SP={}/{} DT={:02X} ST={:02X} frame={} cycle={}\n",
+        registers.get_i_register(),
+        machine.program_counter().get_pc(),
+        machine.stack().get_sp(),
+        machine.stack().get_depth(),
+        registers.get_delay_timer(),
+        machine.sound_timer().get_timer(),
+        machine.frame_count(),
+        machine.cycle_count(),
+    ));
+    let stack = machine.stack().get_stack();
+    let entries = stack
+        .iter()
+        .map(|entry| format!("{entry:03X}"))
+        .collect::<Vec<String>>();
+    text.push_str(&format!("stack: [{}]", entries.join(" ")));
+    text
+}
+
+fn parse_index(text: &str) -> Result<usize, String> {
+    text.trim()
+        .parse()
+        .map_err(|_| format!("\"{text}\" is not a number"))
+}
+
+// a number in hex with 0x or $, or in decimal
+fn parse_address(text: &str) -> Result<u16, String> {
+    let text = text.trim();
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix('$')) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("\"{text}\" is not a number"))
+}
+
+#[cfg(test)]
+mod debugger_tests {
+    use crate::platform::Platform;
+
+    use super::*;
+
+    // 6000 v0 := 0, 220C call, 7001 v0 += 1, A300 i := 0x300, F055 save v0, 1202 loop
+    // 20C: 6105 v1 := 5, 00EE return
+    const ROM: [u8; 16] = [
+        0x60, 0x00, 0x22, 0x0C, 0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x02, 0x61, 0x05, 0x00,
+        0xEE,
+    ];
+
+    fn machine() -> Machine {
+        Machine::new(64, 32, &ROM).unwrap()
+    }
+
+    #[test]
+    fn steps_over_into_and_out_of_calls() {
+        let mut machine = machine();
+        let mut debugger = Debugger::new();
+
+        debugger.step(1);
+        assert_eq!(debugger.run_frame(&mut machine), Some(StopReason::Step));
+        assert_eq!(machine.program_counter().get_pc(), 0x202);
+
+        // step over the call
+        debugger.step_over(&machine);
+        assert_eq!(debugger.run_frame(&mut machine), Some(StopReason::Step));
+        assert_eq!(machine.program_counter().get_pc(), 0x204);
+        assert_eq!(*machine.registers().get_register(1).unwrap(), 5);
+
+        // into it and back out
+        debugger.run_to(0x202);
+        debugger.run_frame(&mut machine);
+        debugger.step(1);
+        debugger.run_frame(&mut machine);
+        assert_eq!(machine.program_counter().get_pc(), 0x20C);
+        debugger.step_out(&machine);
+        assert_eq!(debugger.run_frame(&mut machine), Some(StopReason::Step));
+        assert_eq!(machine.program_counter().get_pc(), 0x204);
+        // a call in the last two bytes of XO-CHIP memory returns to address 0
+        let mut machine = Machine::for_platform(Platform::XoChip, &ROM).unwrap();
+        machine.memory_mut().get_memory()[0xFFFE..].copy_from_slice(&[0x22, 0x0C]);
+        machine.program_counter.set_counter(0xFFFE);
+        debugger.step_over(&machine);
+        assert!(matches!(debugger.mode(), RunMode::RunTo { address: 0, .. }));
+    }
+
+    #[test]
+    fn stops_at_breakpoints_and_watchpoints() {
+        let mut machine = machine();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint("op FX55".parse().unwrap());
+        debugger.add_watchpoint("0x300-0x301".parse().unwrap());
+        debugger.add_breakpoint("if v0 == 3".parse().unwrap());
+
+        debugger.resume();
+        assert_eq!(
+            debugger.run_frame(&mut machine),
+            Some(StopReason::Breakpoint(0))
+        );
+        assert_eq!(current_opcode(&machine), Some(0xF055));
+
+        debugger.resume();
+        assert_eq!(
+            debugger.run_frame(&mut machine),
+            Some(StopReason::Watchpoint {
+                address: 0x300,
+                old: 0,
+                new: 1
+            })
+        );
+
+        debugger.remove_breakpoint(0).unwrap();
+        debugger.remove_watchpoint(0).unwrap();
+        debugger.resume();
+        while debugger.run_frame(&mut machine).is_none() {}
+        assert_eq!(*machine.registers().get_register(0).unwrap(), 3);
+        assert!(debugger.is_paused());
+    }
+
+    #[test]
+    fn parses_and_shows_the_machine() {
+        let pattern: OpcodePattern = "DXYN".parse().unwrap();
+        assert!(pattern.matches(0xD125) && !pattern.matches(0xC125));
+        assert_eq!(pattern.to_string(), "DXYN");
+        assert!("if q == 1".parse::<Breakpoint>().is_err());
+
+        let machine = machine();
+        let listing = disassemble_around(&machine, 0x202, 1, 1);
+        assert_eq!(listing[1], "=> 202: 220C  CALL 0x20C");
+        assert!(describe_registers(&machine).contains("PC=200"));
+
+        let mut debugger = Debugger::new();
+        assert_eq!(
+            debugger.execute(&machine, "b op DXYN"),
+            Ok(String::from("breakpoint 0: op DXYN"))
+        );
+        assert_eq!(
+            debugger.execute(&machine, "x 0x200 4"),
+            Ok(String::from("200: 60 00 22 0C"))
+        );
+        assert!(debugger.execute(&machine, "finish").is_err());
+        debugger.execute(&machine, "until 0x208").unwrap();
+        assert_eq!(
+            debugger.mode(),
+            RunMode::RunTo {
+                address: 0x208,
+                depth: usize::MAX
+            }
+        );
+    }
+}
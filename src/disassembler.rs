@@ -0,0 +1,268 @@
+use std::{collections::BTreeSet, fmt, str::FromStr};
+
+use crate::{instruction::Instruction, machine::PROGRAM_START};
+
+/* The mnemonic syntax used in a listing */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    // e.g. "v3 += 0x05", as written in Octo sources
+    Octo,
+    // e.g. "ADD V3, 0x05", as written in Cowgod's Chip-8 technical reference
+    Cowgod,
+}
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Syntax, String> {
+        match text.to_ascii_lowercase().as_str() {
+            "octo" => Ok(Syntax::Octo),
+            "cowgod" | "classic" => Ok(Syntax::Cowgod),
+            _ => Err(format!(
+                "unknown syntax \"{text}\", expected octo or cowgod"
+            )),
+        }
+    }
+}
+
+/* One line of a listing: either a reachable instruction or a byte of data */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+    pub comment: String,
+}
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        write!(f, "0x{:03X}  {bytes:<5}  {:<24}", self.address, self.text)?;
+        if !self.comment.is_empty() {
+            write!(f, "# {}", self.comment)?;
+        }
+        Ok(())
+    }
+}
+
+/*
+ * find_code
+ * follows every jump, call and skip from 0x200 to work out which addresses hold instructions.
+ * Returns the set of addresses an instruction starts at, anything else in the rom is data.
+ */
+pub fn find_code(rom: &[u8]) -> BTreeSet<u16> {
+    let instruction_at = |address: u16| {
+        let index = (address as usize).checked_sub(PROGRAM_START as usize)?;
+        Instruction::decode_bytes(rom.get(index..)?).ok()
+    };
+
+    let mut code = BTreeSet::new();
+    let mut pending = vec![PROGRAM_START];
+
+    while let Some(address) = pending.pop() {
+        if code.contains(&address) {
+            continue;
+        }
+        let instruction = match instruction_at(address) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        code.insert(address);
+
+        // nothing follows an instruction at the end of the address space
+        let next = address.checked_add(instruction.byte_len());
+        match instruction {
+            Instruction::Jump { nnn } => pending.push(nnn),
+            Instruction::Call { nnn } => {
+                pending.push(nnn);
+                pending.extend(next);
+            }
+            // the target depends on v0, the best guess is the table starts at nnn
+            Instruction::JumpV0 { nnn } => pending.push(nnn),
+            Instruction::Ret | Instruction::Exit => {}
+            Instruction::SkipEqByte { .. }
+            | Instruction::SkipNeByte { .. }
+            | Instruction::SkipEqReg { .. }
+            | Instruction::SkipNeReg { .. }
+            | Instruction::SkipKey { .. }
+            | Instruction::SkipNotKey { .. } => {
+                if let Some(next) = next {
+                    pending.push(next);
+                    // XO-CHIP skips the whole of a four byte F000 NNNN
+                    let skipped = instruction_at(next).map_or(2, |skipped| skipped.byte_len());
+                    pending.extend(next.checked_add(skipped));
+                }
+            }
+            _ => pending.extend(next),
+        }
+    }
+
+    code
+}
+
+/** Describes what an instruction does in plain English. */
+pub fn describe(instruction: &Instruction) -> String {
+    match *instruction {
+        Instruction::Sys { nnn } => format!("call machine code at 0x{nnn:03X} (ignored)"),
+        Instruction::ScrollDown { n } => format!("scroll the display down {n} pixels"),
+        Instruction::ScrollUp { n } => format!("scroll the display up {n} pixels"),
+        Instruction::Cls => "clear the screen".to_string(),
+        Instruction::Ret => "return from subroutine".to_string(),
+        Instruction::ScrollRight => "scroll the display right 4 pixels".to_string(),
+        Instruction::ScrollLeft => "scroll the display left 4 pixels".to_string(),
+        Instruction::Exit => "exit the interpreter".to_string(),
+        Instruction::LowRes => "switch to the 64x32 display".to_string(),
+        Instruction::HighRes => "switch to the 128x64 display".to_string(),
+        Instruction::Jump { nnn } => format!("jump to 0x{nnn:03X}"),
+        Instruction::Call { nnn } => format!("call subroutine at 0x{nnn:03X}"),
+        Instruction::SkipEqByte { x, nn } => format!("skip next if v{x:X} == {nn}"),
+        Instruction::SkipNeByte { x, nn } => format!("skip next if v{x:X} != {nn}"),
+        Instruction::SkipEqReg { x, y } => format!("skip next if v{x:X} == v{y:X}"),
+        Instruction::StoreRange { x, y } => format!("store v{x:X} to v{y:X} at i"),
+        Instruction::LoadRange { x, y } => format!("load v{x:X} to v{y:X} from i"),
+        Instruction::LoadByte { x, nn } => format!("v{x:X} = {nn}"),
+        Instruction::AddByte { x, nn } => format!("v{x:X} = v{x:X} + {nn}, no carry"),
+        Instruction::LoadReg { x, y } => format!("v{x:X} = v{y:X}"),
+        Instruction::Or { x, y } => format!("v{x:X} = v{x:X} or v{y:X}"),
+        Instruction::And { x, y } => format!("v{x:X} = v{x:X} and v{y:X}"),
+        Instruction::Xor { x, y } => format!("v{x:X} = v{x:X} xor v{y:X}"),
+        Instruction::AddReg { x, y } => format!("v{x:X} = v{x:X} + v{y:X}, vF = carry"),
+        Instruction::Sub { x, y } => format!("v{x:X} = v{x:X} - v{y:X}, vF = not borrow"),
+        Instruction::ShiftRight { x, .. } => format!("v{x:X} = v{x:X} >> 1, vF = bit shifted out"),
+        Instruction::SubN { x, y } => format!("v{x:X} = v{y:X} - v{x:X}, vF = not borrow"),
+        Instruction::ShiftLeft { x, .. } => format!("v{x:X} = v{x:X} << 1, vF = bit shifted out"),
+        Instruction::SkipNeReg { x, y } => format!("skip next if v{x:X} != v{y:X}"),
+        Instruction::LoadI { nnn } => format!("i = 0x{nnn:03X}"),
+        Instruction::JumpV0 { nnn } => format!("jump to 0x{nnn:03X} + v0"),
+        Instruction::Random { x, nn } => format!("v{x:X} = random and 0x{nn:02X}"),
+        Instruction::Draw { x, y, n: 0 } => {
+            format!("draw 16x16 sprite from i at (v{x:X}, v{y:X}), vF = collision")
+        }
+        Instruction::Draw { x, y, n } => {
+            format!("draw 8x{n} sprite from i at (v{x:X}, v{y:X}), vF = collision")
+        }
+        Instruction::SkipKey { x } => format!("skip next if key v{x:X} is pressed"),
+        Instruction::SkipNotKey { x } => format!("skip next if key v{x:X} is not pressed"),
+        Instruction::LoadLongI { nnnn } => format!("i = 0x{nnnn:04X}"),
+        Instruction::Plane { n } => format!("draw on bit planes {n}"),
+        Instruction::Audio => "load the audio pattern from i".to_string(),
+        Instruction::LoadDelay { x } => format!("v{x:X} = delay timer"),
+        Instruction::WaitKey { x } => format!("wait for a key press, v{x:X} = key"),
+        Instruction::SetDelay { x } => format!("delay timer = v{x:X}"),
+        Instruction::SetSound { x } => format!("sound timer = v{x:X}"),
+        Instruction::AddI { x } => format!("i = i + v{x:X}"),
+        Instruction::LoadFont { x } => format!("i = address of font character v{x:X}"),
+        Instruction::LoadBigFont { x } => format!("i = address of big font character v{x:X}"),
+        Instruction::Pitch { x } => format!("audio pitch = v{x:X}"),
+        Instruction::Bcd { x } => format!("store decimal digits of v{x:X} at i, i+1, i+2"),
+        Instruction::Store { x } => format!("store v0 to v{x:X} at i"),
+        Instruction::Load { x } => format!("load v0 to v{x:X} from i"),
+        Instruction::StoreFlags { x } => format!("store v0 to v{x:X} in the flag registers"),
+        Instruction::LoadFlags { x } => format!("load v0 to v{x:X} from the flag registers"),
+    }
+}
+
+/** Disassembles a rom loaded at 0x200 into an annotated listing. */
+pub fn disassemble(rom: &[u8], syntax: Syntax) -> Vec<Line> {
+    let code = find_code(rom);
+    let mut lines = Vec::new();
+    let mut index = 0;
+
+    while index < rom.len() {
+        // bytes past the end of the address space can not be loaded, so they are not listed
+        let address = match u16::try_from(PROGRAM_START as usize + index) {
+            Ok(address) => address,
+            Err(_) => break,
+        };
+
+        if code.contains(&address) {
+            let instruction = Instruction::decode_bytes(&rom[index..]).unwrap();
+            let length = instruction.byte_len() as usize;
+            let text = match syntax {
+                Syntax::Octo => instruction.to_octo(),
+                Syntax::Cowgod => instruction.to_string(),
+            };
+
+            lines.push(Line {
+                address,
+                bytes: rom[index..index + length].to_vec(),
+                text,
+                comment: describe(&instruction),
+            });
+            index += length;
+        } else {
+            let byte = rom[index];
+            let text = match syntax {
+                Syntax::Octo => format!("0x{byte:02X}"),
+                Syntax::Cowgod => format!("DB 0x{byte:02X}"),
+            };
+            // data is most often sprites, so show the byte as a row of pixels
+            let comment = (0..8)
+                .rev()
+                .map(|bit| if byte >> bit & 1 == 1 { '#' } else { '.' })
+                .collect();
+
+            lines.push(Line {
+                address,
+                bytes: vec![byte],
+                text,
+                comment,
+            });
+            index += 1;
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod disassembler_tests {
+    use super::*;
+
+    // 00E0 clear, A208 i := sprite, D015 draw, 1206 loop, then a 5 byte sprite
+    const ROM: [u8; 13] = [
+        0x00, 0xE0, 0xA2, 0x08, 0xD0, 0x15, 0x12, 0x06, 0xF0, 0x90, 0x90, 0x90, 0xF0,
+    ];
+
+    #[test]
+    fn separates_code_from_data() {
+        let code = find_code(&ROM);
+
+        assert_eq!(
+            code.into_iter().collect::<Vec<u16>>(),
+            [0x200, 0x202, 0x204, 0x206]
+        );
+    }
+
+    #[test]
+    fn lists_both_syntaxes() {
+        let cowgod = disassemble(&ROM, Syntax::Cowgod);
+        let octo = disassemble(&ROM, Syntax::Octo);
+
+        assert_eq!(cowgod.len(), 9);
+        assert_eq!(cowgod[2].text, "DRW V0, V1, 0x5");
+        assert_eq!(octo[2].text, "sprite v0 v1 5");
+        assert_eq!(cowgod[4].text, "DB 0xF0");
+        assert_eq!(cowgod[4].comment, "####....");
+        assert_eq!(
+            cowgod[0].to_string(),
+            "0x200  00 E0  CLS                     # clear the screen"
+        );
+    }
+
+    #[test]
+    fn stops_at_the_end_of_the_address_space() {
+        // 0000 runs on to the next instruction, up to 0xFFFE and two bytes past the end
+        let rom = vec![0; 0x10000 - PROGRAM_START as usize + 2];
+
+        assert_eq!(find_code(&rom).last(), Some(&0xFFFE));
+        assert_eq!(
+            disassemble(&rom, Syntax::Octo).last().unwrap().address,
+            0xFFFE
+        );
+    }
+}
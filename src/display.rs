@@ -1,3 +1,4 @@
+use chip8::{font::get_character_set, keypad::Keypad};
 use pixels::{Error, Pixels, SurfaceTexture};
 use winit::{
     dpi::LogicalSize,
@@ -5,6 +6,8 @@ use winit::{
     window::{Window, WindowBuilder},
 };
 
+use crate::keyboard::KEYPAD_LAYOUT;
+
 /*
  * build_window
  * builds the window for rendering pixels
@@ -15,7 +18,7 @@ pub fn build_window(width: u32, height: u32, event_loop: &EventLoop<()>) -> Wind
         .with_title("chip 8")
         .with_inner_size(size)
         .with_min_inner_size(size)
-        .build(&event_loop)
+        .build(event_loop)
         .unwrap()
 }
 
@@ -30,3 +33,66 @@ pub fn build_pixels(window: &Window, width: u32, height: u32) -> Result<Pixels,
     let viewport = Pixels::new(width, height, surface_texture)?;
     Ok(viewport)
 }
+
+// the color of a lit pixel: red, green, blue, alpha
+pub const PIXEL_COLOR: [u8; 4] = [0xE2, 0x1B, 0x88, 0xFF];
+
+// the XO-CHIP colors, indexed by the bit planes a pixel is lit on: none, plane 1, plane 2, both
+pub const PALETTE: [[u8; 4]; 4] = [
+    [0x00, 0x00, 0x00, 0x00],
+    PIXEL_COLOR,
+    [0x1B, 0xB6, 0xE2, 0xFF],
+    [0xFF, 0xFF, 0xFF, 0xFF],
+];
+
+/*
+ * draw_framebuffer
+ * copies a machine framebuffer (one byte per pixel) into an rgba pixels frame
+ */
+pub fn draw_framebuffer(framebuffer: &[u8], frame: &mut [u8]) {
+    for (pixel, rgba) in framebuffer.iter().zip(frame.chunks_exact_mut(4)) {
+        rgba.copy_from_slice(&PALETTE[(*pixel & 0x3) as usize]);
+    }
+}
+
+// the color of the keypad overlay and of the keys held on it
+const OVERLAY_COLOR: [u8; 4] = [0x20, 0x20, 0x20, 0xFF];
+const OVERLAY_TEXT_COLOR: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+
+/*
+ * draw_keypad
+ * draws the chip8 keypad over the top left of an rgba pixels frame, with the keys held lit up,
+ * so pressing a keyboard key shows which chip8 key it is mapped to
+ */
+pub fn draw_keypad(keypad: &Keypad, width: u32, frame: &mut [u8]) {
+    let fonts = get_character_set();
+    // every key is a 4 x 5 digit with a pixel around it
+    let (cell_width, cell_height) = (6, 7);
+    let width = width as usize;
+
+    for (row, keys) in KEYPAD_LAYOUT.iter().enumerate() {
+        for (column, key) in keys.iter().enumerate() {
+            let background = if keypad.is_pressed(*key) {
+                PIXEL_COLOR
+            } else {
+                OVERLAY_COLOR
+            };
+            let glyph = fonts[key];
+            for y in 0..cell_height {
+                for x in 0..cell_width {
+                    let lit = (1..6).contains(&y)
+                        && (1..5).contains(&x)
+                        && glyph[y - 1] & (0x80 >> (x - 1)) > 0;
+                    let pixel = (row * cell_height + y) * width + column * cell_width + x;
+                    if let Some(rgba) = frame.get_mut(pixel * 4..pixel * 4 + 4) {
+                        rgba.copy_from_slice(if lit {
+                            &OVERLAY_TEXT_COLOR
+                        } else {
+                            &background
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,69 +1,186 @@
 // Draw provides basic drawing capabilities for blitting drawables to the chip8 display
-// Display 64 x 32 pixels monochrome
+// Display 64 x 32 pixels monochrome, or 128 x 64 on SUPER-CHIP
+// XO-CHIP has two bit planes: bit 0 of a screen byte is plane 1 and bit 1 is plane 2, giving 4 colors
 pub struct Draw<'a> {
     width: usize,
     screen: &'a mut [u8],
-    // sprites: Vec<u16>,
+    // each sprite pixel covers scale x scale screen pixels, 2 in the SUPER-CHIP low resolution mode
+    scale: usize,
+    // the bit planes drawn on, cleared and scrolled
+    planes: u8,
 }
 impl Draw<'_> {
-    pub fn new(width: u32, screen: &mut [u8]) -> Draw {
+    pub fn new(width: u32, screen: &mut [u8]) -> Draw<'_> {
+        Draw::with_scale(width, 1, screen)
+    }
+
+    pub fn with_scale(width: u32, scale: u32, screen: &mut [u8]) -> Draw<'_> {
         Draw {
             width: width as usize,
             screen,
+            scale: scale.max(1) as usize,
+            planes: 1,
         }
     }
 
+    /* selects the bit planes to act on, 1 and 2 are the single planes and 3 is both */
+    pub fn set_planes(&mut self, planes: u8) {
+        self.planes = planes & 0x3;
+    }
+
+    fn height(&self) -> usize {
+        self.screen.len() / self.width
+    }
+
     // bits raw hexadecimal values to the screen at the given destination
-    pub fn blit_raw(&mut self, pixels: &[u8], dest: &Point, height: u8) -> bool {
-        // calculate the base point: where to draw the sprite
-        // multiply by 4 because there is one byte for the pixel and 3 bytes for the color
-        let mut draw_point = (self.width * 4 * dest.y) + dest.x * 4;
+    // pixels past the edge of the screen wrap around to the other side when wrap is set, otherwise they are clipped
+    pub fn blit_raw(&mut self, pixels: &[u8], dest: &Point, height: u8, wrap: bool) -> bool {
+        self.blit(pixels, dest, height as usize, 1, wrap)
+    }
+
+    // bits a 16 x 16 SUPER-CHIP sprite, two bytes per row
+    pub fn blit_wide(&mut self, pixels: &[u8], dest: &Point, wrap: bool) -> bool {
+        self.blit(pixels, dest, 16, 2, wrap)
+    }
 
+    // the number of bytes a sprite takes up in memory, one copy of it for every selected plane
+    pub fn sprite_length(&self, rows: usize, bytes_per_row: usize) -> usize {
+        rows * bytes_per_row * self.planes.count_ones() as usize
+    }
+
+    fn blit(
+        &mut self,
+        pixels: &[u8],
+        dest: &Point,
+        rows: usize,
+        bytes_per_row: usize,
+        wrap: bool,
+    ) -> bool {
         // if any pixels are turned off, set the flag register
         let mut set_flag_register = false;
 
-        // TODO: create a final pixels array to store the data and then blit all in one shot
-        // let mut final_pixels: Vec<u8> = Vec::new();
+        // with both planes selected the sprite for plane 1 is followed by the sprite for plane 2
+        let sprite_length = rows * bytes_per_row;
+        let selected = self.planes;
+        let planes = [1_u8, 2].into_iter().filter(|plane| selected & plane > 0);
+        for (index, plane) in planes.enumerate() {
+            let sprite = &pixels[index * sprite_length..(index + 1) * sprite_length];
+            if self.blit_plane(sprite, dest, bytes_per_row, wrap, plane) {
+                set_flag_register = true;
+            }
+        }
+
+        set_flag_register
+    }
+
+    fn blit_plane(
+        &mut self,
+        pixels: &[u8],
+        dest: &Point,
+        bytes_per_row: usize,
+        wrap: bool,
+        plane: u8,
+    ) -> bool {
+        let rows = pixels.len() / bytes_per_row;
+
+        // the size of the screen in sprite pixels
+        let width = self.width / self.scale;
+        let height = self.height() / self.scale;
+
+        let mut set_flag_register = false;
 
         // loop through the height
-        for i in 0..height {
-            // get the pixel
-            let byte = &pixels[i as usize];
-            for (index, byte_i) in (0..8).rev().enumerate() {
-                let bit = (byte >> byte_i) & 1;
-                let loc = draw_point + (index * 4);
-
-                if bit == 1 && (loc < self.screen.len()) {
-                    // if data already exists flip the bit to
-                    if self.screen[loc] > 0x0
-                        && self.screen[loc + 1] > 0x0
-                        && self.screen[loc + 2] > 0x0
-                        && self.screen[loc + 3] > 0x0
-                    {
-                        self.screen[loc] = 0x0;
-                        self.screen[loc + 1] = 0x0;
-                        self.screen[loc + 2] = 0x0;
-                        self.screen[loc + 3] = 0x0;
-
-                        set_flag_register = true;
-                    } else {
-                        self.screen[loc] = 0xE2;
-                        self.screen[loc + 1] = 0x1B;
-                        self.screen[loc + 2] = 0x88;
-                        self.screen[loc + 3] = 0xFF;
+        for row in 0..rows {
+            let mut y = dest.y + row;
+            if y >= height {
+                if !wrap {
+                    break;
+                }
+                y %= height;
+            }
+
+            for column in 0..bytes_per_row * 8 {
+                // get the pixel
+                let byte = pixels[row * bytes_per_row + column / 8];
+                let bit = (byte >> (7 - column % 8)) & 1;
+                if bit == 0 {
+                    continue;
+                }
+
+                let mut x = dest.x + column;
+                if x >= width {
+                    if !wrap {
+                        break;
                     }
+                    x %= width;
+                }
+
+                if self.flip(x, y, plane) {
+                    set_flag_register = true;
                 }
             }
-            draw_point += self.width * 4;
         }
 
         set_flag_register
     }
 
-    /* clears the screen */
+    // flips one sprite pixel on a plane, returns true when it was turned off
+    fn flip(&mut self, x: usize, y: usize, plane: u8) -> bool {
+        // the screen holds one byte per pixel
+        let loc = self.width * y * self.scale + x * self.scale;
+        let turned_off = self.screen[loc] & plane > 0x0;
+
+        for row in 0..self.scale {
+            for column in 0..self.scale {
+                self.screen[loc + row * self.width + column] ^= plane;
+            }
+        }
+
+        turned_off
+    }
+
+    // moves the selected planes by the given number of pixels, what moves in from the edge is cleared
+    fn scroll(&mut self, right: isize, down: isize) {
+        let source = self.screen.to_vec();
+        let width = self.width as isize;
+        let height = self.height() as isize;
+
+        for (loc, pixel) in self.screen.iter_mut().enumerate() {
+            let x = loc as isize % width - right;
+            let y = loc as isize / width - down;
+            let moved = if (0..width).contains(&x) && (0..height).contains(&y) {
+                source[(y * width + x) as usize]
+            } else {
+                0
+            };
+            *pixel = (*pixel & !self.planes) | (moved & self.planes);
+        }
+    }
+
+    /* scrolls the screen down by the given number of pixels, the rows left at the top are cleared */
+    pub fn scroll_down(&mut self, rows: usize) {
+        self.scroll(0, rows as isize);
+    }
+
+    /* scrolls the screen up by the given number of pixels */
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.scroll(0, -(rows as isize));
+    }
+
+    /* scrolls the screen right by the given number of pixels */
+    pub fn scroll_right(&mut self, columns: usize) {
+        self.scroll(columns as isize, 0);
+    }
+
+    /* scrolls the screen left by the given number of pixels */
+    pub fn scroll_left(&mut self, columns: usize) {
+        self.scroll(-(columns as isize), 0);
+    }
+
+    /* clears the selected planes of the screen */
     pub fn clear(&mut self) {
         for element in self.screen.iter_mut() {
-            *element = 0;
+            *element &= !self.planes;
         }
     }
 }
@@ -74,10 +191,3 @@ pub struct Point {
     pub x: usize,
     pub y: usize,
 }
-
-/*  Drawables can be blitted to the pixel buffer and animated. */
-pub trait Drawable {
-    fn width(&self) -> usize;
-    fn height(&self) -> usize;
-    fn pixels(&self) -> &[u8];
-}
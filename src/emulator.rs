@@ -1,508 +1,573 @@
-use pixels::Pixels;
-use winit::event::{ScanCode, ElementState};
+use std::{fs, ops::Range};
 
 use crate::{
     draw::{Draw, Point},
+    error::Chip8Error,
+    font::{BIG_FONT_START, FONT_START},
+    instruction::Instruction,
+    machine::Machine,
     memory::Memory,
     program_counter::ProgramCounter,
+    quirks::MemoryIncrement,
     registers::Registers,
-    stack::Stack,
+    stack::StackPolicy,
 };
 
-pub fn execute(
-    instruction: u16,
-    memory: &mut Memory,
-    stack: &mut Stack,
-    registers: &mut Registers,
-    program_counter: &mut ProgramCounter,
-    pixels: &mut Pixels,
-    width: u32,
-    key_state: KeyPress,
-) {
-    // println!("{:04X}", instruction);
+/**
+ * Executes an instruction fetched from pc. Errors are raised before the instruction changes
+ * anything, so the machine is left as it was.
+ */
+pub fn execute(instruction: Instruction, pc: u16, machine: &mut Machine) -> Result<(), Chip8Error> {
     /*
-     * NNN: address
-     * NN: 8-bit constant
-     * N: 4-bit constant
-     * X and Y: 4-bit register identifier
      * PC : Program Counter
      * I : 16bit register (For memory address) (Similar to void pointer);
      * VN: One of the 16 available variables. N may be 0 to F (hexadecimal);
      */
-    let first_nibble = (instruction >> 12) & 0xF;
-
-    let screen = pixels.frame_mut();
-
-    let mut draw = Draw::new(width, screen);
-
-    let vy_index = (instruction >> 4 & 0xF) as u8;
-    let vy_value = *registers.get_register(vy_index).unwrap();
-    let vx_index = (instruction >> 8 & 0xF) as u8;
-    let vx_value = *registers.get_register(vx_index).unwrap();
-    let dt = *registers.get_delay_timer();
-    let i = *registers.get_i_register();
-    let active_memory = memory.get_memory();
-
-    let mut render = false;
-
-    match first_nibble {
-        // 0 Calls machine code routine at address NNN - not be needed for emulator
-        0x0 => {
-            match instruction & 0xFF {
-                // 00E0 - clears screen
-                0xE0 => {
-                    draw.clear();
-                    render = true
-                },
-                // 00EE
-                // Return from a subroutine.
-                // The interpreter sets the program counter to the address at the top of the stack, then subtracts 1 from the stack pointer.
-                0xEE => {
-                    let current_address = stack.pop();
-                    program_counter.jump(current_address);
-                }
-                _ => (),
+    let Machine {
+        memory,
+        stack,
+        registers,
+        program_counter,
+        framebuffer,
+        width,
+        height,
+        keypad,
+        display_changed,
+        quirks,
+        waiting_for_frame,
+        platform,
+        hires,
+        exited,
+        rpl_flags,
+        rpl_file,
+        planes,
+        audio_pattern,
+        pitch,
+        sound_timer,
+        rng,
+        frame_count,
+        ..
+    } = machine;
+
+    let vx = |registers: &Registers, x: u8| registers.get_register(x).copied().unwrap_or(0);
+
+    // the addresses an instruction reads or writes, all of which must be in memory
+    let opcode = instruction.encode();
+    let span = |memory: &Memory, start: usize, length: usize| -> Result<Range<usize>, Chip8Error> {
+        if start + length <= memory.size() {
+            Ok(start..start + length)
+        } else {
+            Err(Chip8Error::MemoryOutOfBounds {
+                pc,
+                opcode,
+                address: start.max(memory.size()),
+            })
+        }
+    };
+
+    // in low resolution every pixel is drawn 2 x 2 on the SUPER-CHIP display
+    let scale = if *hires { 1 } else { (*width / 64).max(1) };
+    // SUPER-CHIP 1.1 scrolls the screen pixels, half a pixel in low resolution, where XO-CHIP
+    // scrolls whole pixels as Octo does
+    let scroll_scale = if platform.supports_xochip() { scale } else { 1 };
+
+    // skips the next instruction, on XO-CHIP the whole of a four byte F000 NNNN
+    let xochip = platform.supports_xochip();
+    let skip = |program_counter: &mut ProgramCounter, memory: &mut Memory| {
+        let pc = program_counter.get_pc();
+        let next = memory.get_memory().get(pc..pc + 2);
+        if xochip && next == Some(&[0xF0, 0x00]) {
+            program_counter.increment_by(4);
+        } else {
+            program_counter.increment_by(2);
+        }
+    };
+
+    match instruction {
+        // 0NNN Calls machine code routine at address NNN - not be needed for emulator
+        Instruction::Sys { .. } => (),
+
+        // the SUPER-CHIP instructions are ignored on platforms without them, like 0NNN
+        Instruction::ScrollDown { .. }
+        | Instruction::ScrollRight
+        | Instruction::ScrollLeft
+        | Instruction::Exit
+        | Instruction::LowRes
+        | Instruction::HighRes
+        | Instruction::LoadBigFont { .. }
+        | Instruction::StoreFlags { .. }
+        | Instruction::LoadFlags { .. }
+            if !platform.supports_schip() => {}
+
+        // the XO-CHIP instructions are ignored on platforms without them
+        Instruction::ScrollUp { .. }
+        | Instruction::StoreRange { .. }
+        | Instruction::LoadRange { .. }
+        | Instruction::LoadLongI { .. }
+        | Instruction::Plane { .. }
+        | Instruction::Audio
+        | Instruction::Pitch { .. }
+            if !xochip => {}
+
+        // 00DN Scrolls the display up by N pixels
+        Instruction::ScrollUp { n } => {
+            draw_planes(framebuffer, *width, scale, *planes).scroll_up((n as u32 * scale) as usize);
+            *display_changed = true;
+        }
+
+        // 00CN Scrolls the display down by N pixels, N half pixels in low resolution on SUPER-CHIP
+        Instruction::ScrollDown { n } => {
+            draw_planes(framebuffer, *width, scale, *planes)
+                .scroll_down((n as u32 * scroll_scale) as usize);
+            *display_changed = true;
+        }
+
+        // 00FB Scrolls the display right by 4 pixels, 2 in low resolution on SUPER-CHIP
+        Instruction::ScrollRight => {
+            draw_planes(framebuffer, *width, scale, *planes)
+                .scroll_right((4 * scroll_scale) as usize);
+            *display_changed = true;
+        }
+
+        // 00FC Scrolls the display left by 4 pixels, 2 in low resolution on SUPER-CHIP
+        Instruction::ScrollLeft => {
+            draw_planes(framebuffer, *width, scale, *planes)
+                .scroll_left((4 * scroll_scale) as usize);
+            *display_changed = true;
+        }
+
+        // 00FD Exits the interpreter
+        Instruction::Exit => {
+            *exited = true;
+        }
+
+        // 00FE and 00FF switch between the low and high resolution displays, clearing the screen
+        Instruction::LowRes | Instruction::HighRes => {
+            *hires = instruction == Instruction::HighRes;
+            let mut draw = Draw::new(*width, framebuffer);
+            draw.set_planes(0x3);
+            draw.clear();
+            *display_changed = true;
+        }
+
+        // 00E0 - clears screen
+        Instruction::Cls => {
+            draw_planes(framebuffer, *width, scale, *planes).clear();
+            *display_changed = true;
+        }
+
+        // 00EE
+        // Return from a subroutine.
+        // The interpreter sets the program counter to the address at the top of the stack, then subtracts 1 from the stack pointer.
+        Instruction::Ret => {
+            // with the ignore policy a return from an empty stack carries on to the next instruction
+            match stack.pop() {
+                Some(current_address) => program_counter.jump(current_address),
+                None if stack.get_policy() == StackPolicy::Ignore => {}
+                None => return Err(Chip8Error::StackUnderflow { pc, opcode }),
             }
         }
 
         // 1NNN Jumps to address at NNN
         // The interpreter sets the program counter to nnn.
-        0x1 => {
-            let nnn = instruction & 0xFFF;
+        Instruction::Jump { nnn } => {
             program_counter.jump(nnn);
         }
 
         // 2NNN Calls subroutine at NNN
         // The interpreter increments the stack pointer, then puts the current PC on the top of the stack. The PC is then set to nnn.
-        0x2 => {
-            let pc = program_counter.get_pc() as u16;
-
-            // push current PC to top of stack, which increments the SP
-            stack.push(pc);
-
-            // set PC to NNN
-            let nnn = instruction & 0xFFF;
-
-            program_counter.jump(nnn)
+        Instruction::Call { nnn } => {
+            let return_address = program_counter.get_pc() as u16;
+
+            // push current PC to top of stack, which increments the SP, then set PC to NNN
+            // with the ignore policy a call with the stack full carries on to the next instruction
+            match stack.push(return_address) {
+                Ok(()) => program_counter.jump(nnn),
+                Err(_) if stack.get_policy() == StackPolicy::Ignore => {}
+                Err(_) => return Err(Chip8Error::StackOverflow { pc, opcode }),
+            }
         }
 
         // 3xnn - SE Vx, byte
         // Skip next instruction if Vx = nn.
         // The interpreter compares register Vx to kk, and if they are equal, increments the program counter by 2
-        0x3 => {
-            let nn = (instruction & 0xFF) as u8;
-            // compares
-            if nn == vx_value {
+        Instruction::SkipEqByte { x, nn } => {
+            if nn == vx(registers, x) {
                 // skip next instruction by incrementing PC by two
-                program_counter.increment_by(2);
+                skip(program_counter, memory);
             }
         }
 
         // 4XNN Skips the next instruction if VX does not equal NN (usually the next instruction is a jump to skip a code block).
-        0x4 => {
-            // get NN
-            let nn = (instruction & 0xFF) as u8;
-
-            // compares
-            if nn != vx_value {
-                // skip next instruction by incrementing PC by two
-                program_counter.increment_by(2);
+        Instruction::SkipNeByte { x, nn } => {
+            if nn != vx(registers, x) {
+                skip(program_counter, memory);
             }
         }
 
         // 5XY0 Skips the next instruction if VX equals VY (usually the next instruction is a jump to skip a code block).
         // The interpreter compares register Vx to register Vy, and if they are equal, increments the program counter by 2.
-        0x5 => {
-            if vx_value == vy_value {
-                program_counter.increment_by(2);
+        Instruction::SkipEqReg { x, y } => {
+            if vx(registers, x) == vx(registers, y) {
+                skip(program_counter, memory);
+            }
+        }
+
+        // 5XY2 Stores VX to VY (in either order) in memory, starting at address I. I is left unmodified.
+        Instruction::StoreRange { x, y } => {
+            let i = *registers.get_i_register() as usize;
+            let range = span(memory, i, register_range(x, y).count())?;
+            let active_memory = &mut memory.get_memory()[range];
+
+            for (offset, index) in register_range(x, y).enumerate() {
+                active_memory[offset] = vx(registers, index);
+            }
+        }
+
+        // 5XY3 Fills VX to VY (in either order) with values from memory, starting at address I. I is left unmodified.
+        Instruction::LoadRange { x, y } => {
+            let i = *registers.get_i_register() as usize;
+            let range = span(memory, i, register_range(x, y).count())?;
+            let active_memory = &memory.get_memory()[range];
+
+            for (offset, index) in register_range(x, y).enumerate() {
+                registers.set_register(index, active_memory[offset]);
             }
         }
 
         // 6XNN Sets VX to NN.
-        0x6 => {
-            let nn = ((instruction as usize) & 0xFF) as u8;
-            registers.set_register(vx_index, nn);
+        Instruction::LoadByte { x, nn } => {
+            registers.set_register(x, nn);
         }
 
         // 7XNN Adds NN to VX (carry flag is not changed).
-        0x7 => {
-            let nn = ((instruction as usize) & 0xFF) as u8;
-            let (sum, _carry) = nn.overflowing_add(vx_value);
-            // if carry {
-            //     registers.set_register(vx_index, 255);
-            // } else {
-                registers.set_register(vx_index, sum);
-            // }
-        }
-
-        0x8 => {
-            let instruction_0 = instruction & 0xF;
-            match instruction_0 {
-                0x0 => {
-                    // 8XY0 Sets VX to the value of VY.
-                    registers.set_register(vx_index, vy_value);
-                }
-                0x1 => {
-                    // 8XY1 Sets VX to VX or VY. (bitwise OR operation)
-                    registers.set_register(vx_index, vx_value | vy_value);
+        Instruction::AddByte { x, nn } => {
+            let (sum, _carry) = nn.overflowing_add(vx(registers, x));
+            registers.set_register(x, sum);
+        }
 
-                    // reset flag register to zero
-                    registers.set_register(0xF, 0);
-                }
+        // 8XY0 Sets VX to the value of VY.
+        Instruction::LoadReg { x, y } => {
+            registers.set_register(x, vx(registers, y));
+        }
 
-                0x2 => {
-                    // 8XY2 Sets VX to VX and VY. (bitwise AND operation)
-                    registers.set_register(vx_index, vx_value & vy_value);
-                
-                    // reset flag register to zero
-                    registers.set_register(0xF, 0);
-                }
+        // 8XY1 Sets VX to VX or VY. (bitwise OR operation)
+        Instruction::Or { x, y } => {
+            registers.set_register(x, vx(registers, x) | vx(registers, y));
 
-                0x3 => {
-                    // 8XY3 Sets VX to VX xor VY.
-                    registers.set_register(vx_index, vx_value ^ vy_value);
+            // reset flag register to zero
+            if quirks.vf_reset {
+                registers.set_register(0xF, 0);
+            }
+        }
 
-                    // reset flag register to zero
-                    registers.set_register(0xF, 0);
-                }
+        // 8XY2 Sets VX to VX and VY. (bitwise AND operation)
+        Instruction::And { x, y } => {
+            registers.set_register(x, vx(registers, x) & vx(registers, y));
 
-                0x4 => {
-                    // 8XY4 Adds VY to VX. VF is set to 1 when there's a carry, and to 0 when there is not.
-                    // fourth checkmark: if vF can be used as the vX input  
-                    let (sum, overflow) = vy_value.overflowing_add(vx_value);
-                    registers.set_register(vx_index, sum);
-
-                    if overflow {
-                        registers.set_register(0xF, 1);
-                    } else {
-                        registers.set_register(0xF, 0);
-                    }
-                }
+            // reset flag register to zero
+            if quirks.vf_reset {
+                registers.set_register(0xF, 0);
+            }
+        }
 
-                0x5 => {
-                    // 8XY5 VY is subtracted from VX. VF is set to 0 when there's a borrow, and 1 when there is not.
-                    let (diff, borrow) = vx_value.overflowing_sub(vy_value);
-                    registers.set_register(vx_index, diff);
-                    
-                    if borrow {
-                        registers.set_register(0xF, 0);
-                    } else {
-                        registers.set_register(0xF, 1);
-                    }
-                }
+        // 8XY3 Sets VX to VX xor VY.
+        Instruction::Xor { x, y } => {
+            registers.set_register(x, vx(registers, x) ^ vx(registers, y));
 
-                0x6 => {
-                    // 8XY6 Stores the least significant bit of VX in VF and then shifts VX to the right by 1.
-                    // failing on check if vF can be used as the vY input 
-                    registers.set_register(vx_index, vx_value >> 1);
-                    registers.set_register(0xF, vx_value & 1);
-                }
+            // reset flag register to zero
+            if quirks.vf_reset {
+                registers.set_register(0xF, 0);
+            }
+        }
 
-                0x7 => {
-                    // 8XY7 Sets VX to VY minus VX. VF is set to 0 when there's a borrow, and 1 when there is not
-                    let (diff, borrow) = vy_value.overflowing_sub(vx_value);
-                    registers.set_register(vx_index, diff);
+        // 8XY4 Adds VY to VX. VF is set to 1 when there's a carry, and to 0 when there is not.
+        Instruction::AddReg { x, y } => {
+            let (sum, overflow) = vx(registers, y).overflowing_add(vx(registers, x));
+            registers.set_register(x, sum);
+            registers.set_register(0xF, overflow as u8);
+        }
 
-                    if borrow {
-                        registers.set_register(0xF, 0);
-                    } else {
-                        registers.set_register(0xF, 1);
-                    }
-                }
+        // 8XY5 VY is subtracted from VX. VF is set to 0 when there's a borrow, and 1 when there is not.
+        Instruction::Sub { x, y } => {
+            let (diff, borrow) = vx(registers, x).overflowing_sub(vx(registers, y));
+            registers.set_register(x, diff);
+            registers.set_register(0xF, !borrow as u8);
+        }
 
-                0xE => {
-                    // 8XYE	Stores the most significant bit of VX in VF and then shifts VX to the left by 1
-                    registers.set_register(vx_index, vx_value << 1);
-                    registers.set_register(0xF, vx_value >> 7 & 1); 
-                }
-                _ => (),
-            }
+        // 8XY6 Stores the least significant bit of VX in VF and then shifts VX to the right by 1.
+        // With the shift quirk VY is shifted into VX instead, as on the COSMAC VIP.
+        Instruction::ShiftRight { x, y } => {
+            let vx_value = vx(registers, if quirks.shift_uses_vy { y } else { x });
+            registers.set_register(x, vx_value >> 1);
+            registers.set_register(0xF, vx_value & 1);
+        }
+
+        // 8XY7 Sets VX to VY minus VX. VF is set to 0 when there's a borrow, and 1 when there is not
+        Instruction::SubN { x, y } => {
+            let (diff, borrow) = vx(registers, y).overflowing_sub(vx(registers, x));
+            registers.set_register(x, diff);
+            registers.set_register(0xF, !borrow as u8);
+        }
+
+        // 8XYE Stores the most significant bit of VX in VF and then shifts VX to the left by 1
+        // With the shift quirk VY is shifted into VX instead, as on the COSMAC VIP.
+        Instruction::ShiftLeft { x, y } => {
+            let vx_value = vx(registers, if quirks.shift_uses_vy { y } else { x });
+            registers.set_register(x, vx_value << 1);
+            registers.set_register(0xF, vx_value >> 7 & 1);
         }
 
         // 9XY0 Skips the next instruction if VX does not equal VY. (Usually the next instruction is a jump to skip a code block);
-        0x9 => {
-            if vx_value != vy_value {
-                program_counter.increment_by(2);
+        Instruction::SkipNeReg { x, y } => {
+            if vx(registers, x) != vx(registers, y) {
+                skip(program_counter, memory);
             }
         }
 
         // ANNN Sets I to the address NNN.
-        0xA => {
-            let nnn = instruction & 0xFFF;
+        Instruction::LoadI { nnn } => {
             registers.set_i_register(nnn);
         }
 
         // BNNN Jumps to the address NNN plus V0.
-        0xB => {
-            let nnn = instruction & 0xFFF;
-            program_counter.jump(nnn + *registers.get_register(0).unwrap() as u16);
+        // With the jump quirk BXNN jumps to XNN plus VX, as on CHIP-48 and SUPER-CHIP.
+        Instruction::JumpV0 { nnn } => {
+            let offset_register = if quirks.jump_uses_vx {
+                (nnn >> 8) as u8
+            } else {
+                0
+            };
+            program_counter.jump(nnn + vx(registers, offset_register) as u16);
         }
 
         // CXNN Sets VX to the result of a bitwise and operation on a random number (Typically: 0 to 255) and NN.
-        0xC => {
-            let nn = (instruction & 0xFF) as u8;
-            let random_number = rand::random::<u8>();
-            let x = random_number & nn;
-            registers.set_register(vx_index, x);
+        Instruction::Random { x, nn } => {
+            let random_number = rng.random_byte(*frame_count);
+            registers.set_register(x, random_number & nn);
         }
 
         // DXYN Draws a sprite at coordinate (VX, VY) that has a width of 8 pixels and a height of N pixels. Each row of 8 pixels is read as bit-coded starting from memory location I; I value does not change after the execution of this instruction. As described above, VF is set to 1 if any screen pixels are flipped from set to unset when the sprite is drawn, and to 0 if that does not happen.
-        0xD => {
-            let height = (instruction & 0xF) as u8;
-            let length = 8 * height as usize;
-            let location_u16 = *registers.get_i_register();
-            let location = location_u16 as usize;
-            let pixels = &active_memory[location..location + length];
+        // DXY0 Draws a 16 x 16 sprite on SUPER-CHIP, 32 bytes read two per row
+        Instruction::Draw { x, y, n } => {
+            let location = *registers.get_i_register() as usize;
+            let wide = n == 0 && platform.supports_schip();
+            let mut draw = draw_planes(framebuffer, *width, scale, *planes);
+            let length = if wide {
+                draw.sprite_length(16, 2)
+            } else {
+                draw.sprite_length(n as usize, 1)
+            };
+            let range = span(memory, location, length)?;
+            let sprite = &memory.get_memory()[range];
             let dest = &Point {
-                x: (vx_value % 64) as usize,
-                y: (vy_value % 32) as usize,
+                x: (vx(registers, x) as u32 % (*width / scale)) as usize,
+                y: (vx(registers, y) as u32 % (*height / scale)) as usize,
             };
-            registers.set_register(0xF, 0);
 
-            let set_flag_register = draw.blit_raw(pixels, dest, height);
-
-            if set_flag_register {
-                registers.set_register(0xF, 1);
+            let set_flag_register = if wide {
+                draw.blit_wide(sprite, dest, quirks.wrap_sprites)
             } else {
-                registers.set_register(0xF, 0);
+                draw.blit_raw(sprite, dest, n, quirks.wrap_sprites)
+            };
+            registers.set_register(0xF, set_flag_register as u8);
+
+            *display_changed = true;
+
+            // with the display wait quirk nothing more runs until the next frame
+            if quirks.display_wait {
+                *waiting_for_frame = true;
             }
+        }
 
-            render = true;
+        // EX9E Skips the next instruction if the key stored in VX is pressed (usually the next instruction is a jump to skip a code block).
+        Instruction::SkipKey { x } => {
+            if keypad.is_pressed(vx(registers, x)) {
+                skip(program_counter, memory);
+            }
+        }
 
+        // EXA1 Skips the next instruction if the key stored in VX is not pressed (usually the next instruction is a jump to skip a code block).
+        Instruction::SkipNotKey { x } => {
+            if !keypad.is_pressed(vx(registers, x)) {
+                skip(program_counter, memory);
+            }
         }
 
-        0xE => {
-            // let stored_key = vx_value;
-            match instruction & 0xFF {
-                // EX9E Skips the next instruction if the key stored in VX is pressed (usually the next instruction is a jump to skip a code block).
-                0x9E => {
-                    match key_state.current_key {
-                        Some(value) => {
-                            if value as u8 == vx_value {
-                                program_counter.increment_by(2);
-                            }
-                        }
-                        None => {}
-                    }
-                }
-                // EXA1 Skips the next instruction if the key stored in VX is not pressed (usually the next instruction is a jump to skip a code block).
-                0xA1 => {
-                    match key_state.current_key {
-                        Some(value) => {
-                            if value as u8 != vx_value {
-                                program_counter.increment_by(2);
-                            }
-
-                        }
-                        None => {
-                            program_counter.increment_by(2);
-                        }
-                    }
-                }
-                _ => {}
+        // F000 NNNN Sets I to the 16 bit address NNNN, read from the two bytes after the instruction.
+        Instruction::LoadLongI { nnnn } => {
+            registers.set_i_register(nnnn);
+        }
+
+        // FN01 Selects the bit planes drawing, clearing and scrolling act on.
+        Instruction::Plane { n } => {
+            *planes = n & 0x3;
+        }
+
+        // F002 Loads the 16 byte audio pattern from memory, starting at address I.
+        Instruction::Audio => {
+            let i = *registers.get_i_register() as usize;
+            let range = span(memory, i, audio_pattern.len())?;
+            audio_pattern.copy_from_slice(&memory.get_memory()[range]);
+        }
+
+        // FX3A Sets the pitch the audio pattern is played at to VX.
+        Instruction::Pitch { x } => {
+            *pitch = vx(registers, x);
+        }
+
+        // FX07 Sets VX to the value of the delay timer.
+        Instruction::LoadDelay { x } => {
+            let dt = *registers.get_delay_timer();
+            registers.set_register(x, dt);
+        }
+
+        // FX0A A key press is awaited, and then stored in VX (blocking operation, all instruction halted until next key event).
+        // As on the COSMAC VIP the key is only stored once it has been released again.
+        Instruction::WaitKey { x } => match keypad.wait_for_key() {
+            Some(key) => {
+                registers.set_register(x, key);
             }
+            None => {
+                program_counter.decrement()?;
+                program_counter.decrement()?;
+            }
+        },
+
+        // FX15 Sets the delay timer to VX.
+        Instruction::SetDelay { x } => {
+            registers.set_delay_timer(vx(registers, x));
         }
 
-        0xF => {
-            match instruction & 0xFF {
-                // FX07	Sets VX to the value of the delay timer.
-                0x07 => {
-                    registers.set_register(vx_index, dt);
-                }
-                // FX0A	A key press is awaited, and then stored in VX (blocking operation, all instruction halted until next key event).
-                0x0A => {
-                    match key_state.state {
-                        Some(ElementState::Released) => {
-                            match key_state.current_key {
-                                Some(value) => {
-                                    registers.set_register(vx_index, value as u8);
-                                }
-                                _ => {
-                                    program_counter.decrement();
-                                    program_counter.decrement();
-                                },
-                            }
-                        },
-                        _ => {}
-                    } 
-                }
-                // FX15	Sets the delay timer to VX.
-                0x15 => {
-                    registers.set_delay_timer(vx_value);
-                }
-                // FX18	Sets the sound timer to VX.
-                0x18 => {
-                    // TODO: implement sound
-                }
-                // FX1E	Adds VX to I. VF is not affected.[c]
-                0x1E => {
-                    registers.set_i_register(i + vx_value as u16);
-                }
-                // FX29	Sets I to the location of the sprite for the character in VX. Characters 0-F (in hexadecimal) are represented by a 4x5 font.
-                0x29 => {
-                    let character_sprite_location = vx_value * 5;
-                    registers.set_i_register(character_sprite_location as u16);
-                }
-                // FX33	Stores the binary-coded decimal representation of VX, with the hundreds digit in memory at location in I, the tens digit at location I+1, and the ones digit at location I+2.
-                0x33 => { 
-                    let vx_hundreds = vx_value / 100;
-                    let vx_tens = (vx_value / 10) % 10;
-                    let vx_ones = vx_value % 10;
+        // FX18 Sets the sound timer to VX.
+        Instruction::SetSound { x } => {
+            sound_timer.set_timer(vx(registers, x));
+        }
 
-                    let i = *registers.get_i_register() as usize;
+        // FX1E Adds VX to I. VF is not affected.
+        Instruction::AddI { x } => {
+            let i = *registers.get_i_register();
+            registers.set_i_register(i.wrapping_add(vx(registers, x) as u16));
+        }
 
-                    active_memory[i] = vx_hundreds;
-                    active_memory[i+1] = vx_tens;
-                    active_memory[i+2] = vx_ones;
-                }
-                    ,
-                // FX55	Stores from V0 to VX (including VX) in memory, starting at address I. The offset from I is increased by 1 for each value written, but I itself is left unmodified.[d]
-                0x55 => {
-                    for value in 0..vx_index + 1 {
-                        let reg_value = *registers.get_register(value).unwrap();
-                        let i = *registers.get_i_register() as usize;
-
-                        active_memory[i + value as usize] = reg_value;
-
-                        // incrementing i register for older games
-                        // registers.set_i_register((i + value as usize) as u16);
-                    }
+        // FX29 Sets I to the location of the sprite for the character in VX. Characters 0-F (in hexadecimal) are represented by a 4x5 font.
+        Instruction::LoadFont { x } => {
+            let character_sprite_location = FONT_START as u16 + (vx(registers, x) & 0xF) as u16 * 5;
+            registers.set_i_register(character_sprite_location);
+        }
+
+        // FX30 Sets I to the location of the 8x10 sprite for the digit in VX.
+        Instruction::LoadBigFont { x } => {
+            let character_sprite_location =
+                BIG_FONT_START as u16 + (vx(registers, x) & 0xF) as u16 * 10;
+            registers.set_i_register(character_sprite_location);
+        }
+
+        // FX33 Stores the binary-coded decimal representation of VX, with the hundreds digit in memory at location in I, the tens digit at location I+1, and the ones digit at location I+2.
+        Instruction::Bcd { x } => {
+            let vx_value = vx(registers, x);
+            let i = *registers.get_i_register() as usize;
+            let range = span(memory, i, 3)?;
+            let active_memory = &mut memory.get_memory()[range];
+
+            active_memory[0] = vx_value / 100;
+            active_memory[1] = (vx_value / 10) % 10;
+            active_memory[2] = vx_value % 10;
+        }
+
+        // FX55 Stores from V0 to VX (including VX) in memory, starting at address I. The offset from I is increased by 1 for each value written, but I itself is left unmodified.
+        Instruction::Store { x } => {
+            let i = *registers.get_i_register() as usize;
+            let range = span(memory, i, x as usize + 1)?;
+            let active_memory = &mut memory.get_memory()[range];
+
+            for index in 0..=x {
+                active_memory[index as usize] = vx(registers, index);
+            }
+
+            // with the memory quirk I is left pointing after the last register, as on the COSMAC VIP,
+            // and CHIP-48 leaves it one short of that
+            match quirks.memory_increment {
+                MemoryIncrement::None => {}
+                MemoryIncrement::ByX => registers.set_i_register((i + x as usize) as u16),
+                MemoryIncrement::ByXPlusOne => {
+                    registers.set_i_register((i + x as usize + 1) as u16)
                 }
-                // FX65	Fills from V0 to VX (including VX) with values from memory, starting at address I. The offset from I is increased by 1 for each value read, but I itself is left unmodified.[d]
-                0x65 => {
-                    for value in 0..vx_index + 1 {
-                        let i = *registers.get_i_register() as usize;
+            }
+        }
 
-                        let mem_value = active_memory[i + value as usize];
+        // FX65 Fills from V0 to VX (including VX) with values from memory, starting at address I. The offset from I is increased by 1 for each value read, but I itself is left unmodified.
+        Instruction::Load { x } => {
+            let i = *registers.get_i_register() as usize;
+            let range = span(memory, i, x as usize + 1)?;
+            let active_memory = &memory.get_memory()[range];
 
-                        registers.set_register(value, mem_value);
+            for index in 0..=x {
+                registers.set_register(index, active_memory[index as usize]);
+            }
 
-                        // incrementing i register for older games
-                        // registers.set_i_register((i + value as usize) as u16);
-                    }
+            // with the memory quirk I is left pointing after the last register, as on the COSMAC VIP,
+            // and CHIP-48 leaves it one short of that
+            match quirks.memory_increment {
+                MemoryIncrement::None => {}
+                MemoryIncrement::ByX => registers.set_i_register((i + x as usize) as u16),
+                MemoryIncrement::ByXPlusOne => {
+                    registers.set_i_register((i + x as usize + 1) as u16)
                 }
-                _ => (),
             }
         }
-        _ => (),
-    }
 
-    if render {
-        pixels.render().unwrap();
-    }
-}
+        // FX75 Stores V0 to VX in the RPL user flags, which are saved to disk when a file is set.
+        Instruction::StoreFlags { x } => {
+            for index in 0..=x {
+                rpl_flags[index as usize] = vx(registers, index);
+            }
 
-pub fn _decode(_command: bool) -> bool {
-    false
-}
+            if let Some(path) = rpl_file {
+                if let Err(err) = fs::write(&*path, &rpl_flags[..]) {
+                    log::warn!("could not save the flags to {}: {err}", path.display());
+                }
+            }
+        }
 
-/* Fetches the program instruction from the chip8 Rom */
-pub fn fetch(
-    rom: &mut [u8],
-    program_counter: &mut ProgramCounter,
-    rom_length: usize,
-) -> Option<u16> {
-    if program_counter.get_pc() < rom_length - 1 + 512 {
-        let instruction1 = *rom.get(program_counter.get_pc()).unwrap() as u16;
-        program_counter.increment();
-        let instruction2 = *rom.get(program_counter.get_pc()).unwrap() as u16;
-        program_counter.increment();
-
-        let instruction: u16 = (instruction1 << 8) | instruction2;
-        Some(instruction)
-    } else {
-        None
+        // FX85 Fills V0 to VX from the RPL user flags.
+        Instruction::LoadFlags { x } => {
+            for index in 0..=x {
+                registers.set_register(index, rpl_flags[index as usize]);
+            }
+        }
     }
+
+    Ok(())
 }
 
-/* Fetches and formats the program instruction from the chip8 Rom */
-pub fn fetch_instruction(
-    memory: &mut [u8],
-    program_counter: &mut ProgramCounter,
-    rom_length: usize,
-) -> u16 {
-    match fetch(memory, program_counter, rom_length) {
-        Some(instruction) => {
-            instruction
-        }
-        _ => 0x0,
+/**
+ * Fetches the instruction at the program counter and moves past it. Anywhere in memory can be
+ * run, not just the rom, but both bytes of the instruction must be inside memory.
+ */
+pub fn fetch(memory: &[u8], program_counter: &mut ProgramCounter) -> Result<u16, Chip8Error> {
+    let pc = program_counter.get_pc();
+    match (memory.get(pc), memory.get(pc + 1)) {
+        (Some(instruction1), Some(instruction2)) => {
+            program_counter.increment_by(2);
+            Ok((*instruction1 as u16) << 8 | *instruction2 as u16)
+        }
+        _ => Err(Chip8Error::ProgramCounterOutOfBounds { pc: pc as u16 }),
     }
 }
 
-pub fn match_key(key_scancode: u32) -> Option<u32>{
-    match key_scancode {
-        // Key 1 - 18: 0x1
-        18 => {
-            Some(0x1)
-        }
-        // Key 2 - 19: 0x2
-        19 => {
-            Some(0x2)
-        }
-        // Key 3 - 20: 0x3
-        20 => {
-            Some(0x3)
-        }
-        // Key 4 - 21: 0xC
-        21 => {
-            Some(0xC)
-        }
-        // Key Q - 12: 0x4
-        12 => {
-            Some(0x4)
-        }
-        // Key W - 13: 0x5
-        13 => {
-            Some(0x5)
-        }
-        // Key E - 14: 0x6
-        14 => {
-            Some(0x6)
-        }
-        // Key R - 15: 0xD
-        15 => {
-            Some(0xD)
-        }
-        // Key A - 0:  0x7
-        0 => {
-            Some(0x7)
-        }
-        // Key S - 1:  0x8
-        1 => {
-            Some(0x8)
-        }
-        // Key D - 2:  0x9
-        2 => {
-            Some(0x9)
-        }
-        // Key F - 3:  0xE
-        3 => {
-            Some(0xE)
-        }
-        // Key Z - 6:  0xA
-        6 => {
-            Some(0xA)
-        }
-        // Key X - 7:  0x0
-        7 => {
-            Some(0x0)
-        }
-        // Key C - 8:  0xB
-        8 => {
-            Some(0xB)
-        }
-        // Key V - 9:  0xF
-        9 => {
-            Some(0xF)
-        }
-        _ => {
-            None
-        }
+// the registers from X to Y, counting down when Y is below X
+fn register_range(x: u8, y: u8) -> Box<dyn Iterator<Item = u8>> {
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
     }
 }
 
-#[derive(Debug)]
-pub struct KeyPress {
-    pub current_key: Option<ScanCode>,
-    pub state: Option<ElementState>,
+// draws, clears and scrolls the bit planes selected by FN01
+fn draw_planes(framebuffer: &mut [u8], width: u32, scale: u32, planes: u8) -> Draw<'_> {
+    let mut draw = Draw::with_scale(width, scale, framebuffer);
+    draw.set_planes(planes);
+    draw
 }
@@ -0,0 +1,144 @@
+use std::{error::Error, fmt};
+
+use crate::{debugger::describe_machine, machine::Machine};
+
+/*
+ * Chip8Error
+ * the ways a program can go wrong. Errors raised by an instruction name the address it was
+ * fetched from and its opcode, and the program counter is left on that instruction.
+ *
+ * Only a running program raises these. Bad input from outside of one, such as a corrupt save state
+ * for load_state or an address given to Memory::poke or Stack::set_sp by a debugger, is reported
+ * with a String like the rom and movie loaders do, as there is no instruction to name.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8Error {
+    // 2NNN called a subroutine with every stack entry in use
+    StackOverflow {
+        pc: u16,
+        opcode: u16,
+    },
+    // 00EE returned with nothing on the stack
+    StackUnderflow {
+        pc: u16,
+        opcode: u16,
+    },
+    // the instruction read or wrote an address past the end of memory
+    MemoryOutOfBounds {
+        pc: u16,
+        opcode: u16,
+        address: usize,
+    },
+    UnknownOpcode {
+        pc: u16,
+        opcode: u16,
+    },
+    // the program counter moved outside of memory. pc is its value when that was found: past the
+    // end of memory when an instruction was fetched there, or 0 when FX0A moved it back from 0
+    ProgramCounterOutOfBounds {
+        pc: u16,
+    },
+    // the rom does not fit in the program space from 0x200 to the end of memory
+    RomTooLarge {
+        size: usize,
+        space: usize,
+    },
+}
+impl Chip8Error {
+    /** The address of the instruction that failed, when an instruction failed. */
+    pub fn pc(&self) -> Option<u16> {
+        match self {
+            Chip8Error::StackOverflow { pc, .. }
+            | Chip8Error::StackUnderflow { pc, .. }
+            | Chip8Error::MemoryOutOfBounds { pc, .. }
+            | Chip8Error::UnknownOpcode { pc, .. }
+            | Chip8Error::ProgramCounterOutOfBounds { pc } => Some(*pc),
+            Chip8Error::RomTooLarge { .. } => None,
+        }
+    }
+}
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::StackOverflow { pc, opcode } => {
+                write!(f, "stack overflow at {pc:03X} ({opcode:04X})")
+            }
+            Chip8Error::StackUnderflow { pc, opcode } => {
+                write!(f, "stack underflow at {pc:03X} ({opcode:04X})")
+            }
+            Chip8Error::MemoryOutOfBounds {
+                pc,
+                opcode,
+                address,
+            } => write!(
+                f,
+                "memory access out of bounds at {pc:03X} ({opcode:04X}): address {address:03X}"
+            ),
+            Chip8Error::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode {opcode:04X} at {pc:03X}")
+            }
+            Chip8Error::ProgramCounterOutOfBounds { pc } => {
+                write!(f, "the program counter left memory at {pc:03X}")
+            }
+            Chip8Error::RomTooLarge { size, space } => write!(
+                f,
+                "the rom is {size} bytes but there is only room for {space}"
+            ),
+        }
+    }
+}
+impl Error for Chip8Error {}
+impl From<Chip8Error> for String {
+    fn from(error: Chip8Error) -> String {
+        error.to_string()
+    }
+}
+
+/** Describes an error and the state of the machine it left behind, for printing when a program crashes. */
+pub fn crash_report(error: &Chip8Error, machine: &Machine) -> String {
+    format!(
+        "The program crashed: {error}\n{}",
+        describe_machine(machine)
+    )
+}
+
+#[cfg(test)]
+mod error_tests {
+    use super::*;
+
+    #[test]
+    fn names_the_failing_instruction() {
+        // 00EE returns with nothing on the stack
+        let mut machine = Machine::new(64, 32, &[0x00, 0xE0, 0x00, 0xEE]).unwrap();
+        machine.step().unwrap();
+        let error = machine.step().unwrap_err();
+        assert_eq!(
+            error,
+            Chip8Error::StackUnderflow {
+                pc: 0x202,
+                opcode: 0x00EE
+            }
+        );
+        assert_eq!(machine.program_counter().get_pc(), 0x202);
+        assert!(crash_report(&error, &machine)
+            .starts_with("The program crashed: stack underflow at 202 (00EE)\n"));
+
+        // code past the end of the rom runs, until the program counter leaves memory
+        let mut machine = Machine::new(64, 32, &[0x1F, 0xFE]).unwrap();
+        machine.memory_mut().get_memory()[0xFFE..].copy_from_slice(&[0x00, 0xE0]);
+        machine.step().unwrap();
+        machine.step().unwrap();
+        assert_eq!(
+            machine.step(),
+            Err(Chip8Error::ProgramCounterOutOfBounds { pc: 0x1000 })
+        );
+
+        assert_eq!(
+            Machine::new(64, 32, &[0; 4000]).err(),
+            Some(Chip8Error::RomTooLarge {
+                size: 4000,
+                space: 3584
+            })
+        );
+    }
+}
@@ -4,6 +4,10 @@ pub const CHAR_SET: [u8; 16] = [
     0x0, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0x8, 0x9, 0xA, 0xB, 0xC, 0xD, 0xE, 0xF,
 ];
 
+// the small font is loaded at 0x000, the big SUPER-CHIP font follows it
+pub const FONT_START: usize = 0x000;
+pub const BIG_FONT_START: usize = 0x050;
+
 pub fn get_character_set() -> HashMap<u8, [u8; 5]> {
     HashMap::from([
         (0x0, [0xF0, 0x90, 0x90, 0x90, 0xF0]),
@@ -24,3 +28,73 @@ pub fn get_character_set() -> HashMap<u8, [u8; 5]> {
         (0xF, [0xF0, 0x80, 0xF0, 0x80, 0x80]),
     ])
 }
+
+// the SUPER-CHIP 1.1 big font, 8 x 10 pixels. SUPER-CHIP only had the digits, A to F are from Octo
+pub fn get_big_character_set() -> HashMap<u8, [u8; 10]> {
+    HashMap::from([
+        (
+            0x0,
+            [0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF],
+        ),
+        (
+            0x1,
+            [0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF],
+        ),
+        (
+            0x2,
+            [0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF],
+        ),
+        (
+            0x3,
+            [0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+        ),
+        (
+            0x4,
+            [0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03],
+        ),
+        (
+            0x5,
+            [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+        ),
+        (
+            0x6,
+            [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF],
+        ),
+        (
+            0x7,
+            [0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18],
+        ),
+        (
+            0x8,
+            [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF],
+        ),
+        (
+            0x9,
+            [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+        ),
+        (
+            0xA,
+            [0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3],
+        ),
+        (
+            0xB,
+            [0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC],
+        ),
+        (
+            0xC,
+            [0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C],
+        ),
+        (
+            0xD,
+            [0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC],
+        ),
+        (
+            0xE,
+            [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF],
+        ),
+        (
+            0xF,
+            [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0],
+        ),
+    ])
+}
@@ -0,0 +1,371 @@
+use std::{
+    io::{self, BufRead},
+    path::PathBuf,
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::Instant,
+};
+
+use winit::{
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+};
+
+use chip8::{
+    debugger::{describe_machine, StopReason},
+    error::crash_report,
+    gdb::GdbStub,
+    Debugger, Machine, Movie, MoviePlayer, Rewind, SaveSlots, Scheduler, FRAMES_PER_SECOND,
+};
+
+use crate::{
+    display::{build_pixels, build_window, draw_framebuffer, draw_keypad},
+    keyboard::{KeyMap, SHOW_MAPPING_KEY},
+};
+
+// the window is 1280 pixels wide whatever the size of the chip8 display
+const WINDOW_WIDTH: u32 = 1280;
+
+// how much page up and page down change the speed by, in instructions per frame
+const SPEED_STEP: u32 = 2;
+
+// how far back holding backspace can rewind, in seconds
+const REWIND_SECONDS: u32 = 30;
+
+/* Whether the keypad is being recorded to a movie or played back from one */
+pub enum MovieMode {
+    Off,
+    // written to the path when the program exits or the window is closed
+    Record { movie: Movie, path: PathBuf },
+    Replay(MoviePlayer),
+}
+impl MovieMode {
+    pub fn is_on(&self) -> bool {
+        !matches!(self, MovieMode::Off)
+    }
+
+    // writes out a recording, the movie ends at the frame the machine has reached
+    fn finish(&mut self, machine: &Machine) {
+        if let MovieMode::Record { movie, path } = self {
+            movie.set_length(machine.frame_count());
+            match movie.write(&path) {
+                Ok(()) => println!("Recorded {} frames to {}", movie.length, path.display()),
+                Err(err) => println!("Problem writing the movie: {err}"),
+            }
+        }
+        *self = MovieMode::Off;
+    }
+}
+
+/* How the machine is debugged, if at all */
+pub enum Debugging {
+    Off,
+    // commands typed at the terminal
+    Terminal(Debugger),
+    // a debugger attached over TCP, see gdb.rs
+    Gdb(GdbStub),
+}
+
+/*
+ * chip8
+ * runs the machine in a window until it exits or the window is closed
+ * page up / page down change the speed, F1 to F4 pick a save slot, F5 saves to it and F9 loads it
+ * holding backspace plays the game backwards
+ * while a movie is recorded or replayed, rewinding, loading states and the speed keys are turned
+ * off so the movie stays in step with the machine, and the keyboard is ignored during a replay
+ * with a debugger, commands typed at the terminal or sent by gdb control the machine while the
+ * window runs
+ * the keyboard is mapped to the keypad by the key map, F12 shows the keypad over the display and
+ * prints the mapping
+ */
+pub fn chip8(
+    mut machine: Machine,
+    save_slots: SaveSlots,
+    mut movie_mode: MovieMode,
+    mut debugging: Debugging,
+    key_map: KeyMap,
+) {
+    let event_loop = EventLoop::new();
+    let scale = WINDOW_WIDTH / machine.width();
+    let window = build_window(
+        machine.width() * scale,
+        machine.height() * scale,
+        &event_loop,
+    );
+    let mut pixels = build_pixels(&window, machine.width(), machine.height()).unwrap();
+
+    let mut scheduler = Scheduler::new(FRAMES_PER_SECOND);
+    let mut slot = 1;
+    let mut rewind = Rewind::new((REWIND_SECONDS * FRAMES_PER_SECOND) as usize);
+    let mut rewinding = false;
+    let mut show_mapping = false;
+    let commands = matches!(debugging, Debugging::Terminal(_)).then(read_commands);
+    if commands.is_some() {
+        println!("The debugger has paused the program, type help for commands");
+        println!("{}", describe_machine(&machine));
+    }
+
+    // main event loop
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::WaitUntil(scheduler.next_frame());
+
+        match event {
+            // Event::MainEventsCleared case signifies that all the events which were available up to the point of the last call to the event handler have been processed and the event loop is ready to proceed to the next phase of the loop's body.
+            Event::MainEventsCleared => {
+                match (&mut debugging, &commands) {
+                    (Debugging::Terminal(debugger), Some(commands)) => {
+                        for line in commands.try_iter() {
+                            match debugger.execute(&machine, &line) {
+                                Ok(output) if output.is_empty() => {}
+                                Ok(output) => println!("{output}"),
+                                Err(err) => println!("{err}"),
+                            }
+                        }
+                    }
+                    (Debugging::Gdb(stub), _) => stub.poll(&mut machine),
+                    _ => {}
+                }
+
+                // run every frame that is due, then present the display once
+                let mut crash = None;
+                for _ in 0..scheduler.frames_due(Instant::now()) {
+                    if let MovieMode::Replay(player) = &mut movie_mode {
+                        if player.is_finished(&machine) {
+                            println!("The movie has finished, the keyboard is back");
+                            movie_mode = MovieMode::Off;
+                        } else {
+                            player.apply_inputs(&mut machine);
+                        }
+                    }
+
+                    if rewinding && !movie_mode.is_on() {
+                        if let Some(state) = rewind.pop() {
+                            // a state that can not be loaded makes the rest of the history useless
+                            if let Err(err) = machine.load_state(&state) {
+                                println!("Problem rewinding: {err}");
+                                rewind.clear();
+                            }
+                        }
+                        continue;
+                    }
+
+                    // the debuggers stop on errors, without one the program crashes
+                    let ran = match &mut debugging {
+                        Debugging::Terminal(debugger) if debugger.is_paused() => break,
+                        Debugging::Terminal(debugger) => {
+                            if let Some(reason) = debugger.run_frame(&mut machine) {
+                                print_stop(&machine, &reason);
+                            }
+                            Ok(())
+                        }
+                        Debugging::Gdb(stub) if stub.is_paused() => break,
+                        Debugging::Gdb(stub) => stub.run_frame(&mut machine),
+                        Debugging::Off => machine.run_frame(),
+                    };
+                    if let Err(error) = ran {
+                        crash = Some(error);
+                        break;
+                    }
+                    rewind.push(&machine.save_state());
+                    if machine.has_exited() {
+                        break;
+                    }
+                }
+                *control_flow = ControlFlow::WaitUntil(scheduler.next_frame());
+
+                if let Some(error) = crash {
+                    println!("{}", crash_report(&error, &machine));
+                    movie_mode.finish(&machine);
+                    machine.set_tracer(None);
+                    machine.close_audio_sink();
+                    control_flow.set_exit_with_code(1);
+                    return;
+                }
+
+                if machine.has_exited() {
+                    println!("The program exited; stopping");
+                    movie_mode.finish(&machine);
+                    // the event loop exits without dropping the machine, so the trace and the wav file
+                    // are finished here
+                    machine.set_tracer(None);
+                    machine.close_audio_sink();
+                    control_flow.set_exit();
+                    return;
+                }
+
+                // the overlay is drawn every frame, to light up the keys as they are pressed
+                if machine.take_display_changed() || show_mapping {
+                    draw_framebuffer(machine.framebuffer(), pixels.frame_mut());
+                    if show_mapping {
+                        draw_keypad(machine.keypad(), machine.width(), pixels.frame_mut());
+                    }
+                    pixels.render().unwrap();
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                ..
+            } => {
+                println!("The close button was pressed; stopping");
+                movie_mode.finish(&machine);
+                machine.set_tracer(None);
+                machine.close_audio_sink();
+                control_flow.set_exit();
+            }
+            Event::RedrawRequested(_) => {
+                pixels.render().unwrap();
+            }
+            // page up and page down change the speed of the machine
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode:
+                                    Some(
+                                        virtual_keycode @ (VirtualKeyCode::PageUp
+                                        | VirtualKeyCode::PageDown),
+                                    ),
+                                ..
+                            },
+                        ..
+                    },
+                window_id,
+            } if window_id == window.id() => {
+                if movie_mode.is_on() {
+                    println!("The speed can not change while a movie is recorded or replayed");
+                    return;
+                }
+                let speed = machine.instructions_per_frame();
+                let speed = if virtual_keycode == VirtualKeyCode::PageUp {
+                    speed.saturating_add(SPEED_STEP)
+                } else {
+                    speed.saturating_sub(SPEED_STEP)
+                };
+                machine.set_instructions_per_frame(speed);
+                println!(
+                    "Running {} instructions per frame",
+                    machine.instructions_per_frame()
+                );
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state,
+                                virtual_keycode: Some(VirtualKeyCode::Back),
+                                ..
+                            },
+                        ..
+                    },
+                window_id,
+            } if window_id == window.id() => {
+                rewinding = state == ElementState::Pressed;
+            }
+            // save states
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode:
+                                    Some(
+                                        virtual_keycode @ (VirtualKeyCode::F1
+                                        | VirtualKeyCode::F2
+                                        | VirtualKeyCode::F3
+                                        | VirtualKeyCode::F4
+                                        | VirtualKeyCode::F5
+                                        | VirtualKeyCode::F9),
+                                    ),
+                                ..
+                            },
+                        ..
+                    },
+                window_id,
+            } if window_id == window.id() => match virtual_keycode {
+                VirtualKeyCode::F5 => match save_slots.save(&machine, slot) {
+                    Ok(()) => println!("Saved slot {slot}"),
+                    Err(err) => println!("Problem saving slot {slot}: {err}"),
+                },
+                VirtualKeyCode::F9 if movie_mode.is_on() => {
+                    println!("States can not be loaded while a movie is recorded or replayed");
+                }
+                VirtualKeyCode::F9 => match save_slots.load(&mut machine, slot) {
+                    Ok(()) => {
+                        // the history leads up to the state before the load
+                        rewind.clear();
+                        println!("Loaded slot {slot}");
+                    }
+                    Err(err) => println!("Problem loading slot {slot}: {err}"),
+                },
+                _ => {
+                    slot = virtual_keycode as u8 - VirtualKeyCode::F1 as u8 + 1;
+                    println!("Save slot {slot}");
+                }
+            },
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state,
+                                virtual_keycode: Some(virtual_keycode),
+                                ..
+                            },
+                        ..
+                    },
+                window_id,
+            } if window_id == window.id() => {
+                if virtual_keycode == SHOW_MAPPING_KEY {
+                    if state == ElementState::Pressed {
+                        show_mapping = !show_mapping;
+                        if show_mapping {
+                            println!("{}", key_map.describe());
+                        } else {
+                            // puts back the display the overlay was drawn over
+                            draw_framebuffer(machine.framebuffer(), pixels.frame_mut());
+                            pixels.render().unwrap();
+                        }
+                    }
+                    return;
+                }
+                if let Some(key) = key_map.match_key(virtual_keycode) {
+                    let pressed = state == ElementState::Pressed;
+                    match &mut movie_mode {
+                        MovieMode::Replay(_) => {}
+                        MovieMode::Record { movie, .. } => {
+                            movie.record(machine.frame_count(), key, pressed);
+                            machine.set_key(key, pressed);
+                        }
+                        MovieMode::Off => machine.set_key(key, pressed),
+                    }
+                }
+            }
+            _ => {}
+        }
+    });
+}
+
+// reads the lines typed at the terminal on a thread of their own, so the window keeps running
+fn read_commands() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn print_stop(machine: &Machine, reason: &StopReason) {
+    if *reason != StopReason::Step {
+        println!("Stopped: {reason}");
+    }
+    println!("{}", describe_machine(machine));
+}
@@ -0,0 +1,598 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use crate::{
+    debugger::{Breakpoint, Debugger, Register, StopReason, Watchpoint},
+    error::Chip8Error,
+    machine::Machine,
+};
+
+/*
+ * GDB remote serial protocol
+ * a stub that lets gdb, or anything else that speaks the protocol, attach to a running machine
+ * over TCP. It reads and writes the registers and memory, sets software breakpoints and write
+ * watchpoints, steps and continues, and is polled by the frontend between frames so the window
+ * keeps running.
+ *
+ * The register file, numbered the way the p and P packets number it, is
+ *
+ *   0-15  V0 to VF  8 bits
+ *   16    I         16 bits
+ *   17    PC        16 bits
+ *   18    SP        8 bits
+ *   19    DT        8 bits, the delay timer
+ *   20    ST        8 bits, the sound timer
+ *
+ * Like the rest of the chip8, 16 bit registers are sent big endian. The layout is also described
+ * to gdb as a target description, fetched with qXfer:features:read:target.xml.
+ */
+
+// the registers in the order of the register file, with their sizes in bytes
+const REGISTERS: [(Register, usize); 21] = [
+    (Register::V(0x0), 1),
+    (Register::V(0x1), 1),
+    (Register::V(0x2), 1),
+    (Register::V(0x3), 1),
+    (Register::V(0x4), 1),
+    (Register::V(0x5), 1),
+    (Register::V(0x6), 1),
+    (Register::V(0x7), 1),
+    (Register::V(0x8), 1),
+    (Register::V(0x9), 1),
+    (Register::V(0xA), 1),
+    (Register::V(0xB), 1),
+    (Register::V(0xC), 1),
+    (Register::V(0xD), 1),
+    (Register::V(0xE), 1),
+    (Register::V(0xF), 1),
+    (Register::I, 2),
+    (Register::Pc, 2),
+    (Register::Sp, 1),
+    (Register::Delay, 1),
+    (Register::Sound, 1),
+];
+
+// the largest packet the stub takes, told to gdb in the reply to qSupported
+const PACKET_SIZE: usize = 0x1000;
+
+// the signals in stop replies, SIGINT after a ^C and SIGTRAP for everything else
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+pub struct GdbStub {
+    listener: TcpListener,
+    // one debugger at a time
+    client: Option<Client>,
+    debugger: Debugger,
+}
+
+struct Client {
+    stream: TcpStream,
+    // bytes read that do not make up a whole packet yet
+    input: Vec<u8>,
+}
+
+impl GdbStub {
+    /** Listens for gdb on the address, e.g. "127.0.0.1:1234". */
+    pub fn new<A: ToSocketAddrs>(address: A) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbStub {
+            listener,
+            client: None,
+            debugger: Debugger::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /** Returns true while the machine is stopped for the debugger. */
+    pub fn is_paused(&self) -> bool {
+        self.is_connected() && self.debugger.is_paused()
+    }
+
+    /**
+     * Accepts a debugger when none is attached, then answers every packet it has sent. The
+     * machine stops when a debugger attaches, and carries on when it detaches.
+     */
+    pub fn poll(&mut self, machine: &mut Machine) {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                // packets are small and each one waits on the last, so they go out straight away
+                Ok((stream, _))
+                    if stream.set_nonblocking(true).is_ok() && stream.set_nodelay(true).is_ok() =>
+                {
+                    self.client = Some(Client {
+                        stream,
+                        input: Vec::new(),
+                    });
+                    self.debugger.pause();
+                }
+                _ => return,
+            }
+        }
+
+        if !self.read_input() {
+            self.disconnect();
+            return;
+        }
+
+        while let Some(packet) = self.next_packet() {
+            match packet {
+                Packet::Interrupt => {
+                    self.debugger.pause();
+                    self.send(&format!("S{SIGINT:02x}"));
+                }
+                Packet::Data(data) => {
+                    if let Some(reply) = self.handle(machine, &data) {
+                        self.send(&reply);
+                    }
+                    if self.client.is_none() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /**
+     * Runs the machine's frame unless the debugger has stopped it, telling gdb when it stops.
+     * Errors are reported to gdb as signals, and only returned when no debugger is attached.
+     */
+    pub fn run_frame(&mut self, machine: &mut Machine) -> Result<(), Chip8Error> {
+        if self.client.is_none() {
+            return machine.run_frame();
+        }
+        if let Some(reason) = self.debugger.run_frame(machine) {
+            let reply = stop_reply(&reason);
+            self.send(&reply);
+        }
+        Ok(())
+    }
+
+    // reads what the debugger has sent, returns false once it has gone
+    fn read_input(&mut self) -> bool {
+        let Some(client) = &mut self.client else {
+            return false;
+        };
+        let mut buffer = [0; 1024];
+        loop {
+            match client.stream.read(&mut buffer) {
+                Ok(0) => return false,
+                Ok(length) => client.input.extend(&buffer[..length]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return true,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+    }
+
+    // takes the next whole packet from the input, acknowledging it
+    fn next_packet(&mut self) -> Option<Packet> {
+        let client = self.client.as_mut()?;
+        loop {
+            match client.input.first()? {
+                0x03 => {
+                    client.input.remove(0);
+                    return Some(Packet::Interrupt);
+                }
+                b'$' => break,
+                // acknowledgements and noise between packets
+                _ => {
+                    client.input.remove(0);
+                }
+            }
+        }
+
+        let end = client.input.iter().position(|byte| *byte == b'#')?;
+        if client.input.len() < end + 3 {
+            return None;
+        }
+        let packet = client.input.drain(..end + 3).collect::<Vec<u8>>();
+        let data = &packet[1..end];
+        let sent = std::str::from_utf8(&packet[end + 1..])
+            .ok()
+            .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+
+        if sent != Some(checksum(data)) {
+            let _ = client.stream.write_all(b"-");
+            return self.next_packet();
+        }
+        let _ = client.stream.write_all(b"+");
+        Some(Packet::Data(unescape(data)))
+    }
+
+    fn send(&mut self, reply: &str) {
+        let Some(client) = &mut self.client else {
+            return;
+        };
+        let packet = format!("${reply}#{:02x}", checksum(reply.as_bytes()));
+        if client.stream.write_all(packet.as_bytes()).is_err() {
+            self.disconnect();
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.client = None;
+        self.debugger.resume();
+    }
+
+    // answers a packet, None when there is nothing to say until the machine stops
+    fn handle(&mut self, machine: &mut Machine, packet: &[u8]) -> Option<String> {
+        let text = String::from_utf8_lossy(packet);
+        let (command, arguments) = text.split_at(1.min(text.len()));
+
+        match command {
+            "c" | "s" => {
+                if let Some(address) = parse_hex(arguments) {
+                    machine.program_counter_mut().set_counter(address as u16);
+                }
+                if command == "c" {
+                    self.debugger.resume();
+                } else {
+                    self.debugger.step(1);
+                }
+                None
+            }
+            "D" => {
+                self.send("OK");
+                self.disconnect();
+                None
+            }
+            "k" => {
+                self.disconnect();
+                None
+            }
+            // packets that can not be parsed get an error
+            _ => Some(
+                self.answer(machine, command, arguments)
+                    .unwrap_or_else(|| String::from("E01")),
+            ),
+        }
+    }
+
+    // answers the packets that reply straight away, None when the packet is malformed
+    fn answer(&mut self, machine: &mut Machine, command: &str, arguments: &str) -> Option<String> {
+        let reply = match command {
+            "?" => format!("S{SIGTRAP:02x}"),
+            "g" => REGISTERS
+                .iter()
+                .map(|(register, size)| encode_register(machine, register, *size))
+                .collect(),
+            "G" => {
+                let mut bytes = decode_hex(arguments)?;
+                for (register, size) in REGISTERS {
+                    if bytes.len() < size {
+                        break;
+                    }
+                    let value = bytes
+                        .drain(..size)
+                        .fold(0, |value, byte| value << 8 | byte as u16);
+                    register.write(machine, value);
+                }
+                String::from("OK")
+            }
+            "p" => {
+                let (register, size) = REGISTERS.get(parse_hex(arguments)?)?;
+                encode_register(machine, register, *size)
+            }
+            "P" => {
+                let (index, value) = arguments.split_once('=')?;
+                let (register, _) = REGISTERS.get(parse_hex(index)?)?;
+                let value = decode_hex(value)?
+                    .iter()
+                    .fold(0, |value, byte| value << 8 | *byte as u16);
+                register.write(machine, value);
+                String::from("OK")
+            }
+            "m" => {
+                let (address, length) = parse_range(arguments)?;
+                encode_hex(
+                    machine
+                        .memory()
+                        .as_slice()
+                        .get(address..address.checked_add(length)?)?,
+                )
+            }
+            "M" => {
+                let (range, data) = arguments.split_once(':')?;
+                let (address, length) = parse_range(range)?;
+                let bytes = decode_hex(data)?;
+                if bytes.len() != length || address.checked_add(length)? > machine.memory().size() {
+                    return None;
+                }
+                for (offset, byte) in bytes.into_iter().enumerate() {
+                    machine.memory_mut().poke(address + offset, byte).ok()?;
+                }
+                String::from("OK")
+            }
+            "Z" | "z" => {
+                let mut fields = arguments.split(',');
+                let kind = fields.next()?;
+                let address = u16::try_from(parse_hex(fields.next()?)?).ok()?;
+                let length = u16::try_from(parse_hex(fields.next()?)?).ok()?.max(1);
+                if address as usize + length as usize > machine.memory().size() {
+                    return None;
+                }
+                self.set_point(command == "Z", kind, address, length)
+            }
+            "H" | "T" => String::from("OK"),
+            "q" => query(arguments),
+            // anything else is not supported, which an empty reply says
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    // adds or removes a breakpoint or write watchpoint
+    fn set_point(&mut self, insert: bool, kind: &str, address: u16, length: u16) -> String {
+        match kind {
+            // software and hardware breakpoints are the same thing here
+            "0" | "1" => {
+                let breakpoint = Breakpoint::Address(address);
+                let index = self
+                    .debugger
+                    .breakpoints()
+                    .iter()
+                    .position(|b| *b == breakpoint);
+                match (insert, index) {
+                    (true, None) => {
+                        self.debugger.add_breakpoint(breakpoint);
+                    }
+                    (false, Some(index)) => {
+                        let _ = self.debugger.remove_breakpoint(index);
+                    }
+                    _ => {}
+                }
+                String::from("OK")
+            }
+            "2" => {
+                let watchpoint = Watchpoint {
+                    start: address,
+                    end: address.saturating_add(length - 1),
+                };
+                let index = self
+                    .debugger
+                    .watchpoints()
+                    .iter()
+                    .position(|w| *w == watchpoint);
+                match (insert, index) {
+                    (true, None) => {
+                        self.debugger.add_watchpoint(watchpoint);
+                    }
+                    (false, Some(index)) => {
+                        let _ = self.debugger.remove_watchpoint(index);
+                    }
+                    _ => {}
+                }
+                String::from("OK")
+            }
+            // read and access watchpoints are not supported
+            _ => String::new(),
+        }
+    }
+}
+
+enum Packet {
+    // a ^C sent on its own, asking the machine to stop
+    Interrupt,
+    Data(Vec<u8>),
+}
+
+// answers the q packets
+fn query(arguments: &str) -> String {
+    if arguments.starts_with("Supported") {
+        return format!("PacketSize={PACKET_SIZE:x};qXfer:features:read+");
+    }
+    if let Some(request) = arguments.strip_prefix("Xfer:features:read:target.xml:") {
+        let Some((offset, length)) = parse_range(request) else {
+            return String::from("E01");
+        };
+        let description = target_description();
+        let Some(end) = offset.checked_add(length) else {
+            return String::from("E01");
+        };
+        let start = offset.min(description.len());
+        let end = end.min(description.len());
+        let more = if end < description.len() { 'm' } else { 'l' };
+        return format!("{more}{}", &description[start..end]);
+    }
+    match arguments {
+        "Attached" => String::from("1"),
+        "C" => String::from("QC1"),
+        "fThreadInfo" => String::from("m1"),
+        "sThreadInfo" => String::from("l"),
+        _ => String::new(),
+    }
+}
+
+// the register file as a gdb target description
+fn target_description() -> String {
+    let mut registers = String::new();
+    for (register, size) in REGISTERS {
+        let kind = match register {
+            Register::Pc => "code_ptr",
+            Register::I => "data_ptr",
+            _ if size == 2 => "uint16",
+            _ => "uint8",
+        };
+        registers.push_str(&format!(
+            "<reg name=\"{register}\" bitsize=\"{}\" type=\"{kind}\"/>",
+            size * 8
+        ));
+    }
+    format!(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\"><feature name=\"org.chip8.core\">{registers}</feature></target>"
+    )
+}
+
+fn stop_reply(reason: &StopReason) -> String {
+    match reason {
+        StopReason::Watchpoint { address, .. } => format!("T{SIGTRAP:02x}watch:{address:x};"),
+        StopReason::Exited => String::from("W00"),
+        StopReason::Step | StopReason::Breakpoint(_) => format!("S{SIGTRAP:02x}"),
+        StopReason::Error(Chip8Error::UnknownOpcode { .. }) => format!("S{SIGILL:02x}"),
+        StopReason::Error(_) => format!("S{SIGSEGV:02x}"),
+    }
+}
+
+fn encode_register(machine: &Machine, register: &Register, size: usize) -> String {
+    let value = register.read(machine);
+    match size {
+        2 => format!("{value:04x}"),
+        _ => format!("{:02x}", value as u8),
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, byte| sum.wrapping_add(*byte))
+}
+
+// undoes the escaping of }, #, $ and * in packets
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(data.len());
+    let mut escaped = false;
+    for byte in data {
+        match (escaped, byte) {
+            (false, b'}') => escaped = true,
+            (true, _) => {
+                bytes.push(byte ^ 0x20);
+                escaped = false;
+            }
+            _ => bytes.push(*byte),
+        }
+    }
+    bytes
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+// an "address,length" pair in hex
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (address, length) = text.split_once(',')?;
+    Some((parse_hex(address)?, parse_hex(length)?))
+}
+
+#[cfg(test)]
+mod gdb_tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    // 6000 v0 := 0, 7001 v0 += 1, A300 i := 0x300, F055 save v0, 1202 loop
+    const ROM: [u8; 10] = [0x60, 0x00, 0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x02];
+
+    // sends a packet and runs the stub until it replies, like gdb would
+    fn exchange(
+        stub: &mut GdbStub,
+        machine: &mut Machine,
+        client: &mut TcpStream,
+        packet: &str,
+    ) -> String {
+        let packet = format!("${packet}#{:02x}", checksum(packet.as_bytes()));
+        client.write_all(packet.as_bytes()).unwrap();
+
+        let started = Instant::now();
+        let mut received = Vec::new();
+        while started.elapsed() < Duration::from_secs(5) {
+            stub.poll(machine);
+            if !stub.is_paused() {
+                stub.run_frame(machine).unwrap();
+            }
+
+            let mut buffer = [0; 1024];
+            if let Ok(length) = client.read(&mut buffer) {
+                received.extend(&buffer[..length]);
+            }
+            let text = String::from_utf8_lossy(&received).into_owned();
+            if let Some(start) = text.find('$') {
+                if let Some(end) = text[start..].find('#') {
+                    if text.len() >= start + end + 3 {
+                        assert!(text.starts_with('+'));
+                        return text[start + 1..start + end].to_string();
+                    }
+                }
+            }
+        }
+        panic!("no reply to {packet}");
+    }
+
+    #[test]
+    fn answers_a_scripted_client() {
+        let mut machine = Machine::new(64, 32, &ROM).unwrap();
+        let mut stub = GdbStub::new("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(stub.local_addr().unwrap()).unwrap();
+        client.set_nonblocking(true).unwrap();
+        client.set_nodelay(true).unwrap();
+        let mut gdb =
+            |machine: &mut Machine, packet: &str| exchange(&mut stub, machine, &mut client, packet);
+
+        assert!(gdb(&mut machine, "qSupported:swbreak+").contains("qXfer:features:read+"));
+        assert!(gdb(&mut machine, "qXfer:features:read:target.xml:0,1000").contains("\"pc\""));
+        assert_eq!(
+            gdb(
+                &mut machine,
+                "qXfer:features:read:target.xml:ffffffffffffffff,2"
+            ),
+            "E01"
+        );
+        assert_eq!(gdb(&mut machine, "?"), "S05");
+        assert_eq!(gdb(&mut machine, "m200,4"), "60007001");
+        assert_eq!(gdb(&mut machine, "p11"), "0200");
+
+        // run to a breakpoint, then step
+        assert_eq!(gdb(&mut machine, "Z0,206,2"), "OK");
+        assert_eq!(gdb(&mut machine, "c"), "S05");
+        assert_eq!(gdb(&mut machine, "p11"), "0206");
+        assert_eq!(gdb(&mut machine, "s"), "S05");
+        assert_eq!(gdb(&mut machine, "p11"), "0208");
+        assert_eq!(gdb(&mut machine, "z0,206,2"), "OK");
+
+        // and to a watchpoint on the byte F055 writes
+        assert_eq!(gdb(&mut machine, "Z2,300,1"), "OK");
+        assert_eq!(gdb(&mut machine, "c"), "T05watch:300;");
+        assert_eq!(gdb(&mut machine, "m300,1"), "02");
+
+        // registers and memory can be written
+        assert_eq!(gdb(&mut machine, "P3=7f"), "OK");
+        assert_eq!(*machine.registers().get_register(3).unwrap(), 0x7F);
+        assert_eq!(gdb(&mut machine, "M400,2:abcd"), "OK");
+        assert_eq!(gdb(&mut machine, "m400,2"), "abcd");
+        let registers = gdb(&mut machine, "g");
+        assert_eq!(registers.len(), 2 * (16 + 2 + 2 + 1 + 1 + 1));
+        assert_eq!(gdb(&mut machine, "m1000,1"), "E01");
+        // lengths that overflow the address are refused rather than panicking
+        assert_eq!(gdb(&mut machine, "m400,ffffffffffffffff"), "E01");
+        assert_eq!(gdb(&mut machine, "M400,ffffffffffffffff:ab"), "E01");
+        // as are points that do not fit in memory
+        assert_eq!(gdb(&mut machine, "Z2,300,10000"), "E01");
+        assert_eq!(gdb(&mut machine, "Z2,ffff,2"), "E01");
+        assert_eq!(gdb(&mut machine, "Z0,10200,2"), "E01");
+    }
+}
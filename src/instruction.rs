@@ -0,0 +1,705 @@
+use std::{error::Error, fmt, str::FromStr};
+
+/*
+ * The chip8 instruction set.
+ *
+ * NNN: address
+ * NN: 8-bit constant
+ * N: 4-bit constant
+ * X and Y: 4-bit register identifier
+ *
+ * Every instruction is two bytes, stored big endian. The mnemonics used by `Display` and
+ * `FromStr` follow Cowgod's Chip-8 technical reference. The SUPER-CHIP 1.1 and XO-CHIP additions
+ * are decoded as well, it is up to the interpreter whether it runs them.
+ *
+ * F000 NNNN is the one instruction that is four bytes long, `decode` sees only its first two
+ * bytes and `decode_bytes` reads the address from the second two.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    // 0NNN Calls machine code routine at address NNN
+    Sys { nnn: u16 },
+    // 00CN Scrolls the display down by N pixels (SUPER-CHIP)
+    ScrollDown { n: u8 },
+    // 00DN Scrolls the display up by N pixels (XO-CHIP)
+    ScrollUp { n: u8 },
+    // 00E0 Clears the screen
+    Cls,
+    // 00EE Returns from a subroutine
+    Ret,
+    // 00FB Scrolls the display right by 4 pixels (SUPER-CHIP)
+    ScrollRight,
+    // 00FC Scrolls the display left by 4 pixels (SUPER-CHIP)
+    ScrollLeft,
+    // 00FD Exits the interpreter (SUPER-CHIP)
+    Exit,
+    // 00FE Switches to the 64 x 32 low resolution display (SUPER-CHIP)
+    LowRes,
+    // 00FF Switches to the 128 x 64 high resolution display (SUPER-CHIP)
+    HighRes,
+    // 1NNN Jumps to address NNN
+    Jump { nnn: u16 },
+    // 2NNN Calls subroutine at NNN
+    Call { nnn: u16 },
+    // 3XNN Skips the next instruction if VX equals NN
+    SkipEqByte { x: u8, nn: u8 },
+    // 4XNN Skips the next instruction if VX does not equal NN
+    SkipNeByte { x: u8, nn: u8 },
+    // 5XY0 Skips the next instruction if VX equals VY
+    SkipEqReg { x: u8, y: u8 },
+    // 5XY2 Stores VX to VY in memory starting at address I (XO-CHIP)
+    StoreRange { x: u8, y: u8 },
+    // 5XY3 Fills VX to VY with values from memory starting at address I (XO-CHIP)
+    LoadRange { x: u8, y: u8 },
+    // 6XNN Sets VX to NN
+    LoadByte { x: u8, nn: u8 },
+    // 7XNN Adds NN to VX (carry flag is not changed)
+    AddByte { x: u8, nn: u8 },
+    // 8XY0 Sets VX to the value of VY
+    LoadReg { x: u8, y: u8 },
+    // 8XY1 Sets VX to VX or VY
+    Or { x: u8, y: u8 },
+    // 8XY2 Sets VX to VX and VY
+    And { x: u8, y: u8 },
+    // 8XY3 Sets VX to VX xor VY
+    Xor { x: u8, y: u8 },
+    // 8XY4 Adds VY to VX, VF is set to the carry
+    AddReg { x: u8, y: u8 },
+    // 8XY5 VY is subtracted from VX, VF is set to 0 when there's a borrow
+    Sub { x: u8, y: u8 },
+    // 8XY6 Shifts VX to the right by 1, VF is set to the bit shifted out
+    ShiftRight { x: u8, y: u8 },
+    // 8XY7 Sets VX to VY minus VX, VF is set to 0 when there's a borrow
+    SubN { x: u8, y: u8 },
+    // 8XYE Shifts VX to the left by 1, VF is set to the bit shifted out
+    ShiftLeft { x: u8, y: u8 },
+    // 9XY0 Skips the next instruction if VX does not equal VY
+    SkipNeReg { x: u8, y: u8 },
+    // ANNN Sets I to the address NNN
+    LoadI { nnn: u16 },
+    // BNNN Jumps to the address NNN plus V0
+    JumpV0 { nnn: u16 },
+    // CXNN Sets VX to a random number and NN
+    Random { x: u8, nn: u8 },
+    // DXYN Draws an 8 x N sprite from memory location I at coordinate (VX, VY), DXY0 draws a 16 x 16 sprite on SUPER-CHIP
+    Draw { x: u8, y: u8, n: u8 },
+    // EX9E Skips the next instruction if the key stored in VX is pressed
+    SkipKey { x: u8 },
+    // EXA1 Skips the next instruction if the key stored in VX is not pressed
+    SkipNotKey { x: u8 },
+    // F000 NNNN Sets I to the 16 bit address NNNN (XO-CHIP)
+    LoadLongI { nnnn: u16 },
+    // FN01 Selects the bit planes N that drawing, clearing and scrolling act on (XO-CHIP)
+    Plane { n: u8 },
+    // F002 Loads the 16 byte audio pattern from memory starting at address I (XO-CHIP)
+    Audio,
+    // FX07 Sets VX to the value of the delay timer
+    LoadDelay { x: u8 },
+    // FX0A A key press is awaited, and then stored in VX
+    WaitKey { x: u8 },
+    // FX15 Sets the delay timer to VX
+    SetDelay { x: u8 },
+    // FX18 Sets the sound timer to VX
+    SetSound { x: u8 },
+    // FX1E Adds VX to I
+    AddI { x: u8 },
+    // FX29 Sets I to the location of the font sprite for the character in VX
+    LoadFont { x: u8 },
+    // FX30 Sets I to the location of the big font sprite for the digit in VX (SUPER-CHIP)
+    LoadBigFont { x: u8 },
+    // FX3A Sets the audio pattern playback rate to VX (XO-CHIP)
+    Pitch { x: u8 },
+    // FX33 Stores the binary-coded decimal representation of VX at I, I+1 and I+2
+    Bcd { x: u8 },
+    // FX55 Stores V0 to VX in memory starting at address I
+    Store { x: u8 },
+    // FX65 Fills V0 to VX with values from memory starting at address I
+    Load { x: u8 },
+    // FX75 Stores V0 to VX in the RPL user flags (SUPER-CHIP)
+    StoreFlags { x: u8 },
+    // FX85 Fills V0 to VX from the RPL user flags (SUPER-CHIP)
+    LoadFlags { x: u8 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    UnknownOpcode(u16),
+    // the bytes end before the instruction does, or before the address of F000 NNNN
+    Truncated,
+}
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnknownOpcode(opcode) => write!(f, "unknown opcode {opcode:04X}"),
+            DecodeError::Truncated => write!(f, "the instruction is cut off"),
+        }
+    }
+}
+impl Error for DecodeError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseInstructionError(pub String);
+impl fmt::Display for ParseInstructionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "could not parse instruction: {}", self.0)
+    }
+}
+impl Error for ParseInstructionError {}
+
+impl Instruction {
+    /** Decodes a two byte opcode into an instruction. */
+    pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+        let x = ((opcode >> 8) & 0xF) as u8;
+        let y = ((opcode >> 4) & 0xF) as u8;
+        let n = (opcode & 0xF) as u8;
+        let nn = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+
+        let instruction = match (opcode >> 12) & 0xF {
+            0x0 => match opcode {
+                0x00C0..=0x00CF => Instruction::ScrollDown { n },
+                0x00D0..=0x00DF => Instruction::ScrollUp { n },
+                0x00E0 => Instruction::Cls,
+                0x00EE => Instruction::Ret,
+                0x00FB => Instruction::ScrollRight,
+                0x00FC => Instruction::ScrollLeft,
+                0x00FD => Instruction::Exit,
+                0x00FE => Instruction::LowRes,
+                0x00FF => Instruction::HighRes,
+                _ => Instruction::Sys { nnn },
+            },
+            0x1 => Instruction::Jump { nnn },
+            0x2 => Instruction::Call { nnn },
+            0x3 => Instruction::SkipEqByte { x, nn },
+            0x4 => Instruction::SkipNeByte { x, nn },
+            0x5 => match n {
+                0x0 => Instruction::SkipEqReg { x, y },
+                0x2 => Instruction::StoreRange { x, y },
+                0x3 => Instruction::LoadRange { x, y },
+                _ => return Err(DecodeError::UnknownOpcode(opcode)),
+            },
+            0x6 => Instruction::LoadByte { x, nn },
+            0x7 => Instruction::AddByte { x, nn },
+            0x8 => match n {
+                0x0 => Instruction::LoadReg { x, y },
+                0x1 => Instruction::Or { x, y },
+                0x2 => Instruction::And { x, y },
+                0x3 => Instruction::Xor { x, y },
+                0x4 => Instruction::AddReg { x, y },
+                0x5 => Instruction::Sub { x, y },
+                0x6 => Instruction::ShiftRight { x, y },
+                0x7 => Instruction::SubN { x, y },
+                0xE => Instruction::ShiftLeft { x, y },
+                _ => return Err(DecodeError::UnknownOpcode(opcode)),
+            },
+            0x9 if n == 0x0 => Instruction::SkipNeReg { x, y },
+            0xA => Instruction::LoadI { nnn },
+            0xB => Instruction::JumpV0 { nnn },
+            0xC => Instruction::Random { x, nn },
+            0xD => Instruction::Draw { x, y, n },
+            0xE => match nn {
+                0x9E => Instruction::SkipKey { x },
+                0xA1 => Instruction::SkipNotKey { x },
+                _ => return Err(DecodeError::UnknownOpcode(opcode)),
+            },
+            0xF => match nn {
+                0x00 if x == 0 => Instruction::LoadLongI { nnnn: 0 },
+                0x01 => Instruction::Plane { n: x },
+                0x02 if x == 0 => Instruction::Audio,
+                0x07 => Instruction::LoadDelay { x },
+                0x0A => Instruction::WaitKey { x },
+                0x15 => Instruction::SetDelay { x },
+                0x18 => Instruction::SetSound { x },
+                0x1E => Instruction::AddI { x },
+                0x29 => Instruction::LoadFont { x },
+                0x30 => Instruction::LoadBigFont { x },
+                0x3A => Instruction::Pitch { x },
+                0x33 => Instruction::Bcd { x },
+                0x55 => Instruction::Store { x },
+                0x65 => Instruction::Load { x },
+                0x75 => Instruction::StoreFlags { x },
+                0x85 => Instruction::LoadFlags { x },
+                _ => return Err(DecodeError::UnknownOpcode(opcode)),
+            },
+            _ => return Err(DecodeError::UnknownOpcode(opcode)),
+        };
+
+        Ok(instruction)
+    }
+
+    /** Decodes the instruction at the start of bytes, including the address of F000 NNNN. */
+    pub fn decode_bytes(bytes: &[u8]) -> Result<Instruction, DecodeError> {
+        let word = |index: usize| {
+            bytes
+                .get(index..index + 2)
+                .map(|word| (word[0] as u16) << 8 | word[1] as u16)
+        };
+
+        let opcode = word(0).ok_or(DecodeError::Truncated)?;
+        match Instruction::decode(opcode)? {
+            Instruction::LoadLongI { .. } => match word(2) {
+                Some(nnnn) => Ok(Instruction::LoadLongI { nnnn }),
+                None => Err(DecodeError::Truncated),
+            },
+            instruction => Ok(instruction),
+        }
+    }
+
+    /** The length of the instruction in bytes, 4 for F000 NNNN and 2 for everything else. */
+    pub fn byte_len(&self) -> u16 {
+        match self {
+            Instruction::LoadLongI { .. } => 4,
+            _ => 2,
+        }
+    }
+
+    /** Encodes the instruction into its bytes, four of them for F000 NNNN. */
+    pub fn encode_bytes(&self) -> Vec<u8> {
+        let opcode = self.encode();
+        match *self {
+            Instruction::LoadLongI { nnnn } => {
+                vec![
+                    (opcode >> 8) as u8,
+                    opcode as u8,
+                    (nnnn >> 8) as u8,
+                    nnnn as u8,
+                ]
+            }
+            _ => vec![(opcode >> 8) as u8, opcode as u8],
+        }
+    }
+
+    /** Encodes the instruction back into its two byte opcode, the first two bytes of F000 NNNN. */
+    pub fn encode(&self) -> u16 {
+        let xy = |base: u16, x: u8, y: u8, n: u16| {
+            base | ((x as u16 & 0xF) << 8) | ((y as u16 & 0xF) << 4) | n
+        };
+        let xnn = |base: u16, x: u8, nn: u8| base | ((x as u16 & 0xF) << 8) | nn as u16;
+
+        match *self {
+            Instruction::Sys { nnn } => nnn & 0xFFF,
+            Instruction::ScrollDown { n } => 0x00C0 | (n as u16 & 0xF),
+            Instruction::ScrollUp { n } => 0x00D0 | (n as u16 & 0xF),
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowRes => 0x00FE,
+            Instruction::HighRes => 0x00FF,
+            Instruction::Jump { nnn } => 0x1000 | (nnn & 0xFFF),
+            Instruction::Call { nnn } => 0x2000 | (nnn & 0xFFF),
+            Instruction::SkipEqByte { x, nn } => xnn(0x3000, x, nn),
+            Instruction::SkipNeByte { x, nn } => xnn(0x4000, x, nn),
+            Instruction::SkipEqReg { x, y } => xy(0x5000, x, y, 0x0),
+            Instruction::StoreRange { x, y } => xy(0x5000, x, y, 0x2),
+            Instruction::LoadRange { x, y } => xy(0x5000, x, y, 0x3),
+            Instruction::LoadByte { x, nn } => xnn(0x6000, x, nn),
+            Instruction::AddByte { x, nn } => xnn(0x7000, x, nn),
+            Instruction::LoadReg { x, y } => xy(0x8000, x, y, 0x0),
+            Instruction::Or { x, y } => xy(0x8000, x, y, 0x1),
+            Instruction::And { x, y } => xy(0x8000, x, y, 0x2),
+            Instruction::Xor { x, y } => xy(0x8000, x, y, 0x3),
+            Instruction::AddReg { x, y } => xy(0x8000, x, y, 0x4),
+            Instruction::Sub { x, y } => xy(0x8000, x, y, 0x5),
+            Instruction::ShiftRight { x, y } => xy(0x8000, x, y, 0x6),
+            Instruction::SubN { x, y } => xy(0x8000, x, y, 0x7),
+            Instruction::ShiftLeft { x, y } => xy(0x8000, x, y, 0xE),
+            Instruction::SkipNeReg { x, y } => xy(0x9000, x, y, 0x0),
+            Instruction::LoadI { nnn } => 0xA000 | (nnn & 0xFFF),
+            Instruction::JumpV0 { nnn } => 0xB000 | (nnn & 0xFFF),
+            Instruction::Random { x, nn } => xnn(0xC000, x, nn),
+            Instruction::Draw { x, y, n } => xy(0xD000, x, y, n as u16 & 0xF),
+            Instruction::SkipKey { x } => xnn(0xE000, x, 0x9E),
+            Instruction::SkipNotKey { x } => xnn(0xE000, x, 0xA1),
+            Instruction::LoadLongI { .. } => 0xF000,
+            Instruction::Plane { n } => xnn(0xF000, n, 0x01),
+            Instruction::Audio => 0xF002,
+            Instruction::LoadDelay { x } => xnn(0xF000, x, 0x07),
+            Instruction::WaitKey { x } => xnn(0xF000, x, 0x0A),
+            Instruction::SetDelay { x } => xnn(0xF000, x, 0x15),
+            Instruction::SetSound { x } => xnn(0xF000, x, 0x18),
+            Instruction::AddI { x } => xnn(0xF000, x, 0x1E),
+            Instruction::LoadFont { x } => xnn(0xF000, x, 0x29),
+            Instruction::LoadBigFont { x } => xnn(0xF000, x, 0x30),
+            Instruction::Pitch { x } => xnn(0xF000, x, 0x3A),
+            Instruction::Bcd { x } => xnn(0xF000, x, 0x33),
+            Instruction::Store { x } => xnn(0xF000, x, 0x55),
+            Instruction::Load { x } => xnn(0xF000, x, 0x65),
+            Instruction::StoreFlags { x } => xnn(0xF000, x, 0x75),
+            Instruction::LoadFlags { x } => xnn(0xF000, x, 0x85),
+        }
+    }
+
+    /** Formats the instruction the way Octo writes it, e.g. "v3 += 0x05" or "sprite v0 v1 15". */
+    pub fn to_octo(&self) -> String {
+        match *self {
+            Instruction::Sys { nnn } => format!("native 0x{nnn:03X}"),
+            Instruction::ScrollDown { n } => format!("scroll-down {n}"),
+            Instruction::ScrollUp { n } => format!("scroll-up {n}"),
+            Instruction::Cls => "clear".to_string(),
+            Instruction::Ret => "return".to_string(),
+            Instruction::ScrollRight => "scroll-right".to_string(),
+            Instruction::ScrollLeft => "scroll-left".to_string(),
+            Instruction::Exit => "exit".to_string(),
+            Instruction::LowRes => "lores".to_string(),
+            Instruction::HighRes => "hires".to_string(),
+            Instruction::Jump { nnn } => format!("jump 0x{nnn:03X}"),
+            Instruction::Call { nnn } => format!(":call 0x{nnn:03X}"),
+            // octo skips are written as the condition under which the next instruction runs
+            Instruction::SkipEqByte { x, nn } => format!("if v{x:x} != 0x{nn:02X} then"),
+            Instruction::SkipNeByte { x, nn } => format!("if v{x:x} == 0x{nn:02X} then"),
+            Instruction::SkipEqReg { x, y } => format!("if v{x:x} != v{y:x} then"),
+            Instruction::StoreRange { x, y } => format!("save v{x:x} - v{y:x}"),
+            Instruction::LoadRange { x, y } => format!("load v{x:x} - v{y:x}"),
+            Instruction::LoadByte { x, nn } => format!("v{x:x} := 0x{nn:02X}"),
+            Instruction::AddByte { x, nn } => format!("v{x:x} += 0x{nn:02X}"),
+            Instruction::LoadReg { x, y } => format!("v{x:x} := v{y:x}"),
+            Instruction::Or { x, y } => format!("v{x:x} |= v{y:x}"),
+            Instruction::And { x, y } => format!("v{x:x} &= v{y:x}"),
+            Instruction::Xor { x, y } => format!("v{x:x} ^= v{y:x}"),
+            Instruction::AddReg { x, y } => format!("v{x:x} += v{y:x}"),
+            Instruction::Sub { x, y } => format!("v{x:x} -= v{y:x}"),
+            Instruction::ShiftRight { x, y } => format!("v{x:x} >>= v{y:x}"),
+            Instruction::SubN { x, y } => format!("v{x:x} =- v{y:x}"),
+            Instruction::ShiftLeft { x, y } => format!("v{x:x} <<= v{y:x}"),
+            Instruction::SkipNeReg { x, y } => format!("if v{x:x} == v{y:x} then"),
+            Instruction::LoadI { nnn } => format!("i := 0x{nnn:03X}"),
+            Instruction::JumpV0 { nnn } => format!("jump0 0x{nnn:03X}"),
+            Instruction::Random { x, nn } => format!("v{x:x} := random 0x{nn:02X}"),
+            Instruction::Draw { x, y, n } => format!("sprite v{x:x} v{y:x} {n}"),
+            Instruction::SkipKey { x } => format!("if v{x:x} -key then"),
+            Instruction::SkipNotKey { x } => format!("if v{x:x} key then"),
+            Instruction::LoadLongI { nnnn } => format!("i := long 0x{nnnn:04X}"),
+            Instruction::Plane { n } => format!("plane {n}"),
+            Instruction::Audio => "audio".to_string(),
+            Instruction::LoadDelay { x } => format!("v{x:x} := delay"),
+            Instruction::WaitKey { x } => format!("v{x:x} := key"),
+            Instruction::SetDelay { x } => format!("delay := v{x:x}"),
+            Instruction::SetSound { x } => format!("buzzer := v{x:x}"),
+            Instruction::AddI { x } => format!("i += v{x:x}"),
+            Instruction::LoadFont { x } => format!("i := hex v{x:x}"),
+            Instruction::LoadBigFont { x } => format!("i := bighex v{x:x}"),
+            Instruction::Pitch { x } => format!("pitch := v{x:x}"),
+            Instruction::Bcd { x } => format!("bcd v{x:x}"),
+            Instruction::Store { x } => format!("save v{x:x}"),
+            Instruction::Load { x } => format!("load v{x:x}"),
+            Instruction::StoreFlags { x } => format!("saveflags v{x:x}"),
+            Instruction::LoadFlags { x } => format!("loadflags v{x:x}"),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Sys { nnn } => write!(f, "SYS 0x{nnn:03X}"),
+            Instruction::ScrollDown { n } => write!(f, "SCD 0x{n:X}"),
+            Instruction::ScrollUp { n } => write!(f, "SCU 0x{n:X}"),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::LowRes => write!(f, "LOW"),
+            Instruction::HighRes => write!(f, "HIGH"),
+            Instruction::Jump { nnn } => write!(f, "JP 0x{nnn:03X}"),
+            Instruction::Call { nnn } => write!(f, "CALL 0x{nnn:03X}"),
+            Instruction::SkipEqByte { x, nn } => write!(f, "SE V{x:X}, 0x{nn:02X}"),
+            Instruction::SkipNeByte { x, nn } => write!(f, "SNE V{x:X}, 0x{nn:02X}"),
+            Instruction::SkipEqReg { x, y } => write!(f, "SE V{x:X}, V{y:X}"),
+            Instruction::StoreRange { x, y } => write!(f, "SAVE V{x:X}, V{y:X}"),
+            Instruction::LoadRange { x, y } => write!(f, "LOAD V{x:X}, V{y:X}"),
+            Instruction::LoadByte { x, nn } => write!(f, "LD V{x:X}, 0x{nn:02X}"),
+            Instruction::AddByte { x, nn } => write!(f, "ADD V{x:X}, 0x{nn:02X}"),
+            Instruction::LoadReg { x, y } => write!(f, "LD V{x:X}, V{y:X}"),
+            Instruction::Or { x, y } => write!(f, "OR V{x:X}, V{y:X}"),
+            Instruction::And { x, y } => write!(f, "AND V{x:X}, V{y:X}"),
+            Instruction::Xor { x, y } => write!(f, "XOR V{x:X}, V{y:X}"),
+            Instruction::AddReg { x, y } => write!(f, "ADD V{x:X}, V{y:X}"),
+            Instruction::Sub { x, y } => write!(f, "SUB V{x:X}, V{y:X}"),
+            Instruction::ShiftRight { x, y } => write!(f, "SHR V{x:X}, V{y:X}"),
+            Instruction::SubN { x, y } => write!(f, "SUBN V{x:X}, V{y:X}"),
+            Instruction::ShiftLeft { x, y } => write!(f, "SHL V{x:X}, V{y:X}"),
+            Instruction::SkipNeReg { x, y } => write!(f, "SNE V{x:X}, V{y:X}"),
+            Instruction::LoadI { nnn } => write!(f, "LD I, 0x{nnn:03X}"),
+            Instruction::JumpV0 { nnn } => write!(f, "JP V0, 0x{nnn:03X}"),
+            Instruction::Random { x, nn } => write!(f, "RND V{x:X}, 0x{nn:02X}"),
+            Instruction::Draw { x, y, n } => write!(f, "DRW V{x:X}, V{y:X}, 0x{n:X}"),
+            Instruction::SkipKey { x } => write!(f, "SKP V{x:X}"),
+            Instruction::SkipNotKey { x } => write!(f, "SKNP V{x:X}"),
+            Instruction::LoadLongI { nnnn } => write!(f, "LD I, LONG 0x{nnnn:04X}"),
+            Instruction::Plane { n } => write!(f, "PLANE 0x{n:X}"),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::LoadDelay { x } => write!(f, "LD V{x:X}, DT"),
+            Instruction::WaitKey { x } => write!(f, "LD V{x:X}, K"),
+            Instruction::SetDelay { x } => write!(f, "LD DT, V{x:X}"),
+            Instruction::SetSound { x } => write!(f, "LD ST, V{x:X}"),
+            Instruction::AddI { x } => write!(f, "ADD I, V{x:X}"),
+            Instruction::LoadFont { x } => write!(f, "LD F, V{x:X}"),
+            Instruction::LoadBigFont { x } => write!(f, "LD HF, V{x:X}"),
+            Instruction::Pitch { x } => write!(f, "LD PITCH, V{x:X}"),
+            Instruction::Bcd { x } => write!(f, "LD B, V{x:X}"),
+            Instruction::Store { x } => write!(f, "LD [I], V{x:X}"),
+            Instruction::Load { x } => write!(f, "LD V{x:X}, [I]"),
+            Instruction::StoreFlags { x } => write!(f, "LD R, V{x:X}"),
+            Instruction::LoadFlags { x } => write!(f, "LD V{x:X}, R"),
+        }
+    }
+}
+
+// an operand of a Cowgod style instruction, e.g. "V3", "0x200", "DT" or "[I]"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    V(u8),
+    Number(u16),
+    Long(u16),
+    I,
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    BigFont,
+    Bcd,
+    Flags,
+    Pitch,
+}
+
+fn parse_number(text: &str) -> Option<u16> {
+    if let Some(hex) = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_prefix('#'))
+        .or_else(|| text.strip_prefix('$'))
+    {
+        u16::from_str_radix(hex, 16).ok()
+    } else {
+        text.parse::<u16>().ok()
+    }
+}
+
+fn parse_operand(text: &str) -> Option<Operand> {
+    let operand = match text.to_ascii_uppercase().as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::DelayTimer,
+        "ST" => Operand::SoundTimer,
+        "K" => Operand::Key,
+        "F" => Operand::Font,
+        "HF" => Operand::BigFont,
+        "B" => Operand::Bcd,
+        "R" => Operand::Flags,
+        "PITCH" => Operand::Pitch,
+        upper if upper.starts_with("LONG ") => Operand::Long(parse_number(text[5..].trim())?),
+        upper => match upper.strip_prefix('V') {
+            Some(register) if register.len() == 1 => {
+                Operand::V(u8::from_str_radix(register, 16).ok()?)
+            }
+            _ => Operand::Number(parse_number(text)?),
+        },
+    };
+    Some(operand)
+}
+
+impl FromStr for Instruction {
+    type Err = ParseInstructionError;
+
+    /** Parses an instruction written in the same syntax `Display` produces. */
+    fn from_str(text: &str) -> Result<Instruction, ParseInstructionError> {
+        let error = || ParseInstructionError(text.trim().to_string());
+
+        let text = text.trim();
+        let (mnemonic, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let operands = rest
+            .split(',')
+            .map(str::trim)
+            .filter(|operand| !operand.is_empty())
+            .map(parse_operand)
+            .collect::<Option<Vec<Operand>>>()
+            .ok_or_else(error)?;
+
+        let byte = |value: u16| u8::try_from(value).map_err(|_| error());
+        let address = |value: u16| {
+            if value <= 0xFFF {
+                Ok(value)
+            } else {
+                Err(error())
+            }
+        };
+
+        use Operand::*;
+        let instruction = match (mnemonic.to_ascii_uppercase().as_str(), operands.as_slice()) {
+            ("SYS", [Number(nnn)]) => Instruction::Sys {
+                nnn: address(*nnn)?,
+            },
+            ("SCD", [Number(n)]) if *n <= 0xF => Instruction::ScrollDown { n: *n as u8 },
+            ("SCU", [Number(n)]) if *n <= 0xF => Instruction::ScrollUp { n: *n as u8 },
+            ("CLS", []) => Instruction::Cls,
+            ("RET", []) => Instruction::Ret,
+            ("SCR", []) => Instruction::ScrollRight,
+            ("SCL", []) => Instruction::ScrollLeft,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::LowRes,
+            ("HIGH", []) => Instruction::HighRes,
+            ("JP", [Number(nnn)]) => Instruction::Jump {
+                nnn: address(*nnn)?,
+            },
+            ("JP", [V(0), Number(nnn)]) => Instruction::JumpV0 {
+                nnn: address(*nnn)?,
+            },
+            ("CALL", [Number(nnn)]) => Instruction::Call {
+                nnn: address(*nnn)?,
+            },
+            ("SE", [V(x), Number(nn)]) => Instruction::SkipEqByte {
+                x: *x,
+                nn: byte(*nn)?,
+            },
+            ("SE", [V(x), V(y)]) => Instruction::SkipEqReg { x: *x, y: *y },
+            ("SAVE", [V(x), V(y)]) => Instruction::StoreRange { x: *x, y: *y },
+            ("LOAD", [V(x), V(y)]) => Instruction::LoadRange { x: *x, y: *y },
+            ("PLANE", [Number(n)]) if *n <= 0xF => Instruction::Plane { n: *n as u8 },
+            ("AUDIO", []) => Instruction::Audio,
+            ("LD", [I, Long(nnnn)]) => Instruction::LoadLongI { nnnn: *nnnn },
+            ("LD", [Pitch, V(x)]) => Instruction::Pitch { x: *x },
+            ("SNE", [V(x), Number(nn)]) => Instruction::SkipNeByte {
+                x: *x,
+                nn: byte(*nn)?,
+            },
+            ("SNE", [V(x), V(y)]) => Instruction::SkipNeReg { x: *x, y: *y },
+            ("LD", [V(x), Number(nn)]) => Instruction::LoadByte {
+                x: *x,
+                nn: byte(*nn)?,
+            },
+            ("LD", [V(x), V(y)]) => Instruction::LoadReg { x: *x, y: *y },
+            ("LD", [I, Number(nnn)]) => Instruction::LoadI {
+                nnn: address(*nnn)?,
+            },
+            ("LD", [V(x), DelayTimer]) => Instruction::LoadDelay { x: *x },
+            ("LD", [V(x), Key]) => Instruction::WaitKey { x: *x },
+            ("LD", [DelayTimer, V(x)]) => Instruction::SetDelay { x: *x },
+            ("LD", [SoundTimer, V(x)]) => Instruction::SetSound { x: *x },
+            ("LD", [Font, V(x)]) => Instruction::LoadFont { x: *x },
+            ("LD", [BigFont, V(x)]) => Instruction::LoadBigFont { x: *x },
+            ("LD", [Flags, V(x)]) => Instruction::StoreFlags { x: *x },
+            ("LD", [V(x), Flags]) => Instruction::LoadFlags { x: *x },
+            ("LD", [Bcd, V(x)]) => Instruction::Bcd { x: *x },
+            ("LD", [IndirectI, V(x)]) => Instruction::Store { x: *x },
+            ("LD", [V(x), IndirectI]) => Instruction::Load { x: *x },
+            ("ADD", [V(x), Number(nn)]) => Instruction::AddByte {
+                x: *x,
+                nn: byte(*nn)?,
+            },
+            ("ADD", [V(x), V(y)]) => Instruction::AddReg { x: *x, y: *y },
+            ("ADD", [I, V(x)]) => Instruction::AddI { x: *x },
+            ("OR", [V(x), V(y)]) => Instruction::Or { x: *x, y: *y },
+            ("AND", [V(x), V(y)]) => Instruction::And { x: *x, y: *y },
+            ("XOR", [V(x), V(y)]) => Instruction::Xor { x: *x, y: *y },
+            ("SUB", [V(x), V(y)]) => Instruction::Sub { x: *x, y: *y },
+            ("SHR", [V(x)]) => Instruction::ShiftRight { x: *x, y: *x },
+            ("SHR", [V(x), V(y)]) => Instruction::ShiftRight { x: *x, y: *y },
+            ("SUBN", [V(x), V(y)]) => Instruction::SubN { x: *x, y: *y },
+            ("SHL", [V(x)]) => Instruction::ShiftLeft { x: *x, y: *x },
+            ("SHL", [V(x), V(y)]) => Instruction::ShiftLeft { x: *x, y: *y },
+            ("RND", [V(x), Number(nn)]) => Instruction::Random {
+                x: *x,
+                nn: byte(*nn)?,
+            },
+            ("DRW", [V(x), V(y), Number(n)]) if *n <= 0xF => Instruction::Draw {
+                x: *x,
+                y: *y,
+                n: *n as u8,
+            },
+            ("SKP", [V(x)]) => Instruction::SkipKey { x: *x },
+            ("SKNP", [V(x)]) => Instruction::SkipNotKey { x: *x },
+            _ => return Err(error()),
+        };
+
+        Ok(instruction)
+    }
+}
+
+#[cfg(test)]
+mod instruction_tests {
+    use super::*;
+
+    #[test]
+    fn decode_and_encode_round_trip() {
+        for opcode in 0..=0xFFFF_u16 {
+            if let Ok(instruction) = Instruction::decode(opcode) {
+                assert_eq!(instruction.encode(), opcode, "{instruction}");
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_bytes_that_end_early() {
+        assert_eq!(
+            Instruction::decode_bytes(&[0xF0, 0x00, 0x12, 0x34]),
+            Ok(Instruction::LoadLongI { nnnn: 0x1234 })
+        );
+        assert_eq!(
+            Instruction::decode_bytes(&[0xF0, 0x00, 0x12]),
+            Err(DecodeError::Truncated)
+        );
+        assert_eq!(
+            Instruction::decode_bytes(&[0x00]),
+            Err(DecodeError::Truncated)
+        );
+    }
+
+    #[test]
+    fn display_and_parse_round_trip() {
+        for opcode in 0..=0xFFFF_u16 {
+            if let Ok(instruction) = Instruction::decode(opcode) {
+                let text = instruction.to_string();
+                assert_eq!(text.parse::<Instruction>(), Ok(instruction), "{text}");
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_operands() {
+        assert_eq!(
+            Instruction::decode(0xD12F),
+            Ok(Instruction::Draw { x: 1, y: 2, n: 0xF })
+        );
+        assert_eq!(
+            Instruction::decode(0x8AB4),
+            Ok(Instruction::AddReg { x: 0xA, y: 0xB })
+        );
+        assert_eq!(
+            Instruction::decode(0x5121),
+            Err(DecodeError::UnknownOpcode(0x5121))
+        );
+        assert_eq!(
+            Instruction::decode(0xF102),
+            Err(DecodeError::UnknownOpcode(0xF102))
+        );
+        assert_eq!(
+            Instruction::decode(0xF265).unwrap().to_string(),
+            "LD V2, [I]"
+        );
+        assert_eq!(
+            Instruction::decode(0x00C4),
+            Ok(Instruction::ScrollDown { n: 4 })
+        );
+        assert_eq!(Instruction::decode(0x00FF), Ok(Instruction::HighRes));
+        assert_eq!(
+            Instruction::decode_bytes(&[0xF0, 0x00, 0x12, 0x34]),
+            Ok(Instruction::LoadLongI { nnnn: 0x1234 })
+        );
+        assert_eq!(
+            "LD I, LONG 0x1234".parse(),
+            Ok(Instruction::LoadLongI { nnnn: 0x1234 })
+        );
+        assert_eq!(
+            Instruction::decode(0x5123),
+            Ok(Instruction::LoadRange { x: 1, y: 2 })
+        );
+        assert_eq!(
+            Instruction::decode(0x00FA),
+            Ok(Instruction::Sys { nnn: 0xFA })
+        );
+    }
+}
@@ -0,0 +1,392 @@
+use std::{fs, path::Path};
+
+use winit::event::VirtualKeyCode;
+
+/*
+ * Key mapping
+ * which keys of the keyboard press which keys of the chip8 hex keypad. Keys are matched by their
+ * meaning rather than their scancode, so the layout follows the keyboard's own layout. There
+ * are two players, both playing the one keypad, as in the two player chip8 games.
+ *
+ * The default puts player 1 on the left of the keyboard:
+ *
+ *   1 2 3 4      1 2 3 C
+ *   Q W E R  ->  4 5 6 D
+ *   A S D F      7 8 9 E
+ *   Z X C V      A 0 B F
+ *
+ * A key file changes it with sections of "chip8 key = keyboard keys" lines. A [rom <name>]
+ * section only applies to roms whose file is called <name>, or whose hash is <name>, and wins
+ * over the sections for every rom wherever it is in the file:
+ *
+ *   # player 2 plays Pong on the arrow keys
+ *   [player 2]
+ *   C = Up
+ *   D = Down
+ *
+ *   [rom Tetris]
+ *   4 = Left
+ *   5 = Up
+ *   6 = Right
+ *   7 = Down
+ *
+ *   [rom Pong (alt), player 2]
+ *   C = Numpad8
+ *
+ * Keys are named as winit names them, Key1, A, Up, Numpad8, Space, and several can be given
+ * separated by spaces. A line with no keyboard keys leaves the chip8 key unmapped.
+ */
+
+// the keys the interpreter uses itself, see frontend.rs
+const RESERVED_KEYS: [VirtualKeyCode; 10] = [
+    VirtualKeyCode::PageUp,
+    VirtualKeyCode::PageDown,
+    VirtualKeyCode::Back,
+    VirtualKeyCode::F1,
+    VirtualKeyCode::F2,
+    VirtualKeyCode::F3,
+    VirtualKeyCode::F4,
+    VirtualKeyCode::F5,
+    VirtualKeyCode::F9,
+    SHOW_MAPPING_KEY,
+];
+
+// toggles the overlay showing the keypad and prints the mapping
+pub const SHOW_MAPPING_KEY: VirtualKeyCode = VirtualKeyCode::F12;
+
+// the key file read when --keys is not given, when there is one
+pub const DEFAULT_KEY_FILE: &str = "keys.cfg";
+
+// the chip8 keypad as it is laid out, row by row
+pub const KEYPAD_LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+// the keyboard keys that can be mapped, by the name a key file uses
+const KEY_NAMES: [VirtualKeyCode; 79] = [
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::Key5,
+    VirtualKeyCode::Key6,
+    VirtualKeyCode::Key7,
+    VirtualKeyCode::Key8,
+    VirtualKeyCode::Key9,
+    VirtualKeyCode::Key0,
+    VirtualKeyCode::A,
+    VirtualKeyCode::B,
+    VirtualKeyCode::C,
+    VirtualKeyCode::D,
+    VirtualKeyCode::E,
+    VirtualKeyCode::F,
+    VirtualKeyCode::G,
+    VirtualKeyCode::H,
+    VirtualKeyCode::I,
+    VirtualKeyCode::J,
+    VirtualKeyCode::K,
+    VirtualKeyCode::L,
+    VirtualKeyCode::M,
+    VirtualKeyCode::N,
+    VirtualKeyCode::O,
+    VirtualKeyCode::P,
+    VirtualKeyCode::Q,
+    VirtualKeyCode::R,
+    VirtualKeyCode::S,
+    VirtualKeyCode::T,
+    VirtualKeyCode::U,
+    VirtualKeyCode::V,
+    VirtualKeyCode::W,
+    VirtualKeyCode::X,
+    VirtualKeyCode::Y,
+    VirtualKeyCode::Z,
+    VirtualKeyCode::Escape,
+    VirtualKeyCode::F6,
+    VirtualKeyCode::F7,
+    VirtualKeyCode::F8,
+    VirtualKeyCode::F10,
+    VirtualKeyCode::F11,
+    VirtualKeyCode::Insert,
+    VirtualKeyCode::Home,
+    VirtualKeyCode::Delete,
+    VirtualKeyCode::End,
+    VirtualKeyCode::Left,
+    VirtualKeyCode::Up,
+    VirtualKeyCode::Right,
+    VirtualKeyCode::Down,
+    VirtualKeyCode::Return,
+    VirtualKeyCode::Space,
+    VirtualKeyCode::Tab,
+    VirtualKeyCode::Numpad0,
+    VirtualKeyCode::Numpad1,
+    VirtualKeyCode::Numpad2,
+    VirtualKeyCode::Numpad3,
+    VirtualKeyCode::Numpad4,
+    VirtualKeyCode::Numpad5,
+    VirtualKeyCode::Numpad6,
+    VirtualKeyCode::Numpad7,
+    VirtualKeyCode::Numpad8,
+    VirtualKeyCode::Numpad9,
+    VirtualKeyCode::NumpadAdd,
+    VirtualKeyCode::NumpadSubtract,
+    VirtualKeyCode::NumpadMultiply,
+    VirtualKeyCode::NumpadDivide,
+    VirtualKeyCode::NumpadDecimal,
+    VirtualKeyCode::NumpadEnter,
+    VirtualKeyCode::Comma,
+    VirtualKeyCode::Period,
+    VirtualKeyCode::Slash,
+    VirtualKeyCode::Semicolon,
+    VirtualKeyCode::Apostrophe,
+    VirtualKeyCode::LBracket,
+    VirtualKeyCode::RBracket,
+    VirtualKeyCode::Minus,
+    VirtualKeyCode::Equals,
+    VirtualKeyCode::LShift,
+];
+
+/* The keyboard keys of both players, by chip8 key */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMap {
+    players: [[Vec<VirtualKeyCode>; 16]; 2],
+}
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl KeyMap {
+    /** The default mapping, player 1 on the left of the keyboard and player 2 unmapped. */
+    pub fn new() -> KeyMap {
+        let mut key_map = KeyMap {
+            players: Default::default(),
+        };
+        let keyboard = [
+            [
+                VirtualKeyCode::Key1,
+                VirtualKeyCode::Key2,
+                VirtualKeyCode::Key3,
+                VirtualKeyCode::Key4,
+            ],
+            [
+                VirtualKeyCode::Q,
+                VirtualKeyCode::W,
+                VirtualKeyCode::E,
+                VirtualKeyCode::R,
+            ],
+            [
+                VirtualKeyCode::A,
+                VirtualKeyCode::S,
+                VirtualKeyCode::D,
+                VirtualKeyCode::F,
+            ],
+            [
+                VirtualKeyCode::Z,
+                VirtualKeyCode::X,
+                VirtualKeyCode::C,
+                VirtualKeyCode::V,
+            ],
+        ];
+        for (row, keys) in KEYPAD_LAYOUT.iter().zip(keyboard) {
+            for (key, code) in row.iter().zip(keys) {
+                key_map.players[0][*key as usize] = vec![code];
+            }
+        }
+        key_map
+    }
+
+    /** Reads a key file over the default mapping, with the overrides for the rom. */
+    pub fn load<P: AsRef<Path>>(path: P, rom_name: &str, rom_hash: u64) -> Result<KeyMap, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        KeyMap::parse(&text, rom_name, rom_hash).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    /** Parses a key file over the default mapping, see the top of the file for the format. */
+    pub fn parse(text: &str, rom_name: &str, rom_hash: u64) -> Result<KeyMap, String> {
+        let hash = format!("{rom_hash:016x}");
+        let mut section = Section::default();
+        let mut lines = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {message}", number + 1);
+
+            if let Some(header) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                section = Section::parse(header).map_err(error)?;
+                continue;
+            }
+
+            let (key, codes) = line.split_once('=').ok_or_else(|| {
+                error(format!("expected \"<chip8 key> = <keys>\", not \"{line}\""))
+            })?;
+            let key = u8::from_str_radix(key.trim(), 16)
+                .ok()
+                .filter(|key| *key < 16)
+                .ok_or_else(|| error(format!("\"{}\" is not a chip8 key", key.trim())))?;
+            let codes = codes
+                .split_whitespace()
+                .map(parse_key_name)
+                .collect::<Result<Vec<VirtualKeyCode>, String>>()
+                .map_err(error)?;
+
+            let applies = match &section.rom {
+                None => true,
+                Some(rom) => rom.eq_ignore_ascii_case(rom_name) || rom.eq_ignore_ascii_case(&hash),
+            };
+            if applies {
+                lines.push((section.rom.is_some(), section.player, key, codes));
+            }
+        }
+
+        // the rom's own lines are applied last, so they win
+        lines.sort_by_key(|(for_rom, ..)| *for_rom);
+        let mut key_map = KeyMap::new();
+        for (_, player, key, codes) in lines {
+            key_map.set(player, key, codes);
+        }
+        Ok(key_map)
+    }
+
+    // maps a chip8 key of a player to the keyboard keys, which are taken from any other key
+    fn set(&mut self, player: usize, key: u8, codes: Vec<VirtualKeyCode>) {
+        for keys in self.players.iter_mut().flatten() {
+            keys.retain(|code| !codes.contains(code));
+        }
+        self.players[player][key as usize] = codes;
+    }
+
+    /** The chip8 key a keyboard key presses, for either player. */
+    pub fn match_key(&self, code: VirtualKeyCode) -> Option<u8> {
+        self.players.iter().find_map(|player| {
+            player
+                .iter()
+                .position(|keys| keys.contains(&code))
+                .map(|key| key as u8)
+        })
+    }
+
+    /** Lays out the keypad of each player with the keyboard keys that press it. */
+    pub fn describe(&self) -> String {
+        let mut text = String::new();
+        for (number, player) in self.players.iter().enumerate() {
+            if player.iter().all(Vec::is_empty) {
+                continue;
+            }
+            text.push_str(&format!("player {}\n", number + 1));
+            for row in KEYPAD_LAYOUT {
+                let cells = row
+                    .iter()
+                    .map(|key| {
+                        let keys = player[*key as usize]
+                            .iter()
+                            .map(|code| format!("{code:?}"))
+                            .collect::<Vec<String>>();
+                        let keys = if keys.is_empty() {
+                            String::from("-")
+                        } else {
+                            keys.join(" ")
+                        };
+                        format!("{key:X}: {keys:<12}")
+                    })
+                    .collect::<Vec<String>>();
+                text.push_str(&format!("  {}\n", cells.join(" ").trim_end()));
+            }
+        }
+        text.trim_end().to_string()
+    }
+}
+
+/* The rom and player the lines of a key file are for */
+#[derive(Debug, Default)]
+struct Section {
+    rom: Option<String>,
+    player: usize,
+}
+impl Section {
+    // parses "player 2", "rom Pong" or "rom Pong, player 2"
+    fn parse(header: &str) -> Result<Section, String> {
+        let (rom, player) = match header.trim().strip_prefix("rom ") {
+            Some(rest) => match rest.rsplit_once(',') {
+                Some((rom, player)) if player.trim().starts_with("player") => (Some(rom), player),
+                _ => (Some(rest), "player 1"),
+            },
+            None => (None, header),
+        };
+        let player = match player.split_whitespace().collect::<Vec<&str>>()[..] {
+            ["player", "1"] => 0,
+            ["player", "2"] => 1,
+            _ => {
+                return Err(format!(
+                    "expected [player 1], [player 2], [rom <name>] or [rom <name>, player 2], not [{header}]"
+                ))
+            }
+        };
+        Ok(Section {
+            rom: rom.map(|rom| rom.trim().to_string()),
+            player,
+        })
+    }
+}
+
+// finds the keyboard key with the name winit gives it, ignoring case
+fn parse_key_name(name: &str) -> Result<VirtualKeyCode, String> {
+    let code = KEY_NAMES
+        .iter()
+        .chain(&RESERVED_KEYS)
+        .find(|code| format!("{code:?}").eq_ignore_ascii_case(name))
+        .ok_or(format!("unknown key \"{name}\""))?;
+    if RESERVED_KEYS.contains(code) {
+        return Err(format!("{code:?} is used by the interpreter"));
+    }
+    Ok(*code)
+}
+
+#[cfg(test)]
+mod keyboard_tests {
+    use super::*;
+
+    const KEY_FILE: &str = "
+        [player 2]
+        c = Up
+        D = Down   # the paddle
+
+        [rom tetris]
+        5 = up space
+        [rom Pong, player 2]
+        C = Numpad8
+    ";
+
+    #[test]
+    fn reads_players_and_rom_overrides() {
+        let key_map = KeyMap::parse(KEY_FILE, "Tetris", 0).unwrap();
+        assert_eq!(key_map.match_key(VirtualKeyCode::Key1), Some(0x1));
+        assert_eq!(key_map.match_key(VirtualKeyCode::Down), Some(0xD));
+        // the rom section takes Up from player 2, and W from 5
+        assert_eq!(key_map.match_key(VirtualKeyCode::Up), Some(0x5));
+        assert_eq!(key_map.match_key(VirtualKeyCode::Space), Some(0x5));
+        assert_eq!(key_map.match_key(VirtualKeyCode::W), None);
+
+        let key_map = KeyMap::parse(KEY_FILE, "pong", 0).unwrap();
+        assert_eq!(key_map.match_key(VirtualKeyCode::Numpad8), Some(0xC));
+        assert_eq!(key_map.match_key(VirtualKeyCode::Up), None);
+        assert!(key_map.describe().contains("player 2\n  1: -"));
+
+        let rom = "Tetris [Fran Dachille, 1991]";
+        let key_map = KeyMap::parse("[rom Tetris [Fran Dachille, 1991]]\n5 = Up", rom, 0).unwrap();
+        assert_eq!(key_map.match_key(VirtualKeyCode::Up), Some(0x5));
+
+        assert_eq!(
+            KeyMap::parse("[player 1]\n5 = F5", "pong", 0).unwrap_err(),
+            "line 2: F5 is used by the interpreter"
+        );
+    }
+}
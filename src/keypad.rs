@@ -0,0 +1,127 @@
+/*
+ * Keypad
+ * the 16 keys of the chip8 hex keypad, 0x0 to 0xF, each pressed or released on its own
+ *
+ *   1 2 3 C
+ *   4 5 6 D
+ *   7 8 9 E
+ *   A 0 B F
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Keypad {
+    // bit n is set while key n is held
+    pressed: u16,
+    // bit n is set when key n went down during this frame, so a tap between two frames is not
+    // missed by FX0A
+    presses: u16,
+    // the key FX0A saw pressed and is waiting to be released
+    waiting: Option<u8>,
+}
+impl Keypad {
+    pub fn new() -> Keypad {
+        Keypad::default()
+    }
+
+    /** Presses or releases a key, only the low 4 bits of the key are used. */
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        let bit = 1 << (key & 0xF);
+        if pressed {
+            self.presses |= bit & !self.pressed;
+            self.pressed |= bit;
+        } else {
+            self.pressed &= !bit;
+        }
+    }
+
+    /** Returns true while the key is held, only the low 4 bits of the key are used. */
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.pressed & 1 << (key & 0xF) > 0
+    }
+
+    /** The keys held, lowest first. */
+    pub fn pressed_keys(&self) -> Vec<u8> {
+        (0..16).filter(|key| self.is_pressed(*key)).collect()
+    }
+
+    /**
+     * Runs FX0A the way the COSMAC VIP does: it waits for a key to be pressed, then for that key
+     * to be released, and returns it once it has been. Returns None while still waiting.
+     */
+    pub fn wait_for_key(&mut self) -> Option<u8> {
+        match self.waiting {
+            None => {
+                let down = self.pressed | self.presses;
+                self.presses = 0;
+                if down > 0 {
+                    self.waiting = Some(down.trailing_zeros() as u8);
+                }
+                None
+            }
+            Some(key) if self.is_pressed(key) => None,
+            Some(key) => {
+                self.waiting = None;
+                Some(key)
+            }
+        }
+    }
+
+    /** Forgets the presses of the frame that has ended. */
+    pub fn end_frame(&mut self) {
+        self.presses = 0;
+    }
+
+    /** The keys held, the presses of this frame and the key FX0A is waiting on, for save states. */
+    pub fn get_state(&self) -> (u16, u16, Option<u8>) {
+        (self.pressed, self.presses, self.waiting)
+    }
+
+    /** Restores the state returned by get_state. */
+    pub fn set_state(&mut self, pressed: u16, presses: u16, waiting: Option<u8>) {
+        self.pressed = pressed;
+        self.presses = presses;
+        self.waiting = waiting.map(|key| key & 0xF);
+    }
+}
+
+#[cfg(test)]
+mod keypad_tests {
+    use crate::machine::Machine;
+
+    use super::*;
+
+    #[test]
+    fn keys_are_independent() {
+        let mut keypad = Keypad::new();
+        keypad.set_key(0x1, true);
+        keypad.set_key(0xF, true);
+        keypad.set_key(0x1, false);
+        assert!(!keypad.is_pressed(0x1));
+        assert!(keypad.is_pressed(0xF));
+        assert_eq!(keypad.pressed_keys(), [0xF]);
+    }
+
+    #[test]
+    fn wait_key_stores_the_key_once_it_is_released() {
+        // F30A v3 := key, 1202 loop
+        let rom = [0xF3, 0x0A, 0x12, 0x02];
+        let mut machine = Machine::new(64, 32, &rom).unwrap();
+        machine.run_frame().unwrap();
+        assert_eq!(machine.program_counter().get_pc(), 0x200);
+
+        machine.set_key(0xB, true);
+        machine.run_frame().unwrap();
+        assert_eq!(machine.program_counter().get_pc(), 0x200);
+
+        machine.set_key(0xB, false);
+        machine.run_frame().unwrap();
+        assert_eq!(machine.program_counter().get_pc(), 0x202);
+        assert_eq!(*machine.registers().get_register(3).unwrap(), 0xB);
+
+        // a tap between two frames is seen too
+        let mut machine = Machine::new(64, 32, &rom).unwrap();
+        machine.set_key(0x4, true);
+        machine.set_key(0x4, false);
+        machine.run_frame().unwrap();
+        assert_eq!(*machine.registers().get_register(3).unwrap(), 0x4);
+    }
+}
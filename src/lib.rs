@@ -0,0 +1,64 @@
+//! A chip 8 interpreter.
+//!
+//! The interpreter is built around [`Machine`], which owns the memory, registers, stack, program
+//! counter and display of a running program. It has no window or keyboard of its own, so it can be
+//! embedded in tools, tests and servers as well as the winit frontend that ships with this crate.
+//!
+//! ```no_run
+//! use chip8::{read_rom, Machine, HEIGHT, WIDTH};
+//!
+//! let rom = read_rom("roms/2-ibm-logo.ch8").unwrap();
+//! let mut machine = Machine::new(WIDTH, HEIGHT, &rom).unwrap();
+//!
+//! // press the chip8 "5" key and run a 60th of a second worth of instructions
+//! machine.set_key(0x5, true);
+//! machine.run_frame().unwrap();
+//!
+//! // one byte per pixel, row by row
+//! let lit = machine.framebuffer().iter().filter(|pixel| **pixel > 0).count();
+//! println!("{lit} pixels are on");
+//! ```
+
+pub mod assembler;
+pub mod audio;
+pub mod dap;
+pub mod debugger;
+pub mod disassembler;
+pub mod draw;
+pub mod emulator;
+pub mod error;
+pub mod font;
+pub mod gdb;
+pub mod hash;
+pub mod instruction;
+pub mod keypad;
+pub mod machine;
+pub mod memory;
+pub mod movie;
+pub mod platform;
+pub mod program_counter;
+pub mod quirks;
+pub mod registers;
+pub mod rewind;
+pub mod rng;
+pub mod rom;
+pub mod save_state;
+pub mod scheduler;
+pub mod sound_timer;
+pub mod stack;
+pub mod trace;
+pub mod trace_diff;
+
+pub use crate::{
+    debugger::Debugger,
+    error::Chip8Error,
+    instruction::{DecodeError, Instruction},
+    machine::{Machine, FRAMES_PER_SECOND, HEIGHT, INSTRUCTIONS_PER_SECOND, PROGRAM_START, WIDTH},
+    movie::{Movie, MoviePlayer},
+    platform::Platform,
+    quirks::{MemoryIncrement, Quirks},
+    rewind::Rewind,
+    rom::{load_rom, read_rom},
+    save_state::{SaveSlots, SaveState},
+    scheduler::Scheduler,
+};
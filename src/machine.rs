@@ -0,0 +1,659 @@
+use std::{fs, path::PathBuf};
+
+use crate::{
+    audio::{AudioSink, Buzzer, NullSink, Tone, DEFAULT_PITCH, PATTERN_LENGTH},
+    emulator::{execute, fetch},
+    error::Chip8Error,
+    hash::fnv1a,
+    instruction::Instruction,
+    keypad::Keypad,
+    memory::Memory,
+    platform::Platform,
+    program_counter::ProgramCounter,
+    quirks::Quirks,
+    registers::Registers,
+    rng::{Rng, RngKind},
+    sound_timer::SoundTimer,
+    stack::Stack,
+    trace::{TraceEntry, Tracer, TRACE_TARGET},
+};
+
+// the address most chip8 programs are loaded at, see the memory map in memory.rs
+pub const PROGRAM_START: u16 = 0x200;
+
+// the original chip8 display is 64 x 32 pixels monochrome, see Platform for the SUPER-CHIP display
+pub const WIDTH: u32 = 64;
+pub const HEIGHT: u32 = 32;
+
+// the default speed, see Machine::set_instructions_per_frame
+pub const INSTRUCTIONS_PER_SECOND: u32 = 700;
+pub const FRAMES_PER_SECOND: u32 = 60;
+
+/*
+ * Machine
+ * owns the complete state of a running chip8 program. It has no knowledge of windows, pixels or
+ * keyboards, so it can be driven by the winit frontend, by tests or by tools with no display.
+ */
+pub struct Machine {
+    pub(crate) memory: Memory,
+    pub(crate) stack: Stack,
+    pub(crate) registers: Registers,
+    pub(crate) program_counter: ProgramCounter,
+    // one byte per pixel, 0 is off and 1 is on. On XO-CHIP bit 0 is plane 1 and bit 1 is plane 2
+    pub(crate) framebuffer: Vec<u8>,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) keypad: Keypad,
+    pub(crate) display_changed: bool,
+    pub(crate) quirks: Quirks,
+    // set by DXYN when the display wait quirk is on, cleared at the start of the next frame
+    pub(crate) waiting_for_frame: bool,
+    pub(crate) platform: Platform,
+    // SUPER-CHIP high resolution mode, in low resolution every pixel is drawn 2 x 2
+    pub(crate) hires: bool,
+    // set by 00FD, nothing more runs once the program has exited
+    pub(crate) exited: bool,
+    // the RPL user flags written by FX75, saved to rpl_file when there is one
+    pub(crate) rpl_flags: [u8; 16],
+    pub(crate) rpl_file: Option<PathBuf>,
+    // the XO-CHIP bit planes selected by FN01
+    pub(crate) planes: u8,
+    // the XO-CHIP audio pattern loaded by F002 and its pitch set by FX3A
+    pub(crate) audio_pattern: [u8; PATTERN_LENGTH],
+    pub(crate) pitch: u8,
+    // set by FX18, the buzzer sounds while it is running
+    pub(crate) sound_timer: SoundTimer,
+    // the random numbers for CXNN, and the seed they started from
+    pub(crate) rng: Rng,
+    seed: u64,
+    // the number of frames run so far, movies tag their inputs with it
+    pub(crate) frame_count: u64,
+    // the number of instructions executed so far
+    pub(crate) cycle_count: u64,
+    // the number of instructions executed in the current frame
+    pub(crate) frame_step: u32,
+    buzzer: Buzzer,
+    // where the buzzer samples of every frame go, a NullSink unless one is set
+    audio_sink: Box<dyn AudioSink>,
+    // the size of the rom loaded at PROGRAM_START, kept in save states
+    pub(crate) rom_length: usize,
+    instructions_per_frame: u32,
+    // writes a line for every instruction when set, see trace.rs
+    tracer: Option<Tracer>,
+}
+impl Machine {
+    /** Creates a machine with the fonts loaded and the rom copied to the program space at 0x200. */
+    pub fn new(width: u32, height: u32, rom: &[u8]) -> Result<Machine, Chip8Error> {
+        Machine::with_memory(width, height, Memory::new(), rom)
+    }
+
+    /** Creates a machine for the given platform, with its display size, memory size and quirks. */
+    pub fn for_platform(platform: Platform, rom: &[u8]) -> Result<Machine, Chip8Error> {
+        let memory = Memory::with_size(platform.memory_size());
+        let mut machine = Machine::with_memory(platform.width(), platform.height(), memory, rom)?;
+        machine.platform = platform;
+        machine.quirks = platform.quirks();
+        machine.stack = Stack::with_depth(platform.stack_depth());
+        Ok(machine)
+    }
+
+    fn with_memory(
+        width: u32,
+        height: u32,
+        mut memory: Memory,
+        rom: &[u8],
+    ) -> Result<Machine, Chip8Error> {
+        memory.set_rom(rom)?;
+        memory.set_fonts();
+
+        let mut program_counter = ProgramCounter::new();
+        program_counter.set_counter(PROGRAM_START);
+
+        let seed = rand::random();
+        Ok(Machine {
+            memory,
+            stack: Stack::with_depth(Platform::Chip8.stack_depth()),
+            registers: Registers::new(),
+            program_counter,
+            framebuffer: vec![0; (width * height) as usize],
+            width,
+            height,
+            keypad: Keypad::new(),
+            display_changed: false,
+            quirks: Quirks::default(),
+            waiting_for_frame: false,
+            platform: Platform::Chip8,
+            hires: false,
+            exited: false,
+            rpl_flags: [0; 16],
+            rpl_file: None,
+            planes: 1,
+            audio_pattern: [0; PATTERN_LENGTH],
+            pitch: DEFAULT_PITCH,
+            sound_timer: SoundTimer::new(),
+            rng: Rng::new(seed),
+            seed,
+            frame_count: 0,
+            cycle_count: 0,
+            frame_step: 0,
+            buzzer: Buzzer::default(),
+            audio_sink: Box::new(NullSink::new()),
+            rom_length: rom.len(),
+            instructions_per_frame: INSTRUCTIONS_PER_SECOND / FRAMES_PER_SECOND,
+            tracer: None,
+        })
+    }
+
+    /**
+     * Fetches and executes a single instruction. When it fails the program counter is left on it,
+     * so stepping again fails the same way.
+     */
+    pub fn step(&mut self) -> Result<(), Chip8Error> {
+        if self.exited {
+            return Ok(());
+        }
+        if self.tracer.is_some() || log::log_enabled!(target: TRACE_TARGET, log::Level::Trace) {
+            self.trace();
+        }
+
+        let pc = self.program_counter.get_pc() as u16;
+        let opcode = match fetch(self.memory.as_slice(), &mut self.program_counter) {
+            Ok(opcode) => opcode,
+            Err(error) => {
+                self.dump_trace(&error.to_string());
+                return Err(error);
+            }
+        };
+
+        let executed = match Instruction::decode(opcode) {
+            // the address of F000 NNNN is in the two bytes after it
+            Ok(Instruction::LoadLongI { .. }) if self.platform.supports_xochip() => {
+                // the program counter wraps to 0 after the last instruction in 64K of memory
+                let next = pc as usize + 2;
+                match self.memory.as_slice().get(next..next + 2) {
+                    Some(&[high, low]) => {
+                        self.program_counter.increment_by(2);
+                        let nnnn = (high as u16) << 8 | low as u16;
+                        execute(Instruction::LoadLongI { nnnn }, pc, self)
+                    }
+                    // F000 is in the last two bytes of memory, the address after it is not
+                    _ => Err(Chip8Error::MemoryOutOfBounds {
+                        pc,
+                        opcode,
+                        address: self.memory.size(),
+                    }),
+                }
+            }
+            Ok(instruction) => execute(instruction, pc, self),
+            Err(_) => Err(Chip8Error::UnknownOpcode { pc, opcode }),
+        };
+        if let Err(error) = executed {
+            self.program_counter.set_counter(pc);
+            self.dump_trace(&error.to_string());
+            return Err(error);
+        }
+        self.cycle_count += 1;
+        Ok(())
+    }
+
+    // traces the instruction about to run
+    fn trace(&mut self) {
+        let entry = TraceEntry::capture(self);
+        match &mut self.tracer {
+            Some(tracer) if tracer.wants(entry.pc, entry.opcode) => tracer.record(entry),
+            Some(_) => {}
+            None => log::trace!(target: TRACE_TARGET, "{entry}"),
+        }
+    }
+
+    /** Traces every instruction from now on with the tracer, or stops tracing with None. */
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    /** Writes out the instructions a tracer keeps, when it only keeps the last few. */
+    pub fn dump_trace(&mut self, reason: &str) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.dump(reason);
+        }
+    }
+
+    /** Executes one 60th of a second worth of instructions, then ticks the delay and sound timers. */
+    pub fn run_frame(&mut self) -> Result<(), Chip8Error> {
+        while !self.step_in_frame()? {}
+        Ok(())
+    }
+
+    /**
+     * Executes one instruction as part of the current frame, and ends the frame once it has run
+     * all of its instructions, the program waits for the display or exits. Returns true when the
+     * frame ended. Debuggers use this to stop part way through a frame without changing timing.
+     */
+    pub fn step_in_frame(&mut self) -> Result<bool, Chip8Error> {
+        if self.frame_step == 0 {
+            self.waiting_for_frame = false;
+        }
+
+        self.step()?;
+        self.frame_step += 1;
+        if self.frame_step < self.instructions_per_frame && !self.waiting_for_frame && !self.exited
+        {
+            return Ok(false);
+        }
+
+        self.frame_step = 0;
+        self.play_frame_audio();
+        self.registers.tick_delay_timer();
+        self.keypad.end_frame();
+        self.frame_count += 1;
+        Ok(true)
+    }
+
+    /** The number of frames run since the machine was created. */
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /** The number of instructions run since the machine was created. */
+    pub fn cycle_count(&self) -> u64 {
+        self.cycle_count
+    }
+
+    /**
+     * A hash of the whole state of the machine, see save_state.rs. Two runs of the same rom with
+     * the same seed, settings and inputs end with the same hash.
+     */
+    pub fn state_hash(&self) -> u64 {
+        fnv1a(self.save_state().as_bytes())
+    }
+
+    // hands a frame of buzzer samples to the audio sink and counts the sound timer down
+    fn play_frame_audio(&mut self) {
+        let sample_rate = self.audio_sink.sample_rate();
+        let mut samples = vec![0.0; (sample_rate / FRAMES_PER_SECOND) as usize];
+        let sounding = self.sound_timer.is_active();
+
+        // XO-CHIP programs that have loaded a pattern play it instead of the tone
+        let pattern = self.platform.supports_xochip() && self.audio_pattern.iter().any(|b| *b > 0);
+        if sounding && pattern {
+            self.buzzer
+                .fill_pattern(&self.audio_pattern, self.pitch, sample_rate, &mut samples);
+        } else {
+            self.buzzer.fill_tone(sounding, sample_rate, &mut samples);
+        }
+
+        if let Err(err) = self.audio_sink.play(&samples) {
+            log::warn!("could not play audio: {err}");
+        }
+        self.sound_timer.tick();
+    }
+
+    /** The number of instructions run_frame executes, the speed of the machine. */
+    pub fn instructions_per_frame(&self) -> u32 {
+        self.instructions_per_frame
+    }
+
+    /** Sets the speed of the machine, it can be changed while a program runs. */
+    pub fn set_instructions_per_frame(&mut self, instructions: u32) {
+        self.instructions_per_frame = instructions.max(1);
+    }
+
+    /** The interpreter behaviours the machine follows, see quirks.rs. */
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    /** Returns true when the SUPER-CHIP high resolution mode is on. */
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
+
+    /** Returns true once the program has run 00FD. */
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /** The XO-CHIP bit planes drawing acts on, 1 and 2 are the single planes and 3 is both. */
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    /** The XO-CHIP audio pattern, 128 one bit samples. */
+    pub fn audio_pattern(&self) -> &[u8; PATTERN_LENGTH] {
+        &self.audio_pattern
+    }
+
+    /** The XO-CHIP audio pitch, see audio::pattern_rate. */
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /** The seed the random numbers for CXNN started from, a random one unless set_seed was called. */
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /** Restarts the random numbers for CXNN from the given seed. */
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = Rng::with_kind(seed, self.rng.kind());
+    }
+
+    /** Picks the random number generator for CXNN, restarting it from the seed. */
+    pub fn set_rng_kind(&mut self, kind: RngKind) {
+        self.rng = Rng::with_kind(self.seed, kind);
+    }
+
+    pub fn rng(&self) -> &Rng {
+        &self.rng
+    }
+
+    pub fn rng_mut(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    pub fn sound_timer(&self) -> &SoundTimer {
+        &self.sound_timer
+    }
+
+    pub fn sound_timer_mut(&mut self) -> &mut SoundTimer {
+        &mut self.sound_timer
+    }
+
+    /** Sends the buzzer samples of every frame to the given sink, see audio.rs. */
+    pub fn set_audio_sink(&mut self, audio_sink: Box<dyn AudioSink>) {
+        self.audio_sink = audio_sink;
+    }
+
+    /** Drops the audio sink for a NullSink, so a WAV file being written is finished. */
+    pub fn close_audio_sink(&mut self) {
+        self.audio_sink = Box::new(NullSink::new());
+    }
+
+    /** The frequency, waveform and volume of the buzzer. */
+    pub fn tone(&self) -> Tone {
+        self.buzzer.tone()
+    }
+
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.buzzer.set_tone(tone);
+    }
+
+    pub fn rpl_flags(&self) -> &[u8; 16] {
+        &self.rpl_flags
+    }
+
+    /**
+     * Keeps the RPL user flags in the given file, so high scores survive between runs.
+     * The flags are read from the file now if it exists, and written to it on every FX75.
+     */
+    pub fn set_rpl_file<P: Into<PathBuf>>(&mut self, path: P) -> Result<(), String> {
+        let path = path.into();
+        if path.exists() {
+            let flags = fs::read(&path).map_err(|err| format!("{}: {err}", path.display()))?;
+            let len = flags.len().min(self.rpl_flags.len());
+            self.rpl_flags[..len].copy_from_slice(&flags[..len]);
+        }
+        self.rpl_file = Some(path);
+        Ok(())
+    }
+
+    /** The display, one byte per pixel laid out row by row. A pixel is on when its byte is non zero. */
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    /** The width of the display in pixels. */
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /** The height of the display in pixels. */
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    pub fn stack(&self) -> &Stack {
+        &self.stack
+    }
+
+    pub fn stack_mut(&mut self) -> &mut Stack {
+        &mut self.stack
+    }
+
+    pub fn program_counter(&self) -> &ProgramCounter {
+        &self.program_counter
+    }
+
+    pub fn program_counter_mut(&mut self) -> &mut ProgramCounter {
+        &mut self.program_counter
+    }
+
+    /** Returns true when the display has changed since the last call. */
+    pub fn take_display_changed(&mut self) -> bool {
+        let changed = self.display_changed;
+        self.display_changed = false;
+        changed
+    }
+
+    /** Presses or releases one of the 16 chip8 keys (0x0 to 0xF). */
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.keypad.set_key(key, pressed);
+    }
+
+    pub fn keypad(&self) -> &Keypad {
+        &self.keypad
+    }
+}
+
+#[cfg(test)]
+mod machine_tests {
+    use std::{cell::RefCell, io, rc::Rc};
+
+    use super::*;
+
+    // keeps every sample it is given
+    struct RecordingSink {
+        samples: Rc<RefCell<Vec<f32>>>,
+    }
+    impl AudioSink for RecordingSink {
+        fn sample_rate(&self) -> u32 {
+            600
+        }
+
+        fn play(&mut self, samples: &[f32]) -> io::Result<()> {
+            self.samples.borrow_mut().extend_from_slice(samples);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn runs_without_a_window() {
+        // 00E0 clear, A20A I := sprite, 6000 v0 := 0, D001 draw one row, 1208 loop
+        let rom = [
+            0x00, 0xE0, 0xA2, 0x0A, 0x60, 0x00, 0xD0, 0x01, 0x12, 0x08, 0xF0,
+        ];
+        let mut machine = Machine::new(64, 32, &rom).unwrap();
+
+        machine.run_frame().unwrap();
+
+        assert!(machine.take_display_changed());
+        assert_eq!(machine.framebuffer()[0..5], [1, 1, 1, 1, 0]);
+        assert!(machine.framebuffer()[5..].iter().all(|pixel| *pixel == 0));
+    }
+
+    #[test]
+    fn set_key_is_seen_by_the_program() {
+        // 6105 v1 := 5, E19E skip if key v1, 1202 loop, 1206 done
+        let rom = [0x61, 0x05, 0xE1, 0x9E, 0x12, 0x02, 0x12, 0x06];
+        let mut machine = Machine::new(64, 32, &rom).unwrap();
+
+        machine.run_frame().unwrap();
+        assert_eq!(machine.program_counter.get_pc(), 0x202);
+
+        machine.set_key(0x5, true);
+        machine.run_frame().unwrap();
+        assert_eq!(machine.program_counter.get_pc(), 0x206);
+    }
+
+    #[test]
+    fn follows_the_selected_quirks() {
+        // 6105 v1 := 5, 6203 v2 := 3, 8126 v1 >>= v2, A300 i := 0x300, F155 save v1
+        let rom = [0x61, 0x05, 0x62, 0x03, 0x81, 0x26, 0xA3, 0x00, 0xF1, 0x55];
+
+        let mut vip = Machine::new(64, 32, &rom).unwrap();
+        vip.set_quirks(Quirks::VIP);
+        (0..5).for_each(|_| vip.step().unwrap());
+        assert_eq!(*vip.registers().get_register(1).unwrap(), 1);
+        assert_eq!(*vip.registers().get_i_register(), 0x302);
+
+        let mut schip = Machine::new(64, 32, &rom).unwrap();
+        schip.set_quirks(Quirks::SCHIP);
+        (0..5).for_each(|_| schip.step().unwrap());
+        assert_eq!(*schip.registers().get_register(1).unwrap(), 2);
+        assert_eq!(*schip.registers().get_i_register(), 0x300);
+        // CHIP-48 leaves I on the last register saved
+        let mut chip48 = Machine::new(64, 32, &rom).unwrap();
+        chip48.set_quirks(Quirks::CHIP48);
+        (0..5).for_each(|_| chip48.step().unwrap());
+        assert_eq!(*chip48.registers().get_i_register(), 0x301);
+    }
+
+    #[test]
+    fn runs_super_chip_programs() {
+        // 00FF hires, A300 i := 0x300, D010 16x16 sprite, 00C4 scroll down 4, 00FD exit
+        let mut rom = vec![0x00, 0xFF, 0xA3, 0x00, 0xD0, 0x10, 0x00, 0xC4, 0x00, 0xFD];
+        rom.resize(0x100, 0);
+        rom.extend([0xFF; 32]);
+        let mut machine = Machine::for_platform(Platform::SuperChip, &rom).unwrap();
+
+        machine.run_frame().unwrap();
+
+        assert!(machine.hires() && machine.has_exited());
+        assert_eq!((machine.width(), machine.height()), (128, 64));
+        let lit = |x: usize, y: usize| machine.framebuffer()[y * 128 + x] == 1;
+        assert!(!lit(0, 3) && lit(0, 4) && lit(15, 19) && !lit(16, 4) && !lit(0, 20));
+
+        // the same sprite in low resolution covers 32 x 32 screen pixels, and scrolls down by
+        // 4 screen pixels, which is half a pixel per row of 00C4
+        rom[1] = 0xFE;
+        let mut machine = Machine::for_platform(Platform::SuperChip, &rom).unwrap();
+        machine.run_frame().unwrap();
+        let lit = |x: usize, y: usize| machine.framebuffer()[y * 128 + x] == 1;
+        assert!(!lit(0, 3) && lit(31, 4) && !lit(32, 4) && lit(0, 35) && !lit(0, 36));
+
+        // XO-CHIP scrolls by whole low resolution pixels
+        let mut machine = Machine::for_platform(Platform::XoChip, &rom).unwrap();
+        machine.run_frame().unwrap();
+        let lit = |x: usize, y: usize| machine.framebuffer()[y * 128 + x] == 1;
+        assert!(!lit(0, 7) && lit(31, 8) && !lit(32, 8) && lit(0, 39) && !lit(0, 40));
+    }
+
+    #[test]
+    fn runs_xo_chip_programs() {
+        // F000 1000 i := long 0x1000, F301 plane 3, D011 draw on both planes, 6102 v1 := 2
+        let mut rom = vec![0xF0, 0x00, 0x10, 0x00, 0xF3, 0x01, 0xD0, 0x11, 0x61, 0x02];
+        // 3102 skip if v1 == 2, F000 0000 skipped as a whole, 5012 save v0 - v1, 00FD exit
+        rom.extend([0x31, 0x02, 0xF0, 0x00, 0x00, 0x00, 0x50, 0x12, 0x00, 0xFD]);
+        rom.resize(0x1000 - 0x200, 0);
+        // plane 1 gets the left pixel and plane 2 both
+        rom.extend([0x80, 0xC0]);
+        let mut machine = Machine::for_platform(Platform::XoChip, &rom).unwrap();
+
+        machine.run_frame().unwrap();
+
+        assert!(machine.has_exited());
+        assert_eq!(machine.framebuffer()[..6], [3, 3, 2, 2, 0, 0]);
+        assert_eq!(*machine.registers().get_i_register(), 0x1000);
+        assert_eq!(
+            (machine.memory().peek(0x1000), machine.memory().peek(0x1001)),
+            (Some(0), Some(2))
+        );
+
+        // an F000 in the last two bytes of memory has no address to load
+        machine.memory_mut().get_memory()[0xFFFE..].copy_from_slice(&[0xF0, 0x00]);
+        machine.program_counter.set_counter(0xFFFE);
+        machine.exited = false;
+        assert_eq!(
+            machine.step(),
+            Err(Chip8Error::MemoryOutOfBounds {
+                pc: 0xFFFE,
+                opcode: 0xF000,
+                address: 0x10000
+            })
+        );
+        assert_eq!(machine.program_counter().get_pc(), 0xFFFE);
+    }
+
+    #[test]
+    fn sounds_the_buzzer_while_the_sound_timer_runs() {
+        // 6002 v0 := 2, F018 buzzer := v0, 1204 loop
+        let rom = [0x60, 0x02, 0xF0, 0x18, 0x12, 0x04];
+        let mut machine = Machine::new(64, 32, &rom).unwrap();
+        let samples = Rc::new(RefCell::new(Vec::new()));
+        machine.set_audio_sink(Box::new(RecordingSink {
+            samples: samples.clone(),
+        }));
+
+        (0..3).for_each(|_| machine.run_frame().unwrap());
+
+        // ten samples a frame, two frames of tone then silence
+        let samples = samples.borrow();
+        assert_eq!(samples.len(), 30);
+        assert!(samples[..20].iter().any(|sample| *sample != 0.0));
+        assert!(samples[20..].iter().all(|sample| *sample == 0.0));
+        assert!(!machine.sound_timer().is_active());
+    }
+
+    #[test]
+    fn delay_timer_ticks_once_per_frame() {
+        // 603C v0 := 60, F015 delay := v0, F107 v1 := delay, 1204 loop
+        let rom = [0x60, 0x3C, 0xF0, 0x15, 0xF1, 0x07, 0x12, 0x04];
+        let mut machine = Machine::new(64, 32, &rom).unwrap();
+
+        (0..10).for_each(|_| machine.run_frame().unwrap());
+        assert_eq!(*machine.registers().get_delay_timer(), 50);
+
+        (0..100).for_each(|_| machine.run_frame().unwrap());
+        assert_eq!(*machine.registers().get_delay_timer(), 0);
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_run() {
+        // C0FF v0 := random, 8104 v1 += v0, 1200 loop
+        let rom = [0xC0, 0xFF, 0x81, 0x04, 0x12, 0x00];
+        let run = |seed: u64| {
+            let mut machine = Machine::new(64, 32, &rom).unwrap();
+            machine.set_seed(seed);
+            (0..30).for_each(|_| machine.run_frame().unwrap());
+            machine.state_hash()
+        };
+
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+    }
+}
@@ -1,57 +1,591 @@
 use std::{
     env,
     fs::File,
-    io::{self, Read},
+    io::{self, BufReader},
+    net::TcpListener,
+    path::Path,
     process,
 };
 
-use crate::chip8::chip8;
+use chip8::{
+    audio::{Tone, WavSink, SAMPLE_RATE},
+    dap::DapServer,
+    disassembler::{disassemble, Syntax},
+    error::crash_report,
+    gdb::GdbStub,
+    hash::fnv1a,
+    load_rom,
+    rng::RngKind,
+    stack::StackPolicy,
+    trace::{TraceFilter, Tracer},
+    trace_diff::{first_divergence, TraceFormat},
+    Debugger, Machine, Movie, MoviePlayer, Platform, Quirks, SaveSlots,
+};
+
+use crate::{
+    frontend::{chip8, Debugging, MovieMode},
+    keyboard::{KeyMap, DEFAULT_KEY_FILE},
+};
 
-mod chip8;
 mod display;
-mod draw;
-mod emulator;
-mod font;
-mod memory;
-mod program_counter;
-mod registers;
-mod stack;
+mod frontend;
+mod keyboard;
+
+// the run subcommand runs for 10 seconds when no number of frames is given
+const DEFAULT_RUN_FRAMES: u64 = 600;
+
+// the number of matching lines trace-diff shows before the first divergence
+const DEFAULT_DIFF_CONTEXT: usize = 5;
+
+/* The options the interpreter is run with */
+struct Options {
+    rom_file_path: String,
+    platform: Platform,
+    // the platform's own quirks are used when none are given
+    quirks: Option<Quirks>,
+    // what a call with the stack full or a return with it empty does
+    stack_policy: StackPolicy,
+    // the buzzer is written to this file when one is given
+    wav_file_path: Option<String>,
+    tone: Tone,
+    // instructions per frame, the platform default when none is given
+    speed: Option<u32>,
+    // a movie file to record the keypad to, or to replay it from
+    record_file_path: Option<String>,
+    replay_file_path: Option<String>,
+    // the random numbers start from a random seed when none is given
+    seed: Option<u64>,
+    rng_kind: RngKind,
+    // the number of frames the run subcommand runs for
+    frames: Option<u64>,
+    // start paused, with commands for the debugger read from the terminal
+    debug: bool,
+    // the port to listen for gdb on
+    gdb_port: Option<u16>,
+    // the key file, DEFAULT_KEY_FILE is read when there is one and none is given
+    key_file_path: Option<String>,
+    // a file to trace the instructions to, only those that pass the filter
+    trace_file_path: Option<String>,
+    trace_filter: TraceFilter,
+    // only the last this many instructions are written, when something goes wrong
+    trace_last: Option<usize>,
+}
+
+/*
+ * check_args
+ * usage: chip8 [--platform chip8|schip|xochip] [--quirks vip|chip48|schip|xochip[,+quirk|-quirk...]]
+ *              [--wav <file>] [--tone <hz>] [--waveform square|sine|triangle|sawtooth]
+ *              [--speed <instructions per frame>] [--record <movie> | --replay <movie>]
+ *              [--seed <number>] [--rng xorshift|counter] [--stack-policy error|wrap|ignore]
+ *              [--keys <file>] [--debug | --gdb <port>] [--trace <file> [--trace-range <start>-<end>]
+ *              [--trace-ops <pattern>[,<pattern>...]] [--trace-last <n>]] <rom>
+ */
+fn check_args(args: &[String]) -> Result<Options, String> {
+    let mut platform = Platform::default();
+    let mut quirks = None;
+    let mut stack_policy = StackPolicy::default();
+    let mut rom_file_path = None;
+    let mut wav_file_path = None;
+    let mut tone = Tone::default();
+    let mut speed = None;
+    let mut record_file_path = None;
+    let mut replay_file_path = None;
+    let mut seed = None;
+    let mut rng_kind = RngKind::default();
+    let mut frames = None;
+    let mut debug = false;
+    let mut gdb_port = None;
+    let mut key_file_path = None;
+    let mut trace_file_path = None;
+    let mut trace_filter = TraceFilter::default();
+    let mut trace_last = None;
+
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let value = args.next().ok_or("--quirks needs a value")?;
+                quirks = Some(value.parse()?);
+            }
+            "--platform" => {
+                let value = args.next().ok_or("--platform needs a value")?;
+                platform = value.parse()?;
+            }
+            "--stack-policy" => {
+                let value = args.next().ok_or("--stack-policy needs a value")?;
+                stack_policy = value.parse()?;
+            }
+            "--wav" => {
+                let value = args.next().ok_or("--wav needs a file")?;
+                wav_file_path = Some(value.clone());
+            }
+            "--tone" => {
+                let value = args.next().ok_or("--tone needs a frequency")?;
+                tone.frequency = value
+                    .parse()
+                    .ok()
+                    .filter(|frequency: &f32| *frequency > 0.0)
+                    .ok_or(format!("invalid tone frequency \"{value}\""))?;
+            }
+            "--waveform" => {
+                let value = args.next().ok_or("--waveform needs a value")?;
+                tone.waveform = value.parse()?;
+            }
+            "--speed" => {
+                let value = args.next().ok_or("--speed needs a value")?;
+                speed = Some(
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|speed: &u32| *speed > 0)
+                        .ok_or(format!("invalid speed \"{value}\""))?,
+                );
+            }
+            "--record" => {
+                let value = args.next().ok_or("--record needs a file")?;
+                record_file_path = Some(value.clone());
+            }
+            "--replay" => {
+                let value = args.next().ok_or("--replay needs a file")?;
+                replay_file_path = Some(value.clone());
+            }
+            "--seed" => {
+                let value = args.next().ok_or("--seed needs a number")?;
+                seed = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid seed \"{value}\""))?,
+                );
+            }
+            "--rng" => {
+                let value = args.next().ok_or("--rng needs a value")?;
+                rng_kind = value.parse()?;
+            }
+            "--frames" => {
+                let value = args.next().ok_or("--frames needs a number")?;
+                frames = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid number of frames \"{value}\""))?,
+                );
+            }
+            "--keys" => {
+                let value = args.next().ok_or("--keys needs a file")?;
+                key_file_path = Some(value.clone());
+            }
+            "--debug" => debug = true,
+            "--gdb" => {
+                let value = args.next().ok_or("--gdb needs a port")?;
+                gdb_port = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid port \"{value}\""))?,
+                );
+            }
+            "--trace" => {
+                let value = args.next().ok_or("--trace needs a file")?;
+                trace_file_path = Some(value.clone());
+            }
+            "--trace-range" => {
+                let value = args.next().ok_or("--trace-range needs a range")?;
+                trace_filter.range = Some(TraceFilter::parse_range(value)?);
+            }
+            "--trace-ops" => {
+                let value = args.next().ok_or("--trace-ops needs opcode patterns")?;
+                trace_filter.opcodes = TraceFilter::parse_opcodes(value)?;
+            }
+            "--trace-last" => {
+                let value = args.next().ok_or("--trace-last needs a number")?;
+                trace_last = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid number of instructions \"{value}\""))?,
+                );
+            }
+            _ => set_rom_path(&mut rom_file_path, arg)?,
+        }
+    }
+
+    let rom_file_path = rom_file_path.ok_or("Not enough arguments")?;
+    if record_file_path.is_some() && replay_file_path.is_some() {
+        return Err(String::from(
+            "--record and --replay can not be used together",
+        ));
+    }
+    if debug && gdb_port.is_some() {
+        return Err(String::from("--debug and --gdb can not be used together"));
+    }
+    Ok(Options {
+        rom_file_path,
+        platform,
+        quirks,
+        stack_policy,
+        wav_file_path,
+        tone,
+        speed,
+        record_file_path,
+        replay_file_path,
+        seed,
+        rng_kind,
+        frames,
+        debug,
+        gdb_port,
+        key_file_path,
+        trace_file_path,
+        trace_filter,
+        trace_last,
+    })
+}
+
+// takes an argument that is not an option as the rom, a mistyped option or a second rom is an error
+fn set_rom_path(rom_file_path: &mut Option<String>, arg: &str) -> Result<(), String> {
+    if arg.starts_with("--") {
+        return Err(format!("unknown option \"{arg}\""));
+    }
+    if let Some(path) = rom_file_path {
+        return Err(format!("more than one rom given: \"{path}\" and \"{arg}\""));
+    }
+    *rom_file_path = Some(arg.to_string());
+    Ok(())
+}
+
+/*
+ * disasm
+ * prints an annotated listing of a rom
+ * usage: chip8 disasm [--syntax octo|cowgod] <rom>
+ */
+fn disasm(args: &[String]) -> Result<(), String> {
+    let mut syntax = Syntax::Cowgod;
+    let mut rom_file_path = None;
 
-pub const WIDTH: u32 = 64;
-pub const HEIGHT: u32 = 32;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--syntax" => {
+                let value = args.next().ok_or("--syntax needs a value")?;
+                syntax = value.parse()?;
+            }
+            "--octo" => syntax = Syntax::Octo,
+            "--cowgod" => syntax = Syntax::Cowgod,
+            _ => set_rom_path(&mut rom_file_path, arg)?,
+        }
+    }
 
-fn read_rom(path: String) -> io::Result<Vec<u8>> {
-    let mut file = File::open(path)?;
-    let mut buffer = Vec::new();
+    let rom_file_path = rom_file_path.ok_or("Not enough arguments")?;
+    let rom = load_rom(rom_file_path)?;
 
-    file.read_to_end(&mut buffer)?;
+    for line in disassemble(&rom, syntax) {
+        println!("{line}");
+    }
+    Ok(())
+}
 
-    Ok(buffer)
+/*
+ * build_machine
+ * sets up a machine from the options, or the way a movie recorded it when one is replayed
+ */
+fn build_machine(options: &Options, rom: &[u8]) -> Result<(Machine, Option<MoviePlayer>), String> {
+    let (mut machine, player) = match &options.replay_file_path {
+        Some(replay_file_path) => {
+            let movie = Movie::read(replay_file_path)?;
+            let machine = movie.create_machine(rom)?;
+            (machine, Some(MoviePlayer::new(movie)))
+        }
+        None => (configure_machine(options, rom)?, None),
+    };
+
+    if let Some(trace_file_path) = &options.trace_file_path {
+        let mut tracer =
+            Tracer::create(trace_file_path).map_err(|err| format!("{trace_file_path}: {err}"))?;
+        tracer.set_filter(options.trace_filter.clone());
+        if let Some(instructions) = options.trace_last {
+            tracer.keep_last(instructions);
+        }
+        machine.set_tracer(Some(tracer));
+    }
+
+    // the buzzer is written to the wav file with or without a window
+    machine.set_tone(options.tone);
+    if let Some(wav_file_path) = &options.wav_file_path {
+        let sink = WavSink::create(wav_file_path, SAMPLE_RATE)
+            .map_err(|err| format!("{wav_file_path}: {err}"))?;
+        machine.set_audio_sink(Box::new(sink));
+    }
+    Ok((machine, player))
 }
 
-fn check_args(args: &[String]) -> Result<String, &'static str> {
-    if args.len() < 2 {
-        return Err("Not enough arguments");
+// sets up a machine from the platform, quirks, speed and random number options
+fn configure_machine(options: &Options, rom: &[u8]) -> Result<Machine, String> {
+    let mut machine = Machine::for_platform(options.platform, rom)?;
+    if let Some(quirks) = options.quirks {
+        machine.set_quirks(quirks);
     }
-    Ok(args[1].clone())
+    if let Some(speed) = options.speed {
+        machine.set_instructions_per_frame(speed);
+    }
+    if let Some(seed) = options.seed {
+        machine.set_seed(seed);
+    }
+    machine.set_rng_kind(options.rng_kind);
+    machine.stack_mut().set_policy(options.stack_policy);
+    Ok(machine)
+}
+
+// reads the key file with the overrides for the rom, the default mapping is used when there is none
+fn load_key_map(options: &Options, rom: &[u8]) -> Result<KeyMap, String> {
+    let path = match &options.key_file_path {
+        Some(path) => path.as_str(),
+        None if Path::new(DEFAULT_KEY_FILE).exists() => DEFAULT_KEY_FILE,
+        None => return Ok(KeyMap::new()),
+    };
+    let rom_name = Path::new(&options.rom_file_path)
+        .file_stem()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    KeyMap::load(path, &rom_name, fnv1a(rom))
+}
+
+/*
+ * run
+ * runs a rom with no window for a number of frames, or to the end of a replayed movie, then
+ * prints a hash of the machine state. The same rom, options and movie always print the same hash.
+ * usage: chip8 run [--frames <n>] [options as above] <rom>
+ */
+fn run(args: &[String]) -> Result<(), String> {
+    let options = check_args(args)?;
+    let rom = load_rom(&options.rom_file_path)?;
+    let (mut machine, mut player) = build_machine(&options, &rom)?;
+
+    let ran = match (&mut player, options.frames) {
+        (Some(player), None) => player.play_to_end(&mut machine),
+        (_, frames) => {
+            let mut ran = Ok(());
+            for _ in 0..frames.unwrap_or(DEFAULT_RUN_FRAMES) {
+                if let Some(player) = &mut player {
+                    player.apply_inputs(&mut machine);
+                }
+                ran = machine.run_frame();
+                if ran.is_err() || machine.has_exited() {
+                    break;
+                }
+            }
+            ran
+        }
+    };
+    ran.map_err(|error| crash_report(&error, &machine))?;
+
+    println!("frames {}", machine.frame_count());
+    println!("cycles {}", machine.cycle_count());
+    println!("seed {}", machine.seed());
+    println!("hash {:016x}", machine.state_hash());
+    Ok(())
+}
+
+/*
+ * trace_diff
+ * finds the first instruction where a trace written with --trace and another trace differ, the
+ * other trace can come from another emulator, with its columns given by --format
+ * usage: chip8 trace-diff [--format <columns>] [--context <lines>] <trace> <other trace>
+ * returns true when the traces agree
+ */
+fn trace_diff(args: &[String]) -> Result<bool, String> {
+    let mut format = TraceFormat::Named;
+    let mut context = DEFAULT_DIFF_CONTEXT;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                let value = args.next().ok_or("--format needs the columns")?;
+                format = value.parse()?;
+            }
+            "--context" => {
+                let value = args.next().ok_or("--context needs a number of lines")?;
+                context = value
+                    .parse()
+                    .map_err(|_| format!("invalid number of lines \"{value}\""))?;
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    let [ours, theirs] = paths.as_slice() else {
+        return Err(String::from("expected two traces"));
+    };
+    let open = |path: &String| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|err| format!("{path}: {err}"))
+    };
+    match first_divergence(
+        open(ours)?,
+        &TraceFormat::Named,
+        open(theirs)?,
+        &format,
+        context,
+    )? {
+        Some(divergence) => {
+            print!("{divergence}");
+            Ok(false)
+        }
+        None => {
+            println!("the traces agree");
+            Ok(true)
+        }
+    }
+}
+
+/*
+ * dap
+ * serves the Debug Adapter Protocol to an editor on stdin and stdout, or to the first editor to
+ * connect to the port on localhost
+ * usage: chip8 dap [--port <port>]
+ */
+fn dap(args: &[String]) -> Result<(), String> {
+    let port = match args {
+        [] => None,
+        [flag, port] if flag == "--port" => Some(
+            port.parse::<u16>()
+                .map_err(|_| format!("invalid port \"{port}\""))?,
+        ),
+        _ => return Err(String::from("usage: chip8 dap [--port <port>]")),
+    };
+
+    let served = match port {
+        None => DapServer::new(io::stdout()).serve(BufReader::new(io::stdin())),
+        Some(port) => {
+            let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|err| err.to_string())?;
+            eprintln!("Waiting for an editor on 127.0.0.1:{port}");
+            let (stream, _) = listener.accept().map_err(|err| err.to_string())?;
+            let input = stream.try_clone().map_err(|err| err.to_string())?;
+            DapServer::new(stream).serve(BufReader::new(input))
+        }
+    };
+    served.map_err(|err| err.to_string())
 }
 
 fn main() {
-    // Initialize the logger
-    // env_logger::builder()
-    //     .filter_level(log::LevelFilter::Info) // Set the log level
-    //     .init();
+    // warnings are logged unless RUST_LOG says otherwise, RUST_LOG=chip8::trace=trace logs a trace
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
 
     let args: Vec<String> = env::args().collect();
 
-    let rom_file_path = check_args(&args).unwrap_or_else(|err| {
+    if args.get(1).map(String::as_str) == Some("disasm") {
+        if let Err(err) = disasm(&args[2..]) {
+            println!("Problem disassembling rom: {err}");
+            process::exit(1);
+        }
+        return;
+    }
+
+    if args.get(1).map(String::as_str) == Some("trace-diff") {
+        match trace_diff(&args[2..]) {
+            Ok(true) => return,
+            Ok(false) => process::exit(1),
+            Err(err) => {
+                println!("Problem comparing the traces: {err}");
+                process::exit(2);
+            }
+        }
+    }
+
+    if args.get(1).map(String::as_str) == Some("dap") {
+        // stdout carries the protocol, so problems go to stderr
+        if let Err(err) = dap(&args[2..]) {
+            eprintln!("Problem serving the debug adapter: {err}");
+            process::exit(1);
+        }
+        return;
+    }
+
+    if args.get(1).map(String::as_str) == Some("run") {
+        if let Err(err) = run(&args[1..]) {
+            println!("Problem running rom: {err}");
+            process::exit(1);
+        }
+        return;
+    }
+
+    let options = check_args(&args).unwrap_or_else(|err| {
         println!("Problem parsing arguments: {err}");
         process::exit(1);
     });
 
-    let rom = read_rom(rom_file_path).unwrap();
+    let rom = load_rom(&options.rom_file_path).unwrap_or_else(|err| {
+        println!("Problem loading rom: {err}");
+        process::exit(1);
+    });
+
+    let (mut machine, player) = build_machine(&options, &rom).unwrap_or_else(|err| {
+        println!("Problem loading rom: {err}");
+        process::exit(1);
+    });
+    let mut movie_mode = match player {
+        Some(player) => MovieMode::Replay(player),
+        None => MovieMode::Off,
+    };
+
+    if let Some(record_file_path) = &options.record_file_path {
+        movie_mode = MovieMode::Record {
+            movie: Movie::new(&machine, &rom),
+            path: record_file_path.into(),
+        };
+    }
+
+    // SUPER-CHIP games keep their high scores in the flag registers, saved next to the rom
+    // movies leave them out, saved flags would make the run differ from the recording
+    if !movie_mode.is_on() {
+        let rpl_file = Path::new(&options.rom_file_path).with_extension("rpl");
+        if let Err(err) = machine.set_rpl_file(rpl_file) {
+            println!("Problem loading the saved flags: {err}");
+        }
+    }
 
-    chip8(WIDTH, HEIGHT, rom);
+    let debugging = match options.gdb_port {
+        Some(port) => match GdbStub::new(("127.0.0.1", port)) {
+            Ok(stub) => {
+                println!("Waiting for gdb on 127.0.0.1:{port}");
+                Debugging::Gdb(stub)
+            }
+            Err(err) => {
+                println!("Problem listening for gdb: {err}");
+                process::exit(1);
+            }
+        },
+        None if options.debug => Debugging::Terminal(Debugger::new()),
+        None => Debugging::Off,
+    };
+    let key_map = load_key_map(&options, &rom).unwrap_or_else(|err| {
+        println!("Problem loading the key mapping: {err}");
+        process::exit(1);
+    });
+    chip8(
+        machine,
+        SaveSlots::new(&options.rom_file_path),
+        movie_mode,
+        debugging,
+        key_map,
+    );
 
     println!("exiting program");
 }
+
+#[cfg(test)]
+mod main_tests {
+    use super::*;
+
+    #[test]
+    fn refuses_unknown_options_and_extra_roms() {
+        let args = |text: &str| text.split(' ').map(String::from).collect::<Vec<String>>();
+
+        let options = check_args(&args("chip8 --quirks schip pong.ch8")).unwrap();
+        assert_eq!(options.rom_file_path, "pong.ch8");
+        assert_eq!(
+            check_args(&args("chip8 --qurks schip pong.ch8")).err(),
+            Some(String::from("unknown option \"--qurks\""))
+        );
+        assert!(check_args(&args("chip8 pong.ch8 tetris.ch8")).is_err());
+        assert!(disasm(&args("--sintax octo pong.ch8")).is_err());
+    }
+}
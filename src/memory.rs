@@ -25,41 +25,77 @@
 // */
 // // static mut RAM: [u8; 4096] = [0; 4096];
 
-use crate::font::{get_character_set, CHAR_SET};
+use crate::{
+    error::Chip8Error,
+    font::{get_big_character_set, get_character_set, BIG_FONT_START, CHAR_SET, FONT_START},
+};
+
+// chip8 and SUPER-CHIP have 4 kilobytes of RAM, XO-CHIP has 64
+pub const MEMORY_SIZE: usize = 4096;
+pub const XO_CHIP_MEMORY_SIZE: usize = 0x10000;
 
 pub struct Memory {
-    ram: [u8; 4096],
+    ram: Vec<u8>,
+}
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl Memory {
     // the first 512 blocks of memory are empty, because the original chip8 used these to store the interpreter software
     pub fn new() -> Memory {
-        let ram: [u8; 4096] = [0; 4096];
-        Memory { ram }
+        Memory::with_size(MEMORY_SIZE)
+    }
+
+    /** Creates a memory of the given size in bytes. */
+    pub fn with_size(size: usize) -> Memory {
+        Memory { ram: vec![0; size] }
+    }
+
+    /** The size of the memory in bytes. */
+    pub fn size(&self) -> usize {
+        self.ram.len()
     }
 
     pub fn set_fonts(&mut self) {
         let fonts = get_character_set();
+        let big_fonts = get_big_character_set();
         let ram_len = self.ram.len();
 
         for (index, char) in CHAR_SET.iter().enumerate() {
-            let base_index = index * 5;
+            let base_index = FONT_START + index * 5;
 
             let font = fonts.get(char).unwrap();
 
             if base_index + 5 <= ram_len {
                 self.ram[base_index..base_index + 5].copy_from_slice(font);
             }
+
+            let base_index = BIG_FONT_START + index * 10;
+            let big_font = big_fonts.get(char).unwrap();
+            self.ram[base_index..base_index + 10].copy_from_slice(big_font);
         }
     }
 
-    /** Returns the RAM value at the given address. */
-    fn _peek(&self, address: usize) -> u8 {
-        self.ram[address]
+    /** Returns the RAM value at the given address, or None past the end of memory. */
+    pub fn peek(&self, address: usize) -> Option<u8> {
+        self.ram.get(address).copied()
     }
 
     /** Sets the RAM value at the given address to the given value. */
-    fn _poke(&mut self, address: usize, value: u8) {
-        self.ram[address] = value;
+    pub fn poke(&mut self, address: usize, value: u8) -> Result<(), String> {
+        let size = self.ram.len();
+        let byte = self.ram.get_mut(address).ok_or(format!(
+            "address {address:03X} is past the end of the {size} byte memory"
+        ))?;
+        *byte = value;
+        Ok(())
+    }
+
+    /** The whole of RAM, read only. */
+    pub fn as_slice(&self) -> &[u8] {
+        &self.ram
     }
 
     pub fn get_memory(&mut self) -> &mut [u8] {
@@ -67,16 +103,32 @@ impl Memory {
     }
 
     /** Sets a rom to the program space in the chip8 memory */
-    pub fn set_rom(&mut self, rom: &Vec<u8>) -> Result<&[u8], String> {
+    pub fn set_rom(&mut self, rom: &[u8]) -> Result<&[u8], Chip8Error> {
         let rom_len = rom.len();
         let end_index = 512 + rom_len;
-        if rom_len <= self.ram.len() - 512 {
-            self.ram[512..end_index].copy_from_slice(&rom);
+        let space = self.ram.len().saturating_sub(512);
+        if rom_len <= space {
+            self.ram[512..end_index].copy_from_slice(rom);
             Ok(&self.ram)
         } else {
-            Err(String::from(
-                "Not enough space in the array to copy new values.",
-            ))
+            Err(Chip8Error::RomTooLarge {
+                size: rom_len,
+                space,
+            })
         }
     }
 }
+
+#[cfg(test)]
+mod memory_tests {
+    use super::*;
+
+    #[test]
+    fn refuses_addresses_past_the_end() {
+        let mut memory = Memory::new();
+        assert_eq!(memory.poke(0xFFF, 0x12), Ok(()));
+        assert_eq!(memory.peek(0xFFF), Some(0x12));
+        assert!(memory.poke(0x1000, 0x12).is_err());
+        assert_eq!(memory.peek(0x1000), None);
+    }
+}
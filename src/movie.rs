@@ -0,0 +1,299 @@
+use std::{fmt, fs, path::Path, str::FromStr};
+
+use crate::{
+    error::Chip8Error, hash::fnv1a, machine::Machine, platform::Platform, quirks::Quirks,
+    rng::RngKind, stack::StackPolicy,
+};
+
+/*
+ * Movies
+ * a recording of every key press and release of a run, tagged with the frame it happened before,
+ * along with everything else the run depended on: the rom, platform, quirks, speed, stack policy
+ * and the seed of the random numbers. Replaying a movie on the same rom gives exactly the same run.
+ *
+ * A movie file is plain text, one item per line:
+ *
+ *   chip8-movie 1
+ *   rom 8f3a0c4e1b2d7f60
+ *   platform chip8
+ *   quirks custom,vf-reset,memory,shift,display-wait
+ *   seed 1234
+ *   rng xorshift
+ *   speed 11
+ *   stack error
+ *   120 5 down
+ *   126 5 up
+ *   end 600
+ *
+ * The rom is its FNV-1a hash in hex and the key lines are the frame, the key in hex and the state.
+ * End is the number of frames recorded. Movies written before the stack line was added have the
+ * error policy.
+ */
+
+// the first line of every movie file
+const MOVIE_HEADER: &str = "chip8-movie";
+const MOVIE_VERSION: u32 = 1;
+
+/* One key press or release */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Input {
+    // the frame the key changed before
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub rng_kind: RngKind,
+    pub instructions_per_frame: u32,
+    pub stack_policy: StackPolicy,
+    pub inputs: Vec<Input>,
+    // the number of frames recorded
+    pub length: u64,
+}
+impl Movie {
+    /** Starts a recording of a machine that has not run yet, with the rom it was loaded with. */
+    pub fn new(machine: &Machine, rom: &[u8]) -> Movie {
+        Movie {
+            rom_hash: fnv1a(rom),
+            platform: machine.platform(),
+            quirks: machine.quirks(),
+            seed: machine.seed(),
+            rng_kind: machine.rng().kind(),
+            instructions_per_frame: machine.instructions_per_frame(),
+            stack_policy: machine.stack().get_policy(),
+            inputs: Vec::new(),
+            length: 0,
+        }
+    }
+
+    /** Records a key change made before the given frame. */
+    pub fn record(&mut self, frame: u64, key: u8, pressed: bool) {
+        self.inputs.push(Input {
+            frame,
+            key,
+            pressed,
+        });
+        self.length = self.length.max(frame);
+    }
+
+    /** Marks the number of frames the recording ran for. */
+    pub fn set_length(&mut self, frames: u64) {
+        self.length = frames;
+    }
+
+    /** Creates a machine set up the way the recorded one was. The rom has to be the one recorded. */
+    pub fn create_machine(&self, rom: &[u8]) -> Result<Machine, String> {
+        let rom_hash = fnv1a(rom);
+        if rom_hash != self.rom_hash {
+            return Err(format!(
+                "the movie was recorded with rom {:016x}, not {rom_hash:016x}",
+                self.rom_hash
+            ));
+        }
+
+        let mut machine = Machine::for_platform(self.platform, rom)?;
+        machine.set_quirks(self.quirks);
+        machine.set_seed(self.seed);
+        machine.set_rng_kind(self.rng_kind);
+        machine.set_instructions_per_frame(self.instructions_per_frame);
+        machine.stack_mut().set_policy(self.stack_policy);
+        Ok(machine)
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Movie, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        text.parse()
+            .map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        fs::write(path, self.to_string()).map_err(|err| format!("{}: {err}", path.display()))
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{MOVIE_HEADER} {MOVIE_VERSION}")?;
+        writeln!(f, "rom {:016x}", self.rom_hash)?;
+        writeln!(f, "platform {}", self.platform)?;
+        writeln!(f, "quirks {}", self.quirks)?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "rng {}", self.rng_kind)?;
+        writeln!(f, "speed {}", self.instructions_per_frame)?;
+        writeln!(f, "stack {}", self.stack_policy)?;
+        for input in &self.inputs {
+            let state = if input.pressed { "down" } else { "up" };
+            writeln!(f, "{} {:X} {state}", input.frame, input.key)?;
+        }
+        writeln!(f, "end {}", self.length)
+    }
+}
+
+impl FromStr for Movie {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Movie, String> {
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty());
+
+        match lines.next() {
+            Some((_, line)) if line == format!("{MOVIE_HEADER} {MOVIE_VERSION}") => {}
+            _ => return Err(String::from("not a chip8 movie, or an unsupported version")),
+        }
+
+        let mut movie = Movie {
+            rom_hash: 0,
+            platform: Platform::default(),
+            quirks: Quirks::default(),
+            seed: 0,
+            rng_kind: RngKind::default(),
+            instructions_per_frame: 1,
+            stack_policy: StackPolicy::default(),
+            inputs: Vec::new(),
+            length: 0,
+        };
+        for (index, line) in lines {
+            let error = |message: &str| format!("line {}: {message}", index + 1);
+            let words = line.split_whitespace().collect::<Vec<&str>>();
+            match words.as_slice() {
+                ["rom", hash] => {
+                    movie.rom_hash =
+                        u64::from_str_radix(hash, 16).map_err(|_| error("invalid rom hash"))?;
+                }
+                ["platform", platform] => {
+                    movie.platform = platform.parse().map_err(|err: String| error(&err))?
+                }
+                ["quirks", quirks] => {
+                    movie.quirks = quirks.parse().map_err(|err: String| error(&err))?
+                }
+                ["seed", seed] => movie.seed = seed.parse().map_err(|_| error("invalid seed"))?,
+                ["rng", kind] => {
+                    movie.rng_kind = kind.parse().map_err(|err: String| error(&err))?
+                }
+                ["speed", speed] => {
+                    movie.instructions_per_frame =
+                        speed.parse().map_err(|_| error("invalid speed"))?;
+                }
+                ["stack", policy] => {
+                    movie.stack_policy = policy.parse().map_err(|err: String| error(&err))?
+                }
+                ["end", length] => {
+                    movie.length = length.parse().map_err(|_| error("invalid end"))?
+                }
+                [frame, key, state] => {
+                    let frame = frame.parse().map_err(|_| error("invalid frame"))?;
+                    let key = u8::from_str_radix(key, 16)
+                        .ok()
+                        .filter(|key| *key < 16)
+                        .ok_or_else(|| error("invalid key"))?;
+                    let pressed = match *state {
+                        "down" => true,
+                        "up" => false,
+                        _ => return Err(error("expected down or up")),
+                    };
+                    movie.inputs.push(Input {
+                        frame,
+                        key,
+                        pressed,
+                    });
+                }
+                _ => return Err(error(&format!("unexpected \"{line}\""))),
+            }
+        }
+
+        Ok(movie)
+    }
+}
+
+/*
+ * MoviePlayer
+ * feeds the inputs of a movie to a machine, call apply_inputs before every frame
+ */
+#[derive(Debug, Clone)]
+pub struct MoviePlayer {
+    movie: Movie,
+    // the next input to apply
+    next: usize,
+}
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> MoviePlayer {
+        MoviePlayer { movie, next: 0 }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    /** Presses and releases the keys recorded before the machine's next frame. */
+    pub fn apply_inputs(&mut self, machine: &mut Machine) {
+        let frame = machine.frame_count();
+        while let Some(input) = self.movie.inputs.get(self.next) {
+            if input.frame > frame {
+                break;
+            }
+            machine.set_key(input.key, input.pressed);
+            self.next += 1;
+        }
+    }
+
+    /** Returns true once the machine has run every recorded frame. */
+    pub fn is_finished(&self, machine: &Machine) -> bool {
+        machine.frame_count() >= self.movie.length && self.next >= self.movie.inputs.len()
+    }
+
+    /** Runs the whole movie on the machine with no window, a frame at a time. */
+    pub fn play_to_end(&mut self, machine: &mut Machine) -> Result<(), Chip8Error> {
+        while !self.is_finished(machine) && !machine.has_exited() {
+            self.apply_inputs(machine);
+            machine.run_frame()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod movie_tests {
+    use super::*;
+
+    // C00F v0 := random 0x0F, E09E skip if key v0, 1200 loop, 7101 v1 += 1, 1200 loop
+    const ROM: [u8; 10] = [0xC0, 0x0F, 0xE0, 0x9E, 0x12, 0x00, 0x71, 0x01, 0x12, 0x00];
+
+    #[test]
+    fn replays_a_recording_exactly() {
+        let mut machine = Machine::new(64, 32, &ROM).unwrap();
+        machine.stack_mut().set_policy(StackPolicy::Wrap);
+        let mut movie = Movie::new(&machine, &ROM);
+        for frame in 0..120 {
+            if frame % 10 == 0 {
+                let key = (frame / 10 % 16) as u8;
+                let pressed = frame % 20 == 0;
+                machine.set_key(key, pressed);
+                movie.record(machine.frame_count(), key, pressed);
+            }
+            machine.run_frame().unwrap();
+        }
+        movie.set_length(machine.frame_count());
+
+        let movie: Movie = movie.to_string().parse().unwrap();
+        let mut replayed = movie.create_machine(&ROM).unwrap();
+        MoviePlayer::new(movie).play_to_end(&mut replayed).unwrap();
+
+        assert_eq!(replayed.frame_count(), 120);
+        assert_eq!(replayed.stack().get_policy(), StackPolicy::Wrap);
+        assert_eq!(replayed.save_state(), machine.save_state());
+        assert!(Movie::new(&machine, &ROM)
+            .create_machine(&[0x00, 0xE0])
+            .is_err());
+    }
+}
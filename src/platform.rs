@@ -0,0 +1,100 @@
+use std::{fmt, str::FromStr};
+
+use crate::{
+    memory::{MEMORY_SIZE, XO_CHIP_MEMORY_SIZE},
+    quirks::Quirks,
+};
+
+/*
+ * Platform
+ * the chip8 variant a rom was written for. It decides the instructions that run, the size of the
+ * display and the quirks used when none are given.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Platform {
+    // the original chip8, 64 x 32
+    #[default]
+    Chip8,
+    // SUPER-CHIP 1.1, 128 x 64 with a 64 x 32 low resolution mode
+    SuperChip,
+    // XO-CHIP, SUPER-CHIP with 64 kilobytes of memory, two bit planes and audio patterns
+    XoChip,
+}
+
+impl Platform {
+    /** The quirks roms written for the platform expect. */
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::VIP,
+            Platform::SuperChip => Quirks::SCHIP,
+            Platform::XoChip => Quirks::XOCHIP,
+        }
+    }
+
+    /** The width of the display in its highest resolution. */
+    pub fn width(&self) -> u32 {
+        match self {
+            Platform::Chip8 => 64,
+            Platform::SuperChip | Platform::XoChip => 128,
+        }
+    }
+
+    /** The height of the display in its highest resolution. */
+    pub fn height(&self) -> u32 {
+        match self {
+            Platform::Chip8 => 32,
+            Platform::SuperChip | Platform::XoChip => 64,
+        }
+    }
+
+    /** The size of memory in bytes. */
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => MEMORY_SIZE,
+            Platform::XoChip => XO_CHIP_MEMORY_SIZE,
+        }
+    }
+
+    /** The number of return addresses the stack holds, 12 on the COSMAC VIP and 16 on HP48 based interpreters. */
+    pub fn stack_depth(&self) -> usize {
+        match self {
+            Platform::Chip8 => 12,
+            Platform::SuperChip | Platform::XoChip => 16,
+        }
+    }
+
+    /** Returns true when the SUPER-CHIP instructions run, on other platforms they are ignored. */
+    pub fn supports_schip(&self) -> bool {
+        matches!(self, Platform::SuperChip | Platform::XoChip)
+    }
+
+    /** Returns true when the XO-CHIP instructions run, on other platforms they are ignored. */
+    pub fn supports_xochip(&self) -> bool {
+        matches!(self, Platform::XoChip)
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Platform, String> {
+        match text.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" | "vip" => Ok(Platform::Chip8),
+            "schip" | "superchip" | "super-chip" => Ok(Platform::SuperChip),
+            "xochip" | "xo-chip" => Ok(Platform::XoChip),
+            _ => Err(format!(
+                "unknown platform \"{text}\", expected chip8, schip or xochip"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Platform::Chip8 => write!(f, "chip8"),
+            Platform::SuperChip => write!(f, "schip"),
+            Platform::XoChip => write!(f, "xochip"),
+        }
+    }
+}
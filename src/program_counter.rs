@@ -1,7 +1,14 @@
+use crate::error::Chip8Error;
+
 // A program counter, often called just “PC”, which points at the current instruction in memory
 pub struct ProgramCounter {
     pc: u16,
 }
+impl Default for ProgramCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl ProgramCounter {
     pub fn new() -> ProgramCounter {
         ProgramCounter { pc: 0 }
@@ -12,18 +19,22 @@ impl ProgramCounter {
     }
 
     pub fn increment_by(&mut self, value: u16) -> u16 {
-        self.pc += value;
+        self.pc = self.pc.wrapping_add(value);
         self.pc
     }
 
     pub fn increment(&mut self) -> u16 {
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
         self.pc
     }
 
-    pub fn decrement(&mut self) -> u16 {
-        self.pc -= 1;
-        self.pc
+    /** Moves back one byte, failing rather than wrapping around below address 0. */
+    pub fn decrement(&mut self) -> Result<u16, Chip8Error> {
+        self.pc = self
+            .pc
+            .checked_sub(1)
+            .ok_or(Chip8Error::ProgramCounterOutOfBounds { pc: self.pc })?;
+        Ok(self.pc)
     }
 
     pub fn jump(&mut self, location: u16) {
@@ -61,6 +72,19 @@ mod program_counter_tests {
         assert!(pc.pc == 3);
     }
 
+    #[test]
+    fn cannot_decrement_below_zero() {
+        let mut pc = ProgramCounter::new();
+
+        pc.increment(); // 1
+        assert_eq!(pc.decrement(), Ok(0));
+        assert_eq!(
+            pc.decrement(),
+            Err(Chip8Error::ProgramCounterOutOfBounds { pc: 0 })
+        );
+        assert!(pc.get_pc() == 0);
+    }
+
     #[test]
     fn can_clear() {
         let mut pc = ProgramCounter::new();
@@ -0,0 +1,248 @@
+use std::{fmt, str::FromStr};
+
+/*
+ * Quirks
+ * the behaviours that differ between chip8 interpreters. Which ones a rom needs depends on the
+ * interpreter it was written for, roms/5-quirks.ch8 shows what the running interpreter does.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Quirks {
+    // 8XY1, 8XY2 and 8XY3 reset VF to 0
+    pub vf_reset: bool,
+    // how far FX55 and FX65 move I
+    pub memory_increment: MemoryIncrement,
+    // 8XY6 and 8XYE shift VY into VX, instead of shifting VX in place and ignoring VY
+    pub shift_uses_vy: bool,
+    // BNNN jumps to XNN + VX instead of NNN + V0
+    pub jump_uses_vx: bool,
+    // sprites drawn over the edge of the screen wrap around to the other side instead of clipping
+    pub wrap_sprites: bool,
+    // DXYN waits for the start of the next frame, so at most one sprite is drawn per frame
+    pub display_wait: bool,
+}
+
+/* How far FX55 and FX65 leave I from where they started, set by the memory and memory-x quirks */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryIncrement {
+    // I is left unchanged
+    None,
+    // I is left on the last register (I += X), a bug of CHIP-48
+    ByX,
+    // I is left after the last register (I += X + 1), as on the COSMAC VIP
+    ByXPlusOne,
+}
+
+// the name of each quirk as written on the command line
+const QUIRK_NAMES: [&str; 7] = [
+    "vf-reset",
+    "memory",
+    "memory-x",
+    "shift",
+    "jump",
+    "wrap",
+    "display-wait",
+];
+
+impl Quirks {
+    /** The original COSMAC VIP interpreter. */
+    pub const VIP: Quirks = Quirks {
+        vf_reset: true,
+        memory_increment: MemoryIncrement::ByXPlusOne,
+        shift_uses_vy: true,
+        jump_uses_vx: false,
+        wrap_sprites: false,
+        display_wait: true,
+    };
+
+    /** CHIP-48 on the HP48 calculators, SUPER-CHIP fixed its FX55 and FX65 to leave I alone. */
+    pub const CHIP48: Quirks = Quirks {
+        vf_reset: false,
+        memory_increment: MemoryIncrement::ByX,
+        shift_uses_vy: false,
+        jump_uses_vx: true,
+        wrap_sprites: false,
+        display_wait: false,
+    };
+
+    /** SUPER-CHIP 1.1, the behaviour modern SCHIP games expect. */
+    pub const SCHIP: Quirks = Quirks {
+        vf_reset: false,
+        memory_increment: MemoryIncrement::None,
+        shift_uses_vy: false,
+        jump_uses_vx: true,
+        wrap_sprites: false,
+        display_wait: false,
+    };
+
+    /** XO-CHIP, as implemented by Octo. */
+    pub const XOCHIP: Quirks = Quirks {
+        vf_reset: false,
+        memory_increment: MemoryIncrement::ByXPlusOne,
+        shift_uses_vy: true,
+        jump_uses_vx: false,
+        wrap_sprites: true,
+        display_wait: false,
+    };
+
+    /** Every quirk turned off, the starting point for a custom profile. */
+    pub const NONE: Quirks = Quirks {
+        vf_reset: false,
+        memory_increment: MemoryIncrement::None,
+        shift_uses_vy: false,
+        jump_uses_vx: false,
+        wrap_sprites: false,
+        display_wait: false,
+    };
+
+    /** Returns the preset with the given name: vip, chip48, schip, xochip or custom. */
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name.to_ascii_lowercase().as_str() {
+            "vip" | "chip8" | "chip-8" => Some(Quirks::VIP),
+            "chip48" | "chip-48" => Some(Quirks::CHIP48),
+            "schip" | "superchip" | "super-chip" => Some(Quirks::SCHIP),
+            "xochip" | "xo-chip" => Some(Quirks::XOCHIP),
+            "custom" | "none" => Some(Quirks::NONE),
+            _ => None,
+        }
+    }
+
+    fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "vf-reset" => Some(&mut self.vf_reset),
+            "shift" => Some(&mut self.shift_uses_vy),
+            "jump" => Some(&mut self.jump_uses_vx),
+            "wrap" => Some(&mut self.wrap_sprites),
+            "display-wait" => Some(&mut self.display_wait),
+            _ => None,
+        }
+    }
+
+    fn flags(&self) -> [bool; 7] {
+        [
+            self.vf_reset,
+            self.memory_increment == MemoryIncrement::ByXPlusOne,
+            self.memory_increment == MemoryIncrement::ByX,
+            self.shift_uses_vy,
+            self.jump_uses_vx,
+            self.wrap_sprites,
+            self.display_wait,
+        ]
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::VIP
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = String;
+
+    /**
+     * Parses a quirk profile: a preset name, optionally followed by quirks to turn on or off,
+     * e.g. "schip", "vip,-display-wait" or "custom,vf-reset,shift".
+     */
+    fn from_str(text: &str) -> Result<Quirks, String> {
+        let items = text
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .collect::<Vec<&str>>();
+
+        let first = items.first().ok_or("no quirk profile given")?;
+        // a list of quirks with no preset starts from everything turned off
+        let (mut quirks, items) = match Quirks::preset(first) {
+            Some(quirks) => (quirks, &items[1..]),
+            None => (Quirks::NONE, &items[..]),
+        };
+
+        // the memory quirks pick one of the ways I moves, so only one of them can be turned on
+        let mut memory_turned_on = None;
+        for item in items {
+            let (name, value) = match item.strip_prefix('-') {
+                Some(name) => (name, false),
+                None => (item.strip_prefix('+').unwrap_or(item), true),
+            };
+            let increment = match name {
+                "memory" => Some(MemoryIncrement::ByXPlusOne),
+                "memory-x" => Some(MemoryIncrement::ByX),
+                _ => None,
+            };
+            if let Some(increment) = increment {
+                if value {
+                    if memory_turned_on.is_some_and(|on| on != increment) {
+                        return Err(String::from("memory and memory-x can not both be on"));
+                    }
+                    memory_turned_on = Some(increment);
+                    quirks.memory_increment = increment;
+                } else if quirks.memory_increment == increment {
+                    quirks.memory_increment = MemoryIncrement::None;
+                }
+                continue;
+            }
+            let flag = quirks.flag_mut(name).ok_or(format!(
+                "unknown quirk \"{name}\", expected one of {}",
+                QUIRK_NAMES.join(", ")
+            ))?;
+            *flag = value;
+        }
+
+        Ok(quirks)
+    }
+}
+
+impl fmt::Display for Quirks {
+    /** Writes the quirks in the form `FromStr` reads, e.g. "custom,vf-reset,shift". */
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "custom")?;
+        for (name, on) in QUIRK_NAMES.iter().zip(self.flags()) {
+            if on {
+                write!(f, ",{name}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod quirks_tests {
+    use super::*;
+
+    #[test]
+    fn parses_presets_and_overrides() {
+        assert_eq!("schip".parse(), Ok(Quirks::SCHIP));
+        assert_eq!(
+            "vip,-display-wait".parse(),
+            Ok(Quirks {
+                display_wait: false,
+                ..Quirks::VIP
+            })
+        );
+        assert_eq!(
+            "shift,jump".parse(),
+            Ok(Quirks {
+                shift_uses_vy: true,
+                jump_uses_vx: true,
+                ..Quirks::NONE
+            })
+        );
+        assert!("vip,+sideways".parse::<Quirks>().is_err());
+        assert_ne!(Quirks::CHIP48, Quirks::SCHIP);
+        assert_eq!("schip,memory-x".parse(), Ok(Quirks::CHIP48));
+        assert_eq!(
+            "chip48,memory"
+                .parse::<Quirks>()
+                .map(|q| q.memory_increment),
+            Ok(MemoryIncrement::ByXPlusOne)
+        );
+        assert!("memory,memory-x".parse::<Quirks>().is_err());
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for quirks in [Quirks::VIP, Quirks::CHIP48, Quirks::SCHIP, Quirks::XOCHIP] {
+            assert_eq!(quirks.to_string().parse(), Ok(quirks));
+        }
+    }
+}
@@ -1,7 +1,4 @@
-use std::{
-    collections::HashMap,
-    time::{Duration, Instant},
-};
+use std::collections::HashMap;
 
 // // 16 8-bit (one byte) general-purpose variable registers numbered 0 through F hexadecimal, ie. 0 through 15 in decimal, called V0 through VF
 // // VF is also used as a flag register; many instructions will set it to either 1 or 0 based on some rule, for example using it as a carry flag
@@ -28,9 +25,13 @@ pub enum GeneralRegisters {
 pub struct Registers {
     general_registers: HashMap<GeneralRegisters, u8>,
     i_register: u16,
+    // counts down once per emulated frame, 60 times a second, until it reaches 0
     delay_timer: u8,
-    delay_timer_instant: Instant,
-    delay_timer_duration: Duration,
+}
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl Registers {
     pub fn new() -> Registers {
@@ -56,9 +57,7 @@ impl Registers {
         Registers {
             general_registers: registers,
             i_register: 0,
-            delay_timer: 60,
-            delay_timer_instant: Instant::now(),
-            delay_timer_duration: Duration::new(0, 0),
+            delay_timer: 0,
         }
     }
 
@@ -84,13 +83,15 @@ impl Registers {
         }
     }
 
+    /** Sets V0 to VF, returning the old value. Indexes above 0xF are ignored and return None. */
     pub fn set_register(&mut self, index: u8, value: u8) -> Option<u8> {
-        let register = self.match_register(index).unwrap();
+        let register = self.match_register(index)?;
         self.general_registers.insert(register, value)
     }
 
+    /** Gets V0 to VF, or None for indexes above 0xF. */
     pub fn get_register(&self, index: u8) -> Option<&u8> {
-        let register = self.match_register(index).unwrap();
+        let register = self.match_register(index)?;
         self.general_registers.get(&register)
     }
 
@@ -102,39 +103,37 @@ impl Registers {
         &self.i_register
     }
 
-    pub fn get_delay_timer(&mut self) -> &u8 {
-        // 1. Keep track of the time the last value was written to the timer register. When reading from the register, calculate how long ago it was written to, and subtract an appropriate value from the result.
-
-        if self.delay_timer != 0 {
-            // get the elapsed time since the last time the delay rimer was set
-            self.delay_timer_duration = self.delay_timer_instant.elapsed();
-            let elapsed_time = self.delay_timer_duration.as_millis() as u8;
-
-            // setting this to 12 because of timing issues.
-            // TODO: consider working exclusively in MS to have more accurate timekeeping
-            let num_millis_in_1_60 = 12 as u8;
-
-            // if elapsed time is greater then a difference can be figured from the total elapsed time and subtracted from the delay timer
-            if elapsed_time > num_millis_in_1_60 {
-                // find the number of ticks to subtract from DT total
-                let difference = elapsed_time / (num_millis_in_1_60);
-
-                if difference >= 1 {
-                    // check to make sure that the delay timer has enough value for the operation
-                    if self.delay_timer >= difference {
-                        // set the timer with the local method so that it can restart the timer Instant
-                        self.set_delay_timer(self.delay_timer - difference);
-                    } else {
-                        self.set_delay_timer(self.delay_timer - 1);
-                    }
-                }
-            }
-        }
+    pub fn get_delay_timer(&self) -> &u8 {
         &self.delay_timer
     }
 
     pub fn set_delay_timer(&mut self, value: u8) {
-        self.delay_timer_instant = Instant::now();
         self.delay_timer = value
     }
+
+    /** Counts the delay timer down by one 60th of a second, the machine calls this once per frame. */
+    pub fn tick_delay_timer(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+    }
+}
+
+#[cfg(test)]
+mod registers_tests {
+    use super::*;
+
+    #[test]
+    fn delay_timer_counts_down_once_per_tick() {
+        let mut registers = Registers::new();
+        assert_eq!(*registers.get_delay_timer(), 0);
+
+        registers.set_delay_timer(2);
+        // reading the timer does not change it
+        assert_eq!(*registers.get_delay_timer(), 2);
+        assert_eq!(*registers.get_delay_timer(), 2);
+
+        registers.tick_delay_timer();
+        registers.tick_delay_timer();
+        registers.tick_delay_timer();
+        assert_eq!(*registers.get_delay_timer(), 0);
+    }
 }
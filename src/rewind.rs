@@ -0,0 +1,179 @@
+use std::collections::VecDeque;
+
+use crate::save_state::SaveState;
+
+/*
+ * Rewind
+ * a rolling history of save states, one per frame, that can be played back one frame at a time.
+ * Only the newest state is kept whole. Each older state is kept as its difference from the state
+ * after it: the two are XORed, so unchanged bytes become zero, and the runs of zeros are squeezed
+ * out. A frame of a chip8 program changes a handful of bytes, so a frame costs a few bytes.
+ */
+#[derive(Debug, Clone)]
+pub struct Rewind {
+    // the most frames kept, the oldest are dropped first
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    // oldest first, each one turns the state after it back into itself
+    deltas: VecDeque<Delta>,
+}
+
+#[derive(Debug, Clone)]
+struct Delta {
+    // the length of the older state, states differ in length when the stack grows or shrinks
+    length: usize,
+    runs: Vec<u8>,
+}
+
+impl Rewind {
+    /** Creates a history of up to capacity frames, 30 seconds is 1800 frames. */
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind {
+            capacity,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /** The number of frames that can be stepped back. */
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /** The number of bytes the history takes up. */
+    pub fn memory_size(&self) -> usize {
+        let latest = self.latest.as_ref().map_or(0, Vec::len);
+        let deltas: usize = self.deltas.iter().map(|delta| delta.runs.len()).sum();
+        latest + deltas
+    }
+
+    /** Adds the state of the frame just run. */
+    pub fn push(&mut self, state: &SaveState) {
+        let state = state.as_bytes();
+        if let Some(latest) = self.latest.take() {
+            self.deltas.push_back(Delta {
+                length: latest.len(),
+                runs: compress(&xor(&latest, state)),
+            });
+            while self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(state.to_vec());
+    }
+
+    /** Steps back one frame, returning the state before the newest one. */
+    pub fn pop(&mut self) -> Option<SaveState> {
+        let delta = self.deltas.pop_back()?;
+        let latest = self.latest.take()?;
+
+        let mut previous = xor(&latest, &decompress(&delta.runs));
+        previous.truncate(delta.length);
+
+        let state = SaveState::from_bytes(previous.clone()).ok();
+        self.latest = Some(previous);
+        state
+    }
+}
+
+// XORs two byte strings, the shorter one padded with zeros
+fn xor(first: &[u8], second: &[u8]) -> Vec<u8> {
+    let length = first.len().max(second.len());
+    (0..length)
+        .map(|index| first.get(index).unwrap_or(&0) ^ second.get(index).unwrap_or(&0))
+        .collect()
+}
+
+/*
+ * compress
+ * writes the bytes as pairs of runs: a count of zero bytes, then a count of bytes that follow as
+ * they are. Counts are LEB128 varints.
+ */
+fn compress(bytes: &[u8]) -> Vec<u8> {
+    let mut runs = Vec::new();
+    let mut index = 0;
+    while index < bytes.len() {
+        let zeros = bytes[index..].iter().take_while(|byte| **byte == 0).count();
+        index += zeros;
+        let literals = bytes[index..].iter().take_while(|byte| **byte != 0).count();
+
+        write_varint(&mut runs, zeros);
+        write_varint(&mut runs, literals);
+        runs.extend(&bytes[index..index + literals]);
+        index += literals;
+    }
+    runs
+}
+
+fn decompress(runs: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut index = 0;
+    while index < runs.len() {
+        let zeros = read_varint(runs, &mut index);
+        let literals = read_varint(runs, &mut index);
+        bytes.resize(bytes.len() + zeros, 0);
+        bytes.extend(&runs[index..index + literals]);
+        index += literals;
+    }
+    bytes
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], index: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some(byte) = bytes.get(*index) {
+        *index += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    value
+}
+
+#[cfg(test)]
+mod rewind_tests {
+    use super::*;
+    use crate::machine::Machine;
+
+    #[test]
+    fn steps_back_through_the_frames() {
+        // 7001 v0 += 1, 2206 call, 1200 loop, 00EE return
+        let rom = [0x70, 0x01, 0x22, 0x06, 0x12, 0x00, 0x00, 0xEE];
+        let mut machine = Machine::new(64, 32, &rom).unwrap();
+        let mut rewind = Rewind::new(50);
+        let mut states = Vec::new();
+
+        for _ in 0..100 {
+            // stop part way through the loop, so the stack changes size between frames
+            (0..7).for_each(|_| machine.step().unwrap());
+            rewind.push(&machine.save_state());
+            states.push(machine.save_state());
+        }
+        assert_eq!(rewind.len(), 50);
+        assert!(rewind.memory_size() < 2 * states[0].as_bytes().len());
+
+        for expected in states.iter().rev().skip(1).take(50) {
+            assert_eq!(rewind.pop().as_ref(), Some(expected));
+        }
+        assert!(rewind.pop().is_none());
+    }
+}
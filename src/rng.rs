@@ -0,0 +1,158 @@
+use std::{fmt, str::FromStr};
+
+/*
+ * Rng
+ * the random number generator behind CXNN. Its whole state is one u64, so it can be kept in save
+ * states and a run started from the same seed gives the same numbers. It is an xorshift64*
+ * generator, which is plenty for games, unless the counter kind is picked.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+    kind: RngKind,
+}
+
+/*
+ * RngKind
+ * Xorshift is a good generator. Counter is a poor one on purpose: a counter stepped by every CXNN
+ * is mixed with the count of 60 Hz interrupts and added into a running byte, so the numbers
+ * depend on when the program asks and fall into short cycles. It is not the COSMAC VIP's routine,
+ * which adds in bytes of its own interpreter code.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RngKind {
+    #[default]
+    Xorshift,
+    Counter,
+}
+impl FromStr for RngKind {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<RngKind, String> {
+        match text.to_ascii_lowercase().as_str() {
+            "xorshift" => Ok(RngKind::Xorshift),
+            "counter" => Ok(RngKind::Counter),
+            _ => Err(format!(
+                "unknown random number generator \"{text}\", expected xorshift or counter"
+            )),
+        }
+    }
+}
+impl fmt::Display for RngKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RngKind::Xorshift => write!(f, "xorshift"),
+            RngKind::Counter => write!(f, "counter"),
+        }
+    }
+}
+impl Default for Rng {
+    fn default() -> Self {
+        Self::from_entropy()
+    }
+}
+impl Rng {
+    /** Creates a generator from a seed, the same seed always gives the same numbers. */
+    pub fn new(seed: u64) -> Rng {
+        Rng::with_kind(seed, RngKind::Xorshift)
+    }
+
+    pub fn with_kind(seed: u64, kind: RngKind) -> Rng {
+        // xorshift never leaves a zero state, so mix the seed into a non zero one
+        let mut state = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        state ^= state >> 31;
+        Rng {
+            state: if state == 0 { 1 } else { state },
+            kind,
+        }
+    }
+
+    /** Creates a generator with a random seed. */
+    pub fn from_entropy() -> Rng {
+        Rng::new(rand::random())
+    }
+
+    pub fn kind(&self) -> RngKind {
+        self.kind
+    }
+
+    pub fn set_kind(&mut self, kind: RngKind) {
+        self.kind = kind;
+    }
+
+    /** The whole state of the generator, see set_state. */
+    pub fn get_state(&self) -> u64 {
+        self.state
+    }
+
+    /** Restores a state returned by get_state. */
+    pub fn set_state(&mut self, state: u64) {
+        self.state = if state == 0 { 1 } else { state };
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+
+    /** The random byte for CXNN from the generator's kind, interrupts counts the 60 Hz frames run. */
+    pub fn random_byte(&mut self, interrupts: u64) -> u8 {
+        match self.kind {
+            RngKind::Xorshift => self.next_u8(),
+            RngKind::Counter => {
+                // the low byte of the state is the counter and the next one the running byte
+                let counter = (self.state as u8).wrapping_add(1);
+                let running = (self.state >> 8) as u8;
+                let value = running
+                    .wrapping_add(counter ^ interrupts as u8)
+                    .rotate_right(1);
+                self.state = (self.state & !0xFFFF) | (value as u64) << 8 | counter as u64;
+                value
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod rng_tests {
+    use super::*;
+
+    #[test]
+    fn repeats_from_a_seed_or_state() {
+        let mut first = Rng::new(42);
+        let mut second = Rng::new(42);
+        let numbers: Vec<u8> = (0..16).map(|_| first.next_u8()).collect();
+        assert_eq!(
+            numbers,
+            (0..16).map(|_| second.next_u8()).collect::<Vec<u8>>()
+        );
+        assert!(numbers.iter().any(|number| *number != numbers[0]));
+
+        let mut restored = Rng::new(0);
+        restored.set_state(first.get_state());
+        assert_eq!(restored.next_u64(), first.next_u64());
+    }
+
+    #[test]
+    fn counter_numbers_depend_on_the_interrupts() {
+        // set_state(0) leaves a counter of 1 and a running byte of 0
+        let numbers = |interrupts: fn(u64) -> u64| {
+            let mut rng = Rng::with_kind(0, RngKind::Counter);
+            rng.set_state(0);
+            (0..4)
+                .map(|call| rng.random_byte(interrupts(call)))
+                .collect::<Vec<u8>>()
+        };
+        // the first is (0 + (2 ^ 10)) rotated right, 8 >> 1
+        assert_eq!(numbers(|_| 10), [4, 134, 74, 172]);
+        assert_eq!(numbers(|call| call), [1, 129, 195, 228]);
+    }
+}
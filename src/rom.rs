@@ -0,0 +1,371 @@
+use std::{
+    fs::File,
+    io,
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use crate::{
+    assembler::{assemble, assemble_file},
+    machine::PROGRAM_START,
+};
+
+/* The file formats a rom can be loaded from */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomFormat {
+    // the rom bytes as they are loaded into memory
+    Binary,
+    // hex bytes separated by whitespace or commas, e.g. "00 E0 A2 2A" or one byte per line
+    HexText,
+    // Intel HEX records, e.g. ":10020000..."
+    IntelHex,
+    // a zip archive holding a single rom
+    Zip,
+    // Octo assembly source
+    OctoSource,
+}
+
+// the extensions a rom inside a zip archive may have
+const ROM_EXTENSIONS: [&str; 9] = ["ch8", "c8", "sc8", "xo8", "8o", "bin", "rom", "hex", "txt"];
+
+/** Reads a chip8 rom from disk. */
+pub fn read_rom<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut buffer = Vec::new();
+
+    file.read_to_end(&mut buffer)?;
+
+    Ok(buffer)
+}
+
+fn extension_of(name: &str) -> Option<String> {
+    Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase)
+}
+
+fn is_intel_hex(text: &str) -> bool {
+    let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+    lines.clone().next().is_some() && lines.all(|line| line.starts_with(':'))
+}
+
+// text made of hex digits and 0x or $ prefixes is taken as a hex dump even when a byte is
+// malformed, so parse_hex_text can say which one rather than it being loaded as binary
+fn is_hex_text(text: &str) -> bool {
+    hex_text_tokens(text).all(|(_, token)| {
+        token
+            .chars()
+            .all(|c| c.is_ascii_hexdigit() || matches!(c, 'x' | 'X' | '$'))
+    }) && hex_text_tokens(text).any(|(_, token)| token.chars().any(|c| c.is_ascii_hexdigit()))
+}
+
+// every token of a hex dump with its line number, skipping comments
+fn hex_text_tokens(text: &str) -> impl Iterator<Item = (usize, &str)> + '_ {
+    text.lines().enumerate().flat_map(|(index, line)| {
+        let line = line
+            .split("//")
+            .next()
+            .unwrap_or("")
+            .split([';', '#'])
+            .next()
+            .unwrap_or("");
+        line.split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|token| !token.is_empty())
+            .map(move |token| (index + 1, token))
+    })
+}
+
+fn strip_hex_prefix(token: &str) -> &str {
+    token
+        .strip_prefix("0x")
+        .or_else(|| token.strip_prefix("0X"))
+        .or_else(|| token.strip_prefix('$'))
+        .unwrap_or(token)
+}
+
+/** Works out the format of a rom from its file name and contents. */
+pub fn detect_format(name: &str, bytes: &[u8]) -> RomFormat {
+    if bytes.starts_with(b"PK\x03\x04") {
+        return RomFormat::Zip;
+    }
+
+    let extension = extension_of(name);
+    match extension.as_deref() {
+        Some("8o") => return RomFormat::OctoSource,
+        Some("ch8") | Some("c8") | Some("sc8") | Some("xo8") | Some("bin") | Some("rom") => {
+            return RomFormat::Binary
+        }
+        _ => {}
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(text) if is_intel_hex(text) => RomFormat::IntelHex,
+        Ok(text) if is_hex_text(text) => RomFormat::HexText,
+        _ => RomFormat::Binary,
+    }
+}
+
+/** Parses a hex dump such as "00 E0 A2 2A", "0x00, 0xE0" or one byte per line. */
+pub fn parse_hex_text(text: &str) -> Result<Vec<u8>, String> {
+    let mut rom = Vec::new();
+
+    for (line, token) in hex_text_tokens(text) {
+        let digits = strip_hex_prefix(token);
+        let valid = !digits.is_empty() && digits.chars().all(|c| c.is_ascii_hexdigit());
+
+        // a single digit is a byte with its leading zero left off, longer tokens are whole bytes
+        if !valid || (digits.len() > 1 && digits.len() % 2 == 1) {
+            return Err(format!("line {line}: \"{token}\" is not a hex byte"));
+        }
+        if digits.len() == 1 {
+            rom.push(u8::from_str_radix(digits, 16).unwrap());
+            continue;
+        }
+        for pair in digits.as_bytes().chunks(2) {
+            let pair = std::str::from_utf8(pair).unwrap();
+            rom.push(u8::from_str_radix(pair, 16).unwrap());
+        }
+    }
+
+    Ok(rom)
+}
+
+/*
+ * parse_intel_hex
+ * reads data (00), end of file (01) and extended address (02, 04) records.
+ * Dumps that start at 0x200 or later are treated as memory dumps and moved down to the program start.
+ */
+pub fn parse_intel_hex(text: &str) -> Result<Vec<u8>, String> {
+    let mut chunks: Vec<(usize, usize, Vec<u8>)> = Vec::new();
+    let mut base = 0;
+
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let record = line
+            .strip_prefix(':')
+            .ok_or(format!("line {line_number}: records must start with ':'"))?;
+        if record.len() % 2 == 1 || record.len() < 10 {
+            return Err(format!("line {line_number}: record is too short"));
+        }
+        let bytes = (0..record.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&record[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| format!("line {line_number}: record is not hex"))?;
+
+        let length = bytes[0] as usize;
+        if bytes.len() != length + 5 {
+            return Err(format!(
+                "line {line_number}: record says it holds {length} bytes but holds {}",
+                bytes.len() - 5
+            ));
+        }
+        let checksum = bytes.iter().fold(0_u8, |sum, byte| sum.wrapping_add(*byte));
+        if checksum != 0 {
+            return Err(format!("line {line_number}: checksum does not match"));
+        }
+
+        let address = (bytes[1] as usize) << 8 | bytes[2] as usize;
+        let data = &bytes[4..4 + length];
+        match bytes[3] {
+            0x00 => chunks.push((line_number, base + address, data.to_vec())),
+            0x01 => break,
+            0x02 if length == 2 => base = ((data[0] as usize) << 8 | data[1] as usize) << 4,
+            0x04 if length == 2 => base = ((data[0] as usize) << 8 | data[1] as usize) << 16,
+            0x03 | 0x05 => {}
+            record_type => {
+                return Err(format!(
+                    "line {line_number}: unknown record type {record_type:02X}"
+                ))
+            }
+        }
+    }
+
+    let start = chunks
+        .iter()
+        .map(|(_, address, _)| *address)
+        .min()
+        .unwrap_or(0);
+    let offset = if start >= PROGRAM_START as usize {
+        PROGRAM_START as usize
+    } else {
+        0
+    };
+
+    let mut rom = Vec::new();
+    for (line_number, address, data) in chunks {
+        let address = address - offset;
+        // the extended address records can point anywhere in 4GB, the program space is far smaller
+        if address + data.len() > 0x10000 - PROGRAM_START as usize {
+            return Err(format!("line {line_number}: address out of range"));
+        }
+        if rom.len() < address + data.len() {
+            rom.resize(address + data.len(), 0);
+        }
+        rom[address..address + data.len()].copy_from_slice(&data);
+    }
+
+    Ok(rom)
+}
+
+fn unzip_rom(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes))
+        .map_err(|err| format!("not a valid zip archive: {err}"))?;
+
+    let candidates = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .filter(|name| {
+            extension_of(name).is_some_and(|extension| ROM_EXTENSIONS.contains(&extension.as_str()))
+        })
+        .map(String::from)
+        .collect::<Vec<String>>();
+
+    let name = match candidates.as_slice() {
+        [name] => name.clone(),
+        [] => return Err("the zip archive does not hold a rom".to_string()),
+        names => {
+            return Err(format!(
+                "the zip archive holds more than one rom: {}",
+                names.join(", ")
+            ))
+        }
+    };
+
+    let mut file = archive
+        .by_name(&name)
+        .map_err(|err| format!("{name}: {err}"))?;
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)
+        .map_err(|err| format!("{name}: {err}"))?;
+
+    match detect_format(&name, &contents) {
+        RomFormat::Zip => Err(format!(
+            "{name}: zip archives inside zip archives are not supported"
+        )),
+        format => parse_rom(&contents, format).map_err(|err| format!("{name}: {err}")),
+    }
+}
+
+/** Turns the contents of a rom file into the bytes loaded at 0x200. */
+pub fn parse_rom(bytes: &[u8], format: RomFormat) -> Result<Vec<u8>, String> {
+    let text = || std::str::from_utf8(bytes).map_err(|_| "the file is not text".to_string());
+
+    let rom = match format {
+        RomFormat::Binary => bytes.to_vec(),
+        RomFormat::HexText => parse_hex_text(text()?)?,
+        RomFormat::IntelHex => parse_intel_hex(text()?)?,
+        RomFormat::Zip => unzip_rom(bytes)?,
+        RomFormat::OctoSource => assemble(text()?).map_err(|err| err.to_string())?.bytes,
+    };
+
+    if rom.is_empty() {
+        return Err("the file does not hold a program".to_string());
+    }
+    Ok(rom)
+}
+
+/**
+ * Loads a program from disk. The format is detected from the file: raw binary, hex text,
+ * Intel HEX, a zip archive holding a single rom, or Octo source that is assembled first.
+ */
+pub fn load_rom<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, String> {
+    let path = path.as_ref();
+    let name = path.display().to_string();
+    let bytes = read_rom(path).map_err(|err| format!("{name}: {err}"))?;
+
+    match detect_format(&name, &bytes) {
+        // includes are looked up relative to the source file
+        RomFormat::OctoSource => assemble_file(path)
+            .map(|program| program.bytes)
+            .map_err(|err| format!("{name}: {err}")),
+        format => parse_rom(&bytes, format).map_err(|err| format!("{name}: {err}")),
+    }
+}
+
+#[cfg(test)]
+mod rom_tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn detects_formats() {
+        assert_eq!(detect_format("a.ch8", b"00 E0"), RomFormat::Binary);
+        assert_eq!(detect_format("a.txt", b"00\nE0\n"), RomFormat::HexText);
+        assert_eq!(
+            detect_format("a", b":0402000000E0120008\n:00000001FF\n"),
+            RomFormat::IntelHex
+        );
+        assert_eq!(
+            detect_format("a", &[0x00, 0xE0, 0x12, 0x00]),
+            RomFormat::Binary
+        );
+
+        // a broken hex dump is still a hex dump
+        assert_eq!(detect_format("a", b"00 E0 0x 12"), RomFormat::HexText);
+        assert_eq!(
+            parse_rom(b"00 E0 0x 12", RomFormat::HexText).unwrap_err(),
+            "line 1: \"0x\" is not a hex byte"
+        );
+    }
+
+    #[test]
+    fn parses_hex_text_dumps() {
+        let rom = load_rom("roms/output.txt").unwrap();
+        assert_eq!(rom.len(), 2017);
+        assert_eq!(rom[..3], [0x17, 0x8D, 0x3C]);
+
+        assert_eq!(
+            parse_hex_text("0x00, 0xE0, // clear\n12 00").unwrap(),
+            [0x00, 0xE0, 0x12, 0x00]
+        );
+        assert_eq!(
+            parse_hex_text("00E0 1200\n  A2").unwrap(),
+            [0x00, 0xE0, 0x12, 0x00, 0xA2]
+        );
+
+        // the last line of IBM.txt lost a digit
+        let error = load_rom("roms/IBM.txt").unwrap_err();
+        assert!(
+            error.ends_with("line 105: \"00E\" is not a hex byte"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn parses_intel_hex() {
+        let text = ":0402000000E0120008\n:00000001FF\n";
+        assert_eq!(parse_intel_hex(text).unwrap(), [0x00, 0xE0, 0x12, 0x00]);
+
+        let error = parse_intel_hex(":0402000000E0120009\n").unwrap_err();
+        assert_eq!(error, "line 1: checksum does not match");
+
+        // 04 moves the base to 0xFFFF0000
+        let error = parse_intel_hex(":02000004FFFFFC\n:0402000000E0120008\n").unwrap_err();
+        assert_eq!(error, "line 2: address out of range");
+    }
+
+    #[test]
+    fn unzips_a_single_rom() {
+        let mut archive = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default();
+        archive.start_file("readme.md", options).unwrap();
+        archive.write_all(b"not a rom").unwrap();
+        archive.start_file("game/pong.ch8", options).unwrap();
+        archive.write_all(&[0x00, 0xE0, 0x12, 0x00]).unwrap();
+        let bytes = archive.finish().unwrap().into_inner();
+
+        assert_eq!(detect_format("roms.zip", &bytes), RomFormat::Zip);
+        assert_eq!(
+            parse_rom(&bytes, RomFormat::Zip).unwrap(),
+            [0x00, 0xE0, 0x12, 0x00]
+        );
+    }
+}
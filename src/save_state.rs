@@ -0,0 +1,455 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    machine::{Machine, PROGRAM_START},
+    memory::Memory,
+    platform::Platform,
+    quirks::{MemoryIncrement, Quirks},
+    rng::RngKind,
+};
+
+/*
+ * Save states
+ * a snapshot of everything a running program can see or change, so it can be restored later and
+ * carry on exactly where it was. Settings that are not part of the program, such as the speed,
+ * the stack policy, the audio sink and the RPL file, are left as they are when a state is loaded.
+ *
+ * On disk a state is the following, all numbers little endian:
+ *
+ *   magic            4 bytes   "C8ST"
+ *   version          u16       STATE_VERSION
+ *   platform         u8        0 chip8, 1 SUPER-CHIP, 2 XO-CHIP
+ *   quirks           u8        bit 0 vf-reset, 1 memory, 2 shift, 3 jump, 4 wrap, 5 display-wait,
+ *                              6 memory-x, only one of bits 1 and 6 is set
+ *   flags            u8        bit 0 hires, 1 exited, 2 waiting for the frame
+ *   planes           u8
+ *   pitch            u8
+ *   width, height    u16, u16
+ *   memory           u32 length, then the bytes
+ *   V0 to VF         16 bytes
+ *   I                u16
+ *   delay, sound     u8, u8
+ *   stack pointer    u16       the number of entries in use
+ *   stack            u16 depth, then depth u16 entries
+ *   program counter  u16
+ *   rom length       u32
+ *   framebuffer      u32 length, then the bytes
+ *   keypad           u16 keys held, u16 keys pressed this frame, bit n for key n
+ *   waiting key      u8        the key FX0A is waiting to be released, 0xFF when none
+ *   rng              u64 state, then u8 kind: 0 xorshift, 1 counter
+ *   rpl flags        16 bytes
+ *   audio pattern    16 bytes
+ *   frame count      u64
+ *   cycle count      u64
+ */
+
+// the first bytes of every save state file
+pub const STATE_MAGIC: [u8; 4] = *b"C8ST";
+
+// bumped whenever the layout above changes, older versions are refused
+pub const STATE_VERSION: u16 = 5;
+
+/* A snapshot of a machine, see Machine::save_state */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveState {
+    bytes: Vec<u8>,
+}
+impl SaveState {
+    /** Checks the magic and version of a state read from somewhere else. */
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<SaveState, String> {
+        let mut reader = Reader::new(&bytes);
+        if reader.bytes(4)? != STATE_MAGIC {
+            return Err(String::from("not a chip8 save state"));
+        }
+        let version = reader.u16()?;
+        if version != STATE_VERSION {
+            return Err(format!(
+                "save state version {version} is not supported, expected {STATE_VERSION}"
+            ));
+        }
+        Ok(SaveState { bytes })
+    }
+
+    /** The state in its on-disk format. */
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<SaveState, String> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        SaveState::from_bytes(bytes).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        fs::write(path, &self.bytes).map_err(|err| format!("{}: {err}", path.display()))
+    }
+}
+
+impl Machine {
+    /** Takes a snapshot of the machine that load_state can restore. */
+    pub fn save_state(&self) -> SaveState {
+        let mut bytes = Vec::new();
+        bytes.extend(STATE_MAGIC);
+        bytes.extend(STATE_VERSION.to_le_bytes());
+
+        bytes.push(match self.platform {
+            Platform::Chip8 => 0,
+            Platform::SuperChip => 1,
+            Platform::XoChip => 2,
+        });
+        bytes.push(quirk_bits(&self.quirks));
+        bytes.push(
+            self.hires as u8 | (self.exited as u8) << 1 | (self.waiting_for_frame as u8) << 2,
+        );
+        bytes.push(self.planes);
+        bytes.push(self.pitch);
+        bytes.extend((self.width as u16).to_le_bytes());
+        bytes.extend((self.height as u16).to_le_bytes());
+
+        let ram = self.memory.as_slice();
+        bytes.extend((ram.len() as u32).to_le_bytes());
+        bytes.extend(ram);
+
+        for register in 0..16 {
+            bytes.push(*self.registers.get_register(register).unwrap());
+        }
+        bytes.extend(self.registers.get_i_register().to_le_bytes());
+        bytes.push(*self.registers.get_delay_timer());
+        bytes.push(self.sound_timer.get_timer());
+
+        let stack = self.stack.as_slice();
+        bytes.extend((self.stack.get_sp() as u16).to_le_bytes());
+        bytes.extend((stack.len() as u16).to_le_bytes());
+        for entry in stack {
+            bytes.extend(entry.to_le_bytes());
+        }
+
+        bytes.extend((self.program_counter.get_pc() as u16).to_le_bytes());
+        bytes.extend((self.rom_length as u32).to_le_bytes());
+
+        bytes.extend((self.framebuffer.len() as u32).to_le_bytes());
+        bytes.extend(&self.framebuffer);
+
+        let (pressed, presses, waiting) = self.keypad.get_state();
+        bytes.extend(pressed.to_le_bytes());
+        bytes.extend(presses.to_le_bytes());
+        bytes.push(waiting.unwrap_or(0xFF));
+        bytes.extend(self.rng.get_state().to_le_bytes());
+        bytes.push(match self.rng.kind() {
+            RngKind::Xorshift => 0,
+            RngKind::Counter => 1,
+        });
+        bytes.extend(self.rpl_flags);
+        bytes.extend(self.audio_pattern);
+        bytes.extend(self.frame_count.to_le_bytes());
+        bytes.extend(self.cycle_count.to_le_bytes());
+
+        SaveState { bytes }
+    }
+
+    /** Restores a snapshot taken with save_state. The machine is left unchanged when the state is invalid. */
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), String> {
+        let mut reader = Reader::new(&state.bytes);
+        // the header was checked when the state was made
+        reader.bytes(6)?;
+
+        let platform = match reader.u8()? {
+            0 => Platform::Chip8,
+            1 => Platform::SuperChip,
+            2 => Platform::XoChip,
+            other => return Err(format!("unknown platform {other} in save state")),
+        };
+        let quirks = quirks_from_bits(reader.u8()?);
+        let flags = reader.u8()?;
+        let planes = reader.u8()?;
+        let pitch = reader.u8()?;
+        let width = reader.u16()? as u32;
+        let height = reader.u16()? as u32;
+
+        let ram_length = reader.u32()? as usize;
+        let ram = reader.bytes(ram_length)?;
+        let registers = reader.bytes(16)?;
+        let i = reader.u16()?;
+        let delay = reader.u8()?;
+        let sound = reader.u8()?;
+
+        let sp = reader.u16()? as usize;
+        let stack_depth = reader.u16()? as usize;
+        let stack = (0..stack_depth)
+            .map(|_| reader.u16())
+            .collect::<Result<Vec<u16>, String>>()?;
+
+        let pc = reader.u16()?;
+        let rom_length = reader.u32()? as usize;
+        let framebuffer_length = reader.u32()? as usize;
+        let framebuffer = reader.bytes(framebuffer_length)?;
+        if framebuffer_length != (width * height) as usize {
+            return Err(String::from(
+                "the save state framebuffer does not match its size",
+            ));
+        }
+
+        let pressed = reader.u16()?;
+        let presses = reader.u16()?;
+        let waiting = match reader.u8()? {
+            0xFF => None,
+            key => Some(key),
+        };
+        let rng = reader.u64()?;
+        let rng_kind = match reader.u8()? {
+            0 => RngKind::Xorshift,
+            1 => RngKind::Counter,
+            other => {
+                return Err(format!(
+                    "unknown random number generator {other} in save state"
+                ))
+            }
+        };
+        let rpl_flags = reader.bytes(16)?;
+        let audio_pattern = reader.bytes(16)?;
+        let frame_count = reader.u64()?;
+        let cycle_count = reader.u64()?;
+
+        // the state must fit the platform it was taken on, as a corrupt one could not run
+        if ram_length != platform.memory_size() {
+            return Err(format!(
+                "the save state has {ram_length} bytes of memory, {platform} has {}",
+                platform.memory_size()
+            ));
+        }
+        if rom_length + PROGRAM_START as usize > ram_length {
+            return Err(format!(
+                "the save state rom of {rom_length} bytes does not fit in memory"
+            ));
+        }
+        if (width, height) != (platform.width(), platform.height()) {
+            return Err(format!(
+                "the save state display is {width} x {height}, {platform} has {} x {}",
+                platform.width(),
+                platform.height()
+            ));
+        }
+        if stack_depth != platform.stack_depth() {
+            return Err(format!(
+                "the save state stack has {stack_depth} entries, {platform} has {}",
+                platform.stack_depth()
+            ));
+        }
+        if pc as usize >= ram_length {
+            return Err(format!(
+                "the save state program counter {pc:03X} is outside memory"
+            ));
+        }
+
+        // everything has been read, so the machine can be changed without failing half way
+        self.stack.set_stack(sp, &stack)?;
+        let mut memory = Memory::with_size(ram_length);
+        memory.get_memory().copy_from_slice(ram);
+        self.memory = memory;
+
+        self.platform = platform;
+        self.quirks = quirks;
+        self.hires = flags & 1 > 0;
+        self.exited = flags & 2 > 0;
+        self.waiting_for_frame = flags & 4 > 0;
+        self.planes = planes;
+        self.pitch = pitch;
+        self.width = width;
+        self.height = height;
+
+        for (register, value) in registers.iter().enumerate() {
+            self.registers.set_register(register as u8, *value);
+        }
+        self.registers.set_i_register(i);
+        self.registers.set_delay_timer(delay);
+        self.sound_timer.set_timer(sound);
+
+        self.program_counter.set_counter(pc);
+        self.rom_length = rom_length;
+        self.framebuffer = framebuffer.to_vec();
+        self.keypad.set_state(pressed, presses, waiting);
+        self.rng.set_kind(rng_kind);
+        self.rng.set_state(rng);
+        self.rpl_flags.copy_from_slice(rpl_flags);
+        self.audio_pattern.copy_from_slice(audio_pattern);
+        self.frame_count = frame_count;
+        self.cycle_count = cycle_count;
+        // states are taken between frames, a state taken part way through one starts a new frame
+        self.frame_step = 0;
+
+        self.display_changed = true;
+        Ok(())
+    }
+}
+
+/*
+ * SaveSlots
+ * numbered save state files kept next to a rom, "game.ch8" saves slot 1 to "game.state1"
+ */
+#[derive(Debug, Clone)]
+pub struct SaveSlots {
+    rom_path: PathBuf,
+}
+impl SaveSlots {
+    // slots are numbered from 1 to SLOT_COUNT
+    pub const SLOT_COUNT: u8 = 4;
+
+    pub fn new<P: Into<PathBuf>>(rom_path: P) -> SaveSlots {
+        SaveSlots {
+            rom_path: rom_path.into(),
+        }
+    }
+
+    /** The file a slot is kept in. */
+    pub fn path(&self, slot: u8) -> PathBuf {
+        self.rom_path.with_extension(format!("state{slot}"))
+    }
+
+    pub fn save(&self, machine: &Machine, slot: u8) -> Result<(), String> {
+        machine.save_state().write(self.path(slot))
+    }
+
+    pub fn load(&self, machine: &mut Machine, slot: u8) -> Result<(), String> {
+        machine.load_state(&SaveState::read(self.path(slot))?)
+    }
+}
+
+fn quirk_bits(quirks: &Quirks) -> u8 {
+    [
+        quirks.vf_reset,
+        quirks.memory_increment == MemoryIncrement::ByXPlusOne,
+        quirks.shift_uses_vy,
+        quirks.jump_uses_vx,
+        quirks.wrap_sprites,
+        quirks.display_wait,
+        quirks.memory_increment == MemoryIncrement::ByX,
+    ]
+    .iter()
+    .enumerate()
+    .fold(0, |bits, (bit, on)| bits | (*on as u8) << bit)
+}
+
+fn quirks_from_bits(bits: u8) -> Quirks {
+    let on = |bit: u8| bits >> bit & 1 == 1;
+    Quirks {
+        vf_reset: on(0),
+        memory_increment: match (on(1), on(6)) {
+            (true, _) => MemoryIncrement::ByXPlusOne,
+            (false, true) => MemoryIncrement::ByX,
+            (false, false) => MemoryIncrement::None,
+        },
+        shift_uses_vy: on(2),
+        jump_uses_vx: on(3),
+        wrap_sprites: on(4),
+        display_wait: on(5),
+    }
+}
+
+// reads the numbers of a save state in order
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, position: 0 }
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + length)
+            .ok_or("the save state is truncated")?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod save_state_tests {
+    use super::*;
+
+    #[test]
+    fn restores_the_machine() {
+        // 6105 v1 := 5, C0FF v0 := random, 2208 call, 1206 loop, D015 draw, 00EE return
+        let rom = [
+            0x61, 0x05, 0xC0, 0xFF, 0x22, 0x08, 0x12, 0x06, 0xD0, 0x15, 0x00, 0xEE,
+        ];
+        let mut machine = Machine::new(64, 32, &rom).unwrap();
+        (0..3).for_each(|_| machine.step().unwrap());
+        let state = machine.save_state();
+
+        machine.run_frame().unwrap();
+        let after = (machine.framebuffer().to_vec(), machine.rng().get_state());
+
+        let mut restored = Machine::new(64, 32, &[0x00, 0xE0]).unwrap();
+        let state = SaveState::from_bytes(state.as_bytes().to_vec()).unwrap();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.program_counter().get_pc(), 0x208);
+        assert_eq!(restored.stack().get_stack().last(), Some(&0x206));
+        assert_eq!(*restored.registers().get_register(1).unwrap(), 5);
+
+        restored.run_frame().unwrap();
+        assert_eq!(
+            (restored.framebuffer().to_vec(), restored.rng().get_state()),
+            after
+        );
+    }
+
+    #[test]
+    fn refuses_other_files() {
+        assert!(SaveState::from_bytes(b"not a state".to_vec()).is_err());
+
+        let mut bytes = Machine::new(64, 32, &[0x00, 0xE0])
+            .unwrap()
+            .save_state()
+            .as_bytes()
+            .to_vec();
+        bytes[4] = 99;
+        assert!(SaveState::from_bytes(bytes.clone()).is_err());
+
+        bytes[4] = STATE_VERSION as u8;
+        let mut truncated = bytes.clone();
+        truncated.truncate(bytes.len() - 1);
+        let state = SaveState::from_bytes(truncated).unwrap();
+        assert!(Machine::new(64, 32, &[0x00, 0xE0])
+            .unwrap()
+            .load_state(&state)
+            .is_err());
+
+        // a state with no memory, its length is after the header, settings and display size
+        let mut corrupt = bytes.clone();
+        corrupt[15..19].copy_from_slice(&0u32.to_le_bytes());
+        corrupt.drain(19..19 + 4096);
+        let state = SaveState::from_bytes(corrupt).unwrap();
+        let mut machine = Machine::new(64, 32, &[0x00, 0xE0]).unwrap();
+        assert!(machine.load_state(&state).is_err());
+        assert!(machine.step().is_ok());
+
+        let mut corrupt = bytes;
+        corrupt[11..13].copy_from_slice(&0u16.to_le_bytes());
+        let state = SaveState::from_bytes(corrupt).unwrap();
+        assert!(machine.load_state(&state).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use std::time::{Duration, Instant};
+
+/*
+ * Scheduler
+ * keeps a frontend running the machine at a steady 60 frames a second. It works out how many
+ * frames are due at a given time, so a frontend that falls behind catches up on the frames it
+ * missed rather than running slow. After a long stall, such as a dragged window, only
+ * max_catch_up frames are run and the rest are dropped.
+ */
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    frame_duration: Duration,
+    // when the next frame is due to run
+    next_frame: Instant,
+    max_catch_up: u32,
+}
+impl Scheduler {
+    // the most frames run at once when the frontend has fallen behind
+    pub const MAX_CATCH_UP: u32 = 5;
+
+    /** Creates a scheduler with its first frame due now. */
+    pub fn new(frames_per_second: u32) -> Scheduler {
+        Scheduler::starting_at(frames_per_second, Instant::now())
+    }
+
+    /** Creates a scheduler with its first frame due at the given time. */
+    pub fn starting_at(frames_per_second: u32, start: Instant) -> Scheduler {
+        Scheduler {
+            frame_duration: Duration::from_secs(1) / frames_per_second.max(1),
+            next_frame: start,
+            max_catch_up: Scheduler::MAX_CATCH_UP,
+        }
+    }
+
+    pub fn set_max_catch_up(&mut self, frames: u32) {
+        self.max_catch_up = frames.max(1);
+    }
+
+    /** The time the next frame is due, for a frontend to sleep until. */
+    pub fn next_frame(&self) -> Instant {
+        self.next_frame
+    }
+
+    /** Returns the number of frames to run now, and moves on to the next frame after them. */
+    pub fn frames_due(&mut self, now: Instant) -> u32 {
+        if now < self.next_frame {
+            return 0;
+        }
+
+        let behind = (now - self.next_frame).as_nanos() / self.frame_duration.as_nanos() + 1;
+        let behind = behind.min(u32::MAX as u128) as u32;
+        self.next_frame += self.frame_duration * behind;
+
+        behind.min(self.max_catch_up)
+    }
+}
+
+#[cfg(test)]
+mod scheduler_tests {
+    use super::*;
+
+    #[test]
+    fn catches_up_on_missed_frames() {
+        let start = Instant::now();
+        let frame = Duration::from_secs(1) / 60;
+        let mut scheduler = Scheduler::starting_at(60, start);
+
+        assert_eq!(scheduler.frames_due(start), 1);
+        assert_eq!(scheduler.frames_due(start + frame / 2), 0);
+        assert_eq!(scheduler.next_frame(), start + frame);
+
+        // three frames late
+        assert_eq!(scheduler.frames_due(start + frame * 3), 3);
+        assert_eq!(scheduler.next_frame(), start + frame * 4);
+
+        // a long stall only runs a few frames and drops the rest
+        assert_eq!(
+            scheduler.frames_due(start + frame * 100),
+            Scheduler::MAX_CATCH_UP
+        );
+        assert_eq!(scheduler.next_frame(), start + frame * 101);
+    }
+}
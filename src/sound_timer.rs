@@ -1,3 +1,47 @@
-// // An 8-bit sound timer which functions like the delay timer, but which also gives off a beeping sound as long as it’s not 0
-// pub struct SoundTimer {}
-// impl SoundTimer {}
+// An 8-bit sound timer which functions like the delay timer, but which also gives off a beeping sound as long as it’s not 0
+// It counts down once per frame, 60 times a second
+#[derive(Debug, Clone, Default)]
+pub struct SoundTimer {
+    timer: u8,
+}
+impl SoundTimer {
+    pub fn new() -> SoundTimer {
+        SoundTimer { timer: 0 }
+    }
+
+    pub fn set_timer(&mut self, value: u8) {
+        self.timer = value;
+    }
+
+    pub fn get_timer(&self) -> u8 {
+        self.timer
+    }
+
+    /** Returns true while the timer is running and the buzzer sounds. */
+    pub fn is_active(&self) -> bool {
+        self.timer > 0
+    }
+
+    /** Counts the timer down by one 60th of a second. */
+    pub fn tick(&mut self) {
+        self.timer = self.timer.saturating_sub(1);
+    }
+}
+
+#[cfg(test)]
+mod sound_timer_tests {
+    use super::*;
+
+    #[test]
+    fn counts_down_to_zero() {
+        let mut sound_timer = SoundTimer::new();
+        sound_timer.set_timer(2);
+
+        sound_timer.tick();
+        assert!(sound_timer.is_active());
+        sound_timer.tick();
+        sound_timer.tick();
+        assert_eq!(sound_timer.get_timer(), 0);
+        assert!(!sound_timer.is_active());
+    }
+}
@@ -1,36 +1,187 @@
+use std::{fmt, str::FromStr};
+
+// the deepest stack of any platform, SUPER-CHIP and XO-CHIP have 16 entries
+pub const STACK_LIMIT: usize = 16;
+
+/*
+ * StackPolicy
+ * what happens when 2NNN is run with every stack entry in use, or 00EE with none
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StackPolicy {
+    // the program stops with a stack overflow or underflow
+    #[default]
+    Error,
+    // the stack pointer wraps around, overwriting the oldest entry or returning to the newest
+    Wrap,
+    // the call or return does nothing but move on to the next instruction
+    Ignore,
+}
+impl FromStr for StackPolicy {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<StackPolicy, String> {
+        match text.to_ascii_lowercase().as_str() {
+            "error" => Ok(StackPolicy::Error),
+            "wrap" => Ok(StackPolicy::Wrap),
+            "ignore" => Ok(StackPolicy::Ignore),
+            _ => Err(format!(
+                "unknown stack policy \"{text}\", expected error, wrap or ignore"
+            )),
+        }
+    }
+}
+impl fmt::Display for StackPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StackPolicy::Error => write!(f, "error"),
+            StackPolicy::Wrap => write!(f, "wrap"),
+            StackPolicy::Ignore => write!(f, "ignore"),
+        }
+    }
+}
+
+/*
+ * Stack
+ * the return addresses of the subroutines being run. sp is the number of entries in use, so
+ * entry sp - 1 is the last address pushed. The depth is set by the platform, see
+ * Platform::stack_depth.
+ */
 pub struct Stack {
     sp: usize,
-    stack: [u16; 512],
+    depth: usize,
+    stack: [u16; STACK_LIMIT],
+    policy: StackPolicy,
+}
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl Stack {
     pub fn new() -> Stack {
+        Stack::with_depth(STACK_LIMIT)
+    }
+
+    /** Creates a stack holding the given number of entries, at most STACK_LIMIT. */
+    pub fn with_depth(depth: usize) -> Stack {
         Stack {
-            sp: 1,
-            stack: [0; 512],
+            sp: 0,
+            depth: depth.clamp(1, STACK_LIMIT),
+            stack: [0; STACK_LIMIT],
+            policy: StackPolicy::default(),
         }
     }
 
-    pub fn push(&mut self, value: u16) {
-        self.stack[self.sp + 1] = value;
-        self.sp_increment();
+    pub fn get_depth(&self) -> usize {
+        self.depth
     }
 
-    pub fn pop(&mut self) -> u16 {
-        let return_value = self.stack[self.sp];
-        self.stack[self.sp] = 0;
-        self.sp_decrement();
-        return_value
+    pub fn get_policy(&self) -> StackPolicy {
+        self.policy
     }
 
-    pub fn sp_increment(&mut self) -> usize {
+    pub fn set_policy(&mut self, policy: StackPolicy) {
+        self.policy = policy;
+    }
+
+    /**
+     * Pushes a return address. When every entry is in use it is given back, unless the policy is
+     * Wrap when the stack pointer wraps around and it is written over the oldest entry.
+     */
+    pub fn push(&mut self, value: u16) -> Result<(), u16> {
+        if self.sp == self.depth {
+            if self.policy != StackPolicy::Wrap {
+                return Err(value);
+            }
+            self.sp = 0;
+        }
+        self.stack[self.sp] = value;
         self.sp += 1;
-        self.sp
+        Ok(())
     }
 
-    pub fn sp_decrement(&mut self) -> usize {
-        if self.sp > 0 {
-            self.sp -= 1;
+    /**
+     * Pops the last return address pushed. An empty stack gives None, unless the policy is Wrap
+     * when the stack pointer wraps around to the last entry.
+     */
+    pub fn pop(&mut self) -> Option<u16> {
+        if self.sp == 0 {
+            if self.policy != StackPolicy::Wrap {
+                return None;
+            }
+            self.sp = self.depth;
         }
+        self.sp -= 1;
+        Some(self.stack[self.sp])
+    }
+
+    pub fn get_sp(&self) -> usize {
         self.sp
     }
+
+    /** Moves the stack pointer, which can be anywhere from 0 to the depth. */
+    pub fn set_sp(&mut self, sp: usize) -> Result<(), String> {
+        if sp > self.depth {
+            return Err(format!(
+                "stack pointer {sp} is past the end of the {} entry stack",
+                self.depth
+            ));
+        }
+        self.sp = sp;
+        Ok(())
+    }
+
+    /** The entries in use, the first pushed first. */
+    pub fn get_stack(&self) -> &[u16] {
+        &self.stack[..self.sp]
+    }
+
+    /** Every entry up to the depth, including those not in use. */
+    pub fn as_slice(&self) -> &[u16] {
+        &self.stack[..self.depth]
+    }
+
+    /** Replaces every entry and the stack pointer, the depth becomes the number of entries. */
+    pub fn set_stack(&mut self, sp: usize, entries: &[u16]) -> Result<(), String> {
+        if entries.is_empty() || entries.len() > STACK_LIMIT {
+            return Err(format!(
+                "a stack of {} entries is not supported, the most is {STACK_LIMIT}",
+                entries.len()
+            ));
+        }
+        if sp > entries.len() {
+            return Err(format!("stack pointer {sp} is out of range"));
+        }
+        self.stack = [0; STACK_LIMIT];
+        self.stack[..entries.len()].copy_from_slice(entries);
+        self.depth = entries.len();
+        self.sp = sp;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod stack_tests {
+    use super::*;
+
+    #[test]
+    fn follows_the_overflow_policy() {
+        let mut stack = Stack::with_depth(2);
+        assert_eq!(stack.push(0x202), Ok(()));
+        assert_eq!(stack.push(0x204), Ok(()));
+        assert_eq!(stack.push(0x206), Err(0x206));
+        assert_eq!(stack.get_stack(), [0x202, 0x204]);
+
+        stack.set_policy(StackPolicy::Wrap);
+        assert_eq!(stack.push(0x206), Ok(()));
+        assert_eq!(stack.get_stack(), [0x206]);
+        assert_eq!(stack.pop(), Some(0x206));
+        assert_eq!(stack.pop(), Some(0x204));
+
+        // the wrapped push wrote over 0x202
+        stack.set_policy(StackPolicy::Error);
+        assert_eq!(stack.pop(), Some(0x206));
+        assert_eq!(stack.pop(), None);
+    }
 }
@@ -0,0 +1,314 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+    thread,
+};
+
+use crate::{debugger::OpcodePattern, instruction::Instruction, machine::Machine};
+
+/*
+ * Execution traces
+ * a line for every instruction executed, holding the machine state just before it ran:
+ *
+ *   cycle=42 pc=0204 op=A22A v0=00 v1=05 ... vf=00 i=0000 sp=00 dt=00 st=00 | LD I, 0x22A
+ *
+ * The fields are always in this order and always the same width, everything is in hex apart from
+ * the cycle, and the disassembly follows the bar. Lines starting with # are comments. The format
+ * is stable, so traces of two runs can be compared with diff or with chip8 trace-diff.
+ *
+ * A Tracer writes every instruction as it runs, or keeps the last few in a ring and only writes
+ * them when something goes wrong. The same lines are logged at the trace level to the
+ * "chip8::trace" target.
+ */
+
+// the log target the trace lines go to, enable it with RUST_LOG=chip8::trace=trace
+pub const TRACE_TARGET: &str = "chip8::trace";
+
+/* The state of the machine before an instruction */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub sp: u8,
+    pub delay: u8,
+    pub sound: u8,
+    pub disassembly: String,
+}
+impl TraceEntry {
+    /** Takes the state of the machine before the instruction at the program counter runs. */
+    pub fn capture(machine: &Machine) -> TraceEntry {
+        let pc = machine.program_counter().get_pc();
+        let memory = machine.memory().as_slice();
+        let bytes = memory.get(pc..).unwrap_or(&[]);
+        let opcode = match bytes {
+            [first, second, ..] => (*first as u16) << 8 | *second as u16,
+            _ => 0,
+        };
+        let disassembly = match Instruction::decode_bytes(bytes) {
+            Ok(instruction) => instruction.to_string(),
+            Err(_) => format!("DW 0x{opcode:04X}"),
+        };
+
+        let registers = machine.registers();
+        let mut v = [0; 16];
+        for (x, value) in v.iter_mut().enumerate() {
+            *value = *registers.get_register(x as u8).unwrap();
+        }
+        TraceEntry {
+            cycle: machine.cycle_count(),
+            pc: pc as u16,
+            opcode,
+            v,
+            i: *registers.get_i_register(),
+            sp: machine.stack().get_sp() as u8,
+            delay: *registers.get_delay_timer(),
+            sound: machine.sound_timer().get_timer(),
+            disassembly,
+        }
+    }
+}
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "cycle={} pc={:04X} op={:04X}",
+            self.cycle, self.pc, self.opcode
+        )?;
+        for (x, value) in self.v.iter().enumerate() {
+            write!(f, " v{x:x}={value:02X}")?;
+        }
+        write!(
+            f,
+            " i={:04X} sp={:02X} dt={:02X} st={:02X} | {}",
+            self.i, self.sp, self.delay, self.sound, self.disassembly
+        )
+    }
+}
+
+/*
+ * TraceFilter
+ * which instructions are traced: those in an address range, those that match any of a list of
+ * opcode patterns, or both. An empty filter lets everything through.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    // the first and last address traced
+    pub range: Option<(u16, u16)>,
+    pub opcodes: Vec<OpcodePattern>,
+}
+impl TraceFilter {
+    pub fn matches(&self, pc: u16, opcode: u16) -> bool {
+        let in_range = self
+            .range
+            .is_none_or(|(start, end)| (start..=end).contains(&pc));
+        let matches_opcode =
+            self.opcodes.is_empty() || self.opcodes.iter().any(|pattern| pattern.matches(opcode));
+        in_range && matches_opcode
+    }
+
+    /** Parses an address range like "0x200-0x2FF". */
+    pub fn parse_range(text: &str) -> Result<(u16, u16), String> {
+        let (start, end) = text
+            .split_once('-')
+            .ok_or(format!("\"{text}\" is not a range like 0x200-0x2FF"))?;
+        let start = parse_hex(start)?;
+        let end = parse_hex(end)?;
+        if end < start {
+            return Err(format!("the range {text} ends before it starts"));
+        }
+        Ok((start, end))
+    }
+
+    /** Parses a comma separated list of opcode patterns like "DXYN,2NNN". */
+    pub fn parse_opcodes(text: &str) -> Result<Vec<OpcodePattern>, String> {
+        text.split(',').map(OpcodePattern::from_str).collect()
+    }
+}
+
+pub struct Tracer {
+    output: Box<dyn Write>,
+    filter: TraceFilter,
+    // the last instructions, when only they are written
+    ring: Option<VecDeque<TraceEntry>>,
+    ring_size: usize,
+    // set once writing has failed, so the problem is only reported once
+    failed: bool,
+}
+impl Tracer {
+    /** Creates a tracer that writes every instruction to the output as it runs. */
+    pub fn new(output: Box<dyn Write>) -> Tracer {
+        Tracer {
+            output,
+            filter: TraceFilter::default(),
+            ring: None,
+            ring_size: 0,
+            failed: false,
+        }
+    }
+
+    /** Creates a tracer that writes to a file. */
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Tracer> {
+        let file = File::create(path)?;
+        Ok(Tracer::new(Box::new(BufWriter::new(file))))
+    }
+
+    pub fn set_filter(&mut self, filter: TraceFilter) {
+        self.filter = filter;
+    }
+
+    /**
+     * Keeps only the last instructions in memory, writing them out when dump is called, an
+     * instruction fails or the program panics.
+     */
+    pub fn keep_last(&mut self, instructions: usize) {
+        self.ring = Some(VecDeque::with_capacity(instructions));
+        self.ring_size = instructions.max(1);
+    }
+
+    /** Returns true when the instruction passes the filter. */
+    pub fn wants(&self, pc: u16, opcode: u16) -> bool {
+        self.filter.matches(pc, opcode)
+    }
+
+    pub fn record(&mut self, entry: TraceEntry) {
+        log::trace!(target: TRACE_TARGET, "{entry}");
+        match &mut self.ring {
+            Some(ring) => {
+                if ring.len() == self.ring_size {
+                    ring.pop_front();
+                }
+                ring.push_back(entry);
+            }
+            None => {
+                let line = format!("{entry}\n");
+                self.write(&line);
+            }
+        }
+    }
+
+    /** Writes out the instructions kept in the ring, with a comment saying why. */
+    pub fn dump(&mut self, reason: &str) {
+        let Some(ring) = self.ring.take() else {
+            return;
+        };
+        let mut text = format!("# the last {} instructions before {reason}\n", ring.len());
+        for entry in &ring {
+            text.push_str(&format!("{entry}\n"));
+        }
+        self.write(&text);
+        self.flush();
+        self.ring = Some(VecDeque::with_capacity(self.ring_size));
+    }
+
+    pub fn flush(&mut self) {
+        if let Err(err) = self.output.flush() {
+            self.report(err);
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        if self.failed {
+            return;
+        }
+        if let Err(err) = self.output.write_all(text.as_bytes()) {
+            self.report(err);
+        }
+    }
+
+    fn report(&mut self, err: io::Error) {
+        if !self.failed {
+            log::warn!("could not write the trace: {err}");
+        }
+        self.failed = true;
+    }
+}
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.dump("a panic");
+        }
+        self.flush();
+    }
+}
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    let text = text.trim();
+    let digits = text.strip_prefix("0x").unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("\"{text}\" is not a hex address"))
+}
+
+#[cfg(test)]
+mod trace_tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    // a writer the test can read back after the machine has taken the tracer
+    #[derive(Clone, Default)]
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+    impl Write for SharedOutput {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    impl SharedOutput {
+        fn lines(&self) -> Vec<String> {
+            let text = String::from_utf8(self.0.borrow().clone()).unwrap();
+            text.lines().map(String::from).collect()
+        }
+    }
+
+    // 6005 v0 := 5, A300 i := 0x300, 7001 v0 += 1, 1204 loop
+    const ROM: [u8; 8] = [0x60, 0x05, 0xA3, 0x00, 0x70, 0x01, 0x12, 0x04];
+
+    #[test]
+    fn writes_filtered_lines() {
+        let output = SharedOutput::default();
+        let mut tracer = Tracer::new(Box::new(output.clone()));
+        tracer.set_filter(TraceFilter {
+            range: Some(TraceFilter::parse_range("0x200-0x205").unwrap()),
+            opcodes: TraceFilter::parse_opcodes("6XNN,7XNN").unwrap(),
+        });
+        let mut machine = Machine::new(64, 32, &ROM).unwrap();
+        machine.set_tracer(Some(tracer));
+        (0..6).for_each(|_| machine.step().unwrap());
+
+        let lines = output.lines();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            "cycle=0 pc=0200 op=6005 v0=00 v1=00 v2=00 v3=00 v4=00 v5=00 v6=00 v7=00 v8=00 \
+             v9=00 va=00 vb=00 vc=00 vd=00 ve=00 vf=00 i=0000 sp=00 dt=00 st=00 | LD V0, 0x05"
+        );
+        assert!(lines[2].starts_with("cycle=4 pc=0204 op=7001 v0=06"));
+    }
+
+    #[test]
+    fn keeps_the_last_instructions_until_they_are_dumped() {
+        let output = SharedOutput::default();
+        let mut tracer = Tracer::new(Box::new(output.clone()));
+        tracer.keep_last(2);
+        let mut machine = Machine::new(64, 32, &ROM).unwrap();
+        machine.set_tracer(Some(tracer));
+        (0..10).for_each(|_| machine.step().unwrap());
+        assert!(output.lines().is_empty());
+
+        machine.dump_trace("the test");
+        let lines = output.lines();
+        assert_eq!(lines[0], "# the last 2 instructions before the test");
+        assert!(lines[1].starts_with("cycle=8 "));
+        assert!(lines[2].starts_with("cycle=9 "));
+    }
+}
@@ -0,0 +1,404 @@
+use std::{collections::VecDeque, fmt, io::BufRead, str::FromStr};
+
+/*
+ * Trace diffs
+ * compares two instruction traces line by line and finds the first instruction where the program
+ * counter, opcode, V registers, I or stack pointer differ, with a few lines of context before it.
+ *
+ * Traces written by this interpreter (see trace.rs) are read by the names of their fields. Traces
+ * from other emulators are read with a TraceFormat that names their columns in order, e.g.
+ *
+ *   "pc op v0-vf i sp"       PC, opcode, the 16 V registers, I and SP in 20 columns
+ *   "cycle _ pc op"          a cycle count, a column to skip, the PC and the opcode
+ *
+ * Column values are hex, with or without a 0x or $ prefix, and may be labelled like "PC:0200" or
+ * "pc=0200". Columns are split on whitespace, or on a separator set with ";sep=,". Anything after
+ * the last column is ignored, and so are empty lines and lines starting with #. Fields missing
+ * from either trace are not compared.
+ */
+
+/* A value in a trace line */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Field {
+    Cycle,
+    Pc,
+    Opcode,
+    V(u8),
+    I,
+    Sp,
+    Delay,
+    Sound,
+}
+impl Field {
+    // the fields that have to match
+    fn is_compared(&self) -> bool {
+        !matches!(self, Field::Cycle | Field::Delay | Field::Sound)
+    }
+}
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Field, String> {
+        let lower = text.to_ascii_lowercase();
+        match lower.as_str() {
+            "cycle" => Ok(Field::Cycle),
+            "pc" => Ok(Field::Pc),
+            "op" | "opcode" => Ok(Field::Opcode),
+            "i" => Ok(Field::I),
+            "sp" => Ok(Field::Sp),
+            "dt" => Ok(Field::Delay),
+            "st" => Ok(Field::Sound),
+            _ => match lower.strip_prefix('v') {
+                Some(x) if x.len() == 1 => u8::from_str_radix(x, 16)
+                    .map(Field::V)
+                    .map_err(|_| format!("unknown field \"{text}\"")),
+                _ => Err(format!("unknown field \"{text}\"")),
+            },
+        }
+    }
+}
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Field::Cycle => write!(f, "cycle"),
+            Field::Pc => write!(f, "pc"),
+            Field::Opcode => write!(f, "op"),
+            Field::V(x) => write!(f, "v{x:x}"),
+            Field::I => write!(f, "i"),
+            Field::Sp => write!(f, "sp"),
+            Field::Delay => write!(f, "dt"),
+            Field::Sound => write!(f, "st"),
+        }
+    }
+}
+
+/* How the lines of a trace are laid out */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    // this interpreter's own "name=value" fields
+    #[default]
+    Named,
+    // a field, or None for a column to skip, for each column in order
+    Columns {
+        columns: Vec<Option<Field>>,
+        separator: Option<char>,
+    },
+}
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    /** Parses "named", or column names like "pc op v0-vf i sp;sep=,". */
+    fn from_str(text: &str) -> Result<TraceFormat, String> {
+        if text.trim() == "named" {
+            return Ok(TraceFormat::Named);
+        }
+
+        let (names, separator) = match text.split_once(";sep=") {
+            Some((names, separator)) => {
+                let mut characters = separator.chars();
+                match (characters.next(), characters.next()) {
+                    (Some(separator), None) => (names, Some(separator)),
+                    _ => return Err(format!("\"{separator}\" is not a single character")),
+                }
+            }
+            None => (text, None),
+        };
+
+        let mut columns = Vec::new();
+        for name in names.split_whitespace() {
+            match name {
+                "_" => columns.push(None),
+                "v0-vf" => columns.extend((0..16).map(|x| Some(Field::V(x)))),
+                name => columns.push(Some(name.parse()?)),
+            }
+        }
+        if columns.is_empty() {
+            return Err(String::from("the trace format has no columns"));
+        }
+        Ok(TraceFormat::Columns { columns, separator })
+    }
+}
+impl TraceFormat {
+    /** Reads the fields of a line, None for comments and empty lines. */
+    pub fn parse_line(&self, line: &str) -> Result<Option<Vec<(Field, u64)>>, String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        let mut fields = Vec::new();
+        match self {
+            TraceFormat::Named => {
+                // the disassembly after the bar is left out
+                let values = line.split('|').next().unwrap_or_default();
+                for value in values.split_whitespace() {
+                    let (name, value) = value
+                        .split_once('=')
+                        .ok_or(format!("\"{value}\" is not a name=value field"))?;
+                    let field: Field = name.parse()?;
+                    // the cycle count is the one field in decimal
+                    let value = match field {
+                        Field::Cycle => value.parse().ok(),
+                        _ => u64::from_str_radix(value, 16).ok(),
+                    };
+                    let value = value.ok_or(format!("invalid {field} \"{line}\""))?;
+                    fields.push((field, value));
+                }
+            }
+            TraceFormat::Columns { columns, separator } => {
+                let values: Vec<&str> = match separator {
+                    Some(separator) => line.split(*separator).map(str::trim).collect(),
+                    None => line.split_whitespace().collect(),
+                };
+                if values.len() < columns.len() {
+                    return Err(format!(
+                        "expected {} columns, found {}",
+                        columns.len(),
+                        values.len()
+                    ));
+                }
+                for (field, value) in columns.iter().zip(values) {
+                    if let Some(field) = field {
+                        let value =
+                            parse_value(value).ok_or(format!("invalid {field} \"{value}\""))?;
+                        fields.push((*field, value));
+                    }
+                }
+            }
+        }
+        Ok(Some(fields))
+    }
+}
+
+/* One line of a trace */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceLine {
+    // the line number in its file, from 1
+    pub number: usize,
+    pub text: String,
+    pub fields: Vec<(Field, u64)>,
+}
+impl TraceLine {
+    pub fn get(&self, field: Field) -> Option<u64> {
+        self.fields
+            .iter()
+            .find(|(name, _)| *name == field)
+            .map(|(_, value)| *value)
+    }
+}
+
+/* Where two traces part ways */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    // the number of instructions the traces agree on
+    pub index: usize,
+    // the lines before the divergence, from both traces
+    pub context: Vec<(TraceLine, TraceLine)>,
+    // the diverging lines, None for a trace that has ended
+    pub left: Option<TraceLine>,
+    pub right: Option<TraceLine>,
+    // each field that differs, with its value in both traces
+    pub differences: Vec<(Field, u64, u64)>,
+}
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cycle = self
+            .left
+            .as_ref()
+            .and_then(|line| line.get(Field::Cycle))
+            .map_or(String::new(), |cycle| format!(" (cycle {cycle})"));
+        writeln!(f, "the traces diverge at instruction {}{cycle}", self.index)?;
+        for (field, left, right) in &self.differences {
+            writeln!(f, "  {field}: {left:X} != {right:X}")?;
+        }
+
+        // the context lines agree, but are written differently when the formats differ
+        for (left, right) in &self.context {
+            writeln!(f, "  {:>7}  {}", left.number, left.text)?;
+            if right.text != left.text {
+                writeln!(f, "  {:>7}  {}", right.number, right.text)?;
+            }
+        }
+        for (marker, line) in [("<", &self.left), (">", &self.right)] {
+            match line {
+                Some(line) => writeln!(f, "{marker} {:>7}  {}", line.number, line.text)?,
+                None => writeln!(f, "{marker} the trace ends here")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Compares two traces a line at a time and returns the first divergence, keeping up to context
+ * lines before it. Returns None when the traces agree all the way through.
+ */
+pub fn first_divergence<L: BufRead, R: BufRead>(
+    left: L,
+    left_format: &TraceFormat,
+    right: R,
+    right_format: &TraceFormat,
+    context: usize,
+) -> Result<Option<Divergence>, String> {
+    let mut left = TraceReader::new(left, left_format);
+    let mut right = TraceReader::new(right, right_format);
+    let mut previous = VecDeque::with_capacity(context + 1);
+
+    for index in 0.. {
+        let (left, right) = (left.next_line()?, right.next_line()?);
+        let (left_line, right_line) = match (left, right) {
+            (None, None) => return Ok(None),
+            (Some(left_line), Some(right_line)) => (left_line, right_line),
+            (left, right) => {
+                return Ok(Some(Divergence {
+                    index,
+                    context: previous.into(),
+                    left,
+                    right,
+                    differences: Vec::new(),
+                }))
+            }
+        };
+
+        let differences = differences(&left_line, &right_line);
+        if !differences.is_empty() {
+            return Ok(Some(Divergence {
+                index,
+                context: previous.into(),
+                left: Some(left_line),
+                right: Some(right_line),
+                differences,
+            }));
+        }
+
+        if context > 0 {
+            if previous.len() == context {
+                previous.pop_front();
+            }
+            previous.push_back((left_line, right_line));
+        }
+    }
+    unreachable!()
+}
+
+// the compared fields both lines have that differ
+fn differences(left: &TraceLine, right: &TraceLine) -> Vec<(Field, u64, u64)> {
+    let mut differences = left
+        .fields
+        .iter()
+        .filter(|(field, _)| field.is_compared())
+        .filter_map(|(field, value)| {
+            let other = right.get(*field)?;
+            (other != *value).then_some((*field, *value, other))
+        })
+        .collect::<Vec<_>>();
+    differences.sort();
+    differences
+}
+
+// reads the lines of a trace that hold an instruction
+struct TraceReader<'a, R: BufRead> {
+    input: R,
+    format: &'a TraceFormat,
+    number: usize,
+}
+impl<'a, R: BufRead> TraceReader<'a, R> {
+    fn new(input: R, format: &'a TraceFormat) -> TraceReader<'a, R> {
+        TraceReader {
+            input,
+            format,
+            number: 0,
+        }
+    }
+
+    fn next_line(&mut self) -> Result<Option<TraceLine>, String> {
+        loop {
+            let mut text = String::new();
+            let read = self
+                .input
+                .read_line(&mut text)
+                .map_err(|err| err.to_string())?;
+            if read == 0 {
+                return Ok(None);
+            }
+            self.number += 1;
+
+            let text = text.trim_end().to_string();
+            let fields = self
+                .format
+                .parse_line(&text)
+                .map_err(|err| format!("line {}: {err}", self.number))?;
+            if let Some(fields) = fields {
+                return Ok(Some(TraceLine {
+                    number: self.number,
+                    text,
+                    fields,
+                }));
+            }
+        }
+    }
+}
+
+// a hex value, perhaps labelled "pc:" or "pc=" and perhaps with a 0x or $ prefix
+fn parse_value(text: &str) -> Option<u64> {
+    let text = text.rsplit([':', '=']).next()?.trim();
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_prefix('$'))
+        .unwrap_or(text);
+    u64::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod trace_diff_tests {
+    use super::*;
+    use crate::{machine::Machine, trace::TraceEntry};
+
+    #[test]
+    fn finds_the_first_divergence_from_another_emulator() {
+        // 6005 v0 := 5, 7001 v0 += 1, 8006 v0 >>= v0, 1206 loop
+        let rom = [0x60, 0x05, 0x70, 0x01, 0x80, 0x06, 0x12, 0x06];
+        let mut machine = Machine::new(64, 32, &rom).unwrap();
+        let mut ours = String::new();
+        for _ in 0..5 {
+            ours.push_str(&format!("{}\n", TraceEntry::capture(&machine)));
+            machine.step().unwrap();
+        }
+
+        // the other emulator shifts VY into VX, and writes PC:0200 0x6005 v0 v1 ... vf
+        let theirs = "\
+# a trace from somewhere else
+PC:0200 0x6005 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+PC:0202 0x7001 05 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+PC:0204 0x8006 06 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+PC:0206 0x1206 03 00 00 00 00 00 00 00 00 00 00 00 00 00 00 01
+PC:0206 0x1206 03 00 00 00 00 00 00 00 00 00 00 00 00 00 00 01
+";
+        let format: TraceFormat = "pc op v0-vf".parse().unwrap();
+        let divergence = first_divergence(
+            ours.as_bytes(),
+            &TraceFormat::Named,
+            theirs.as_bytes(),
+            &format,
+            2,
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(divergence.index, 3);
+        assert_eq!(divergence.differences, [(Field::V(0xF), 0, 1)]);
+        assert_eq!(divergence.context.len(), 2);
+        assert_eq!(divergence.right.as_ref().unwrap().number, 5);
+        assert!(divergence.to_string().contains("  vf: 0 != 1"));
+
+        let same = first_divergence(
+            ours.as_bytes(),
+            &TraceFormat::Named,
+            ours.as_bytes(),
+            &TraceFormat::Named,
+            2,
+        );
+        assert_eq!(same, Ok(None));
+    }
+}